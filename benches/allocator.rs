@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use finiky::dhcp::leases::{Lease, LeaseState, LeaseStore, LeaseTable};
+use finiky::dhcp::pool::IpPool;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+
+const POOL_SIZE: u32 = 65536;
+
+fn mac(n: u32) -> [u8; 6] {
+    let [a, b, c, d] = n.to_be_bytes();
+    [0x02, 0x00, a, b, c, d]
+}
+
+/// A /16 pool, kept in memory
+fn pool() -> IpPool {
+    let leases = Arc::new(LeaseTable::load(LeaseStore::new(None)).unwrap());
+    IpPool::new(
+        Ipv4Addr::new(10, 0, 0, 0),
+        Ipv4Addr::new(10, 0, 255, 255),
+        leases,
+    )
+    .unwrap()
+}
+
+/// The /16 pool full of bound leases, saved in a lease file
+fn saved_pool(dir: &Path) -> IpPool {
+    let path = dir.join("leases.json");
+    let leases: Vec<Lease> = (0..POOL_SIZE)
+        .map(|n| {
+            let ip = Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + n);
+            Lease::new(mac(n), ip, LeaseState::Bound, 3600)
+        })
+        .collect();
+    LeaseStore::new(Some(path.clone())).save(&leases).unwrap();
+    let leases = Arc::new(LeaseTable::load(LeaseStore::new(Some(path))).unwrap());
+    IpPool::new(
+        Ipv4Addr::new(10, 0, 0, 0),
+        Ipv4Addr::new(10, 0, 255, 255),
+        leases,
+    )
+    .unwrap()
+}
+
+fn fill(c: &mut Criterion) {
+    c.bench_function("fill 65536 addresses", |b| {
+        b.iter_batched(
+            pool,
+            |pool| {
+                for n in 0..POOL_SIZE {
+                    pool.allocate(mac(n)).unwrap();
+                }
+                pool
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+fn churn(c: &mut Criterion) {
+    // All but a handful of addresses taken, so most of the pool is scanned
+    // past to find a free one
+    let pool = pool();
+    for n in 0..POOL_SIZE - 16 {
+        pool.allocate(mac(n)).unwrap();
+    }
+    let mut n = 0;
+    c.bench_function("release and allocate in a nearly full pool", |b| {
+        b.iter(|| {
+            let client = mac(n % (POOL_SIZE - 16));
+            assert!(pool.release(client, Ipv4Addr::UNSPECIFIED));
+            pool.allocate(client).unwrap();
+            n += 7919;
+        })
+    });
+}
+
+fn commit(c: &mut Criterion) {
+    // Every renewal goes to disk, whatever the size of the lease table
+    let dir = tempfile::TempDir::new().unwrap();
+    let pool = saved_pool(dir.path());
+    let mut n = 0;
+    c.bench_function("renew a lease in a full pool with a lease file", |b| {
+        b.iter(|| {
+            pool.update(mac(n % POOL_SIZE), |lease| {
+                lease.renew(LeaseState::Bound, 3600)
+            });
+            n += 7919;
+        })
+    });
+}
+
+criterion_group!(benches, fill, churn, commit);
+criterion_main!(benches);
@@ -1,10 +1,14 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub dhcp: DhcpConfig,
+    /// DHCPv6 server, only started when configured
+    #[serde(default)]
+    pub dhcpv6: Option<Dhcpv6Config>,
     pub tftp: TftpConfig,
     pub http: HttpConfig,
 }
@@ -12,14 +16,341 @@ pub struct Config {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DhcpConfig {
     pub port: u16,
+    /// Port replies are sent to on clients, 68 unless testing
+    #[serde(default = "default_client_port")]
+    pub client_port: u16,
     pub interface: Option<String>,
     pub protocols: ProtocolConfig,
     pub ip_pool_start: String,
     pub ip_pool_end: String,
+    /// Addresses of the pool never handed out dynamically, "a.b.c.d" or
+    /// "a.b.c.d-a.b.c.d"
+    #[serde(default)]
+    pub exclude: Vec<String>,
     pub subnet_mask: String,
     pub gateway: Option<String>,
     pub dns_servers: Vec<String>,
+    /// Domain name sent in option 15
+    #[serde(default)]
+    pub domain_name: Option<String>,
+    /// Hostname of clients without one in their reservation, e.g.
+    /// "node-{ip_last_octet}" or "build-{mac}"
+    #[serde(default)]
+    pub hostname_pattern: Option<String>,
+    /// Publish client names in DNS
+    #[serde(default)]
+    pub ddns: Option<DdnsConfig>,
     pub next_server: String,
+    /// Path of the lease database; leases are kept in memory only when unset
+    #[serde(default)]
+    pub lease_file: Option<String>,
+    /// Lease time granted when the client does not ask for one, in seconds
+    #[serde(default = "default_lease_time")]
+    pub default_lease_time: u32,
+    /// Upper bound for lease times requested by clients, in seconds
+    #[serde(default = "default_max_lease_time")]
+    pub max_lease_time: u32,
+    /// Static host reservations
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+    /// Only supply boot information alongside an existing DHCP server
+    #[serde(default)]
+    pub proxy_dhcp: bool,
+    /// Which clients get answered
+    #[serde(default)]
+    pub access: AccessConfig,
+    /// Answer plain BOOTP clients, which get a permanent address
+    #[serde(default)]
+    pub bootp: bool,
+    /// Ping fresh addresses before offering them, skipping those in use
+    #[serde(default)]
+    pub conflict_detection: bool,
+    /// How long to wait for an answer to the ping, in milliseconds
+    #[serde(default = "default_conflict_probe_timeout")]
+    pub conflict_probe_timeout_ms: u64,
+    /// Additional subnets served through DHCP relay agents
+    #[serde(default)]
+    pub scopes: Vec<ScopeConfig>,
+    /// Firmware boot menu sent to PXE clients in option 43
+    #[serde(default)]
+    pub pxe_menu: Option<PxeMenuConfig>,
+    /// Additional options sent in every reply
+    #[serde(default)]
+    pub options: Vec<DhcpOptionConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dhcpv6Config {
+    #[serde(default = "default_dhcpv6_port")]
+    pub port: u16,
+    /// Interface to listen on, joining the DHCPv6 servers multicast group
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// On-link prefix addresses are assigned from, e.g. "2001:db8:1::/64"
+    pub prefix: String,
+    /// First and last address handed out, by default the whole prefix but
+    /// its first address
+    #[serde(default)]
+    pub pool_start: Option<String>,
+    #[serde(default)]
+    pub pool_end: Option<String>,
+    /// Server the boot file URLs (option 59) point to
+    pub boot_server: String,
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    #[serde(default = "default_preferred_lifetime")]
+    pub preferred_lifetime: u32,
+    #[serde(default = "default_valid_lifetime")]
+    pub valid_lifetime: u32,
+    /// Path of the binding database; bindings are kept in memory only when
+    /// unset
+    #[serde(default)]
+    pub lease_file: Option<String>,
+    /// Server DUID as hex bytes, by default derived from the interface's
+    /// hardware address
+    #[serde(default)]
+    pub server_duid: Option<String>,
+    /// Boot policy, inherited from `[dhcp.protocols]` when unset
+    #[serde(default)]
+    pub protocols: Option<ProtocolConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScopeConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Serve clients directly attached to this interface from this scope
+    #[serde(default)]
+    pub interface: Option<String>,
+    pub ip_pool_start: String,
+    pub ip_pool_end: String,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub subnet_mask: String,
+    #[serde(default)]
+    pub gateway: Option<String>,
+    /// DNS servers for this subnet, inherited from `[dhcp]` when unset
+    #[serde(default)]
+    pub dns_servers: Option<Vec<String>>,
+    #[serde(default)]
+    pub domain_name: Option<String>,
+    #[serde(default)]
+    pub hostname_pattern: Option<String>,
+    /// Only use this scope when the relay agent's circuit-id (option 82.1) matches
+    #[serde(default)]
+    pub relay_circuit_id: Option<String>,
+    /// Only use this scope when the relay agent's remote-id (option 82.2) matches
+    #[serde(default)]
+    pub relay_remote_id: Option<String>,
+    /// Boot server for this subnet, inherited from `[dhcp]` when unset
+    #[serde(default)]
+    pub next_server: Option<String>,
+    /// Boot policy for this subnet, inherited from `[dhcp.protocols]` when unset
+    #[serde(default)]
+    pub protocols: Option<ProtocolConfig>,
+    #[serde(default)]
+    pub default_lease_time: Option<u32>,
+    #[serde(default)]
+    pub max_lease_time: Option<u32>,
+    /// Options for this subnet, replacing `[dhcp]` options with the same code
+    #[serde(default)]
+    pub options: Vec<DhcpOptionConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostConfig {
+    /// Hardware address, e.g. "00:11:22:33:44:55"
+    #[serde(default)]
+    pub mac: Option<String>,
+    /// Client identifier (option 61) as hex bytes, e.g. "01:00:11:22:33:44:55"
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Machine UUID (option 97), as sent by the client
+    #[serde(default)]
+    pub uuid: Option<String>,
+    pub ip: String,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Boot filename for this host, overrides the protocol defaults
+    #[serde(default)]
+    pub boot_filename: Option<String>,
+    /// TFTP server for this host, overrides `next_server`
+    #[serde(default)]
+    pub next_server: Option<String>,
+    /// Options for this host, replacing scope options with the same code
+    #[serde(default)]
+    pub options: Vec<DhcpOptionConfig>,
+}
+
+/// Which clients get answered, from `[dhcp.access]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessConfig {
+    /// Ignore clients without a host reservation
+    #[serde(default)]
+    pub known_hosts_only: bool,
+    /// When not empty, only clients matching one of these are answered
+    #[serde(default)]
+    pub allow: ClientMatchConfig,
+    /// Clients never answered, even if allowed
+    #[serde(default)]
+    pub deny: ClientMatchConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientMatchConfig {
+    /// Hardware addresses, e.g. "00:11:22:33:44:55"
+    #[serde(default)]
+    pub mac: Vec<String>,
+    /// Hardware address vendor prefixes, e.g. "52:54:00"
+    #[serde(default)]
+    pub oui: Vec<String>,
+    /// Vendor class identifier (option 60) prefixes, e.g. "PXEClient"
+    #[serde(default)]
+    pub vendor_class: Vec<String>,
+    /// Machine UUIDs (option 97, or a DHCPv6 DUID-UUID)
+    #[serde(default)]
+    pub uuid: Vec<String>,
+    /// Client architectures (option 93, DHCPv6 option 61), e.g. 7 for x64 UEFI
+    #[serde(default)]
+    pub arch: Vec<u16>,
+}
+
+/// Dynamic DNS updates (RFC 2136), from `[dhcp.ddns]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DdnsConfig {
+    /// Authoritative server of the zones, "192.168.1.53" or "192.168.1.53:5353"
+    pub server: String,
+    /// Zone of the A records, `domain_name` when unset
+    #[serde(default)]
+    pub forward_zone: Option<String>,
+    /// Zone of the PTR records, e.g. "1.168.192.in-addr.arpa"; no PTR
+    /// records are published when unset
+    #[serde(default)]
+    pub reverse_zone: Option<String>,
+    #[serde(default = "default_ddns_ttl")]
+    pub ttl: u32,
+    /// Key signing the updates
+    #[serde(default)]
+    pub tsig: Option<TsigConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TsigConfig {
+    pub name: String,
+    /// "hmac-sha256" or "hmac-sha512"
+    #[serde(default = "default_tsig_algorithm")]
+    pub algorithm: String,
+    /// Base64 secret, as in a BIND key file
+    pub secret: String,
+}
+
+/// A custom DHCP option from `[[dhcp.options]]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DhcpOptionConfig {
+    pub code: u8,
+    #[serde(rename = "type")]
+    pub kind: DhcpOptionType,
+    pub value: DhcpOptionValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DhcpOptionType {
+    Ip,
+    IpList,
+    String,
+    U8,
+    U16,
+    U32,
+    /// Raw bytes, e.g. "01:02:0a"
+    Hex,
+    /// Classless static routes (RFC 3442), e.g. "10.0.0.0/8 via 192.168.1.1"
+    Routes,
+    /// Domain search list (RFC 3397)
+    DomainList,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DhcpOptionValue {
+    Integer(u64),
+    String(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PxeMenuConfig {
+    /// Text shown while waiting for F8
+    #[serde(default = "default_menu_prompt")]
+    pub prompt: String,
+    /// Seconds to wait before booting the first item; 0 boots it straight
+    /// away, 255 waits for the user
+    #[serde(default = "default_menu_timeout")]
+    pub timeout: u8,
+    /// Raw PXE_DISCOVERY_CONTROL bits, by default only the menu's boot
+    /// servers are contacted, by unicast
+    #[serde(default)]
+    pub discovery_control: Option<u8>,
+    pub items: Vec<PxeMenuItemConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PxeMenuItemConfig {
+    pub label: String,
+    /// File to boot for this item; items without one boot from local disk
+    #[serde(default)]
+    pub boot_filename: Option<String>,
+    /// Boot server for this item, defaults to `next_server`
+    #[serde(default)]
+    pub boot_server: Option<String>,
+}
+
+fn default_menu_prompt() -> String {
+    "Press F8 for boot menu".to_string()
+}
+
+fn default_menu_timeout() -> u8 {
+    10
+}
+
+fn default_client_port() -> u16 {
+    68
+}
+
+fn default_lease_time() -> u32 {
+    3600
+}
+
+fn default_max_lease_time() -> u32 {
+    86400
+}
+
+fn default_conflict_probe_timeout() -> u64 {
+    500
+}
+
+fn default_ddns_ttl() -> u32 {
+    300
+}
+
+fn default_tsig_algorithm() -> String {
+    "hmac-sha256".to_string()
+}
+
+fn default_listen() -> Vec<String> {
+    vec!["0.0.0.0".to_string()]
+}
+
+fn default_dhcpv6_port() -> u16 {
+    547
+}
+
+fn default_preferred_lifetime() -> u32 {
+    3600
+}
+
+fn default_valid_lifetime() -> u32 {
+    7200
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,18 +364,32 @@ pub struct ProtocolConfig {
     pub boot_filename_legacy: Option<String>,
     #[serde(default)]
     pub boot_filename_dhcp_boot: Option<String>,
+    /// Boot filenames by client architecture name (e.g. `arm64_uefi`) or
+    /// option 93 code, taking precedence over the per-protocol filenames
+    #[serde(default)]
+    pub boot_filenames: BTreeMap<String, String>,
+    /// Script handed to clients already running iPXE, as a path on the HTTP
+    /// server or a full URL
+    #[serde(default)]
+    pub ipxe_script: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TftpConfig {
     pub port: u16,
     pub root: String,
+    /// Addresses to listen on, e.g. "0.0.0.0", "::" or "fe80::1%eth0"
+    #[serde(default = "default_listen")]
+    pub listen: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpConfig {
     pub port: u16,
     pub root: String,
+    /// Addresses to listen on, e.g. "0.0.0.0", "::" or "fe80::1%eth0"
+    #[serde(default = "default_listen")]
+    pub listen: Vec<String>,
 }
 
 impl Default for Config {
@@ -52,6 +397,7 @@ impl Default for Config {
         Config {
             dhcp: DhcpConfig {
                 port: 67,
+                client_port: default_client_port(),
                 interface: None,
                 protocols: ProtocolConfig {
                     efi: true,
@@ -60,21 +406,42 @@ impl Default for Config {
                     boot_filename_efi: None,
                     boot_filename_legacy: None,
                     boot_filename_dhcp_boot: None,
+                    boot_filenames: BTreeMap::new(),
+                    ipxe_script: None,
                 },
                 ip_pool_start: "192.168.1.100".to_string(),
                 ip_pool_end: "192.168.1.200".to_string(),
+                exclude: Vec::new(),
                 subnet_mask: "255.255.255.0".to_string(),
                 gateway: Some("192.168.1.1".to_string()),
                 dns_servers: vec!["8.8.8.8".to_string()],
+                domain_name: None,
+                hostname_pattern: None,
+                ddns: None,
                 next_server: "192.168.1.1".to_string(),
+                lease_file: None,
+                default_lease_time: default_lease_time(),
+                max_lease_time: default_max_lease_time(),
+                hosts: Vec::new(),
+                proxy_dhcp: false,
+                access: AccessConfig::default(),
+                bootp: false,
+                conflict_detection: false,
+                conflict_probe_timeout_ms: default_conflict_probe_timeout(),
+                scopes: Vec::new(),
+                pxe_menu: None,
+                options: Vec::new(),
             },
+            dhcpv6: None,
             tftp: TftpConfig {
                 port: 69,
                 root: "./tftp".to_string(),
+                listen: default_listen(),
             },
             http: HttpConfig {
                 port: 8080,
                 root: "./http".to_string(),
+                listen: default_listen(),
             },
         }
     }
@@ -182,5 +549,112 @@ root = "./http"
         assert_eq!(config.dhcp.protocols.boot_filename_efi, None);
         assert_eq!(config.dhcp.protocols.boot_filename_legacy, None);
         assert_eq!(config.dhcp.protocols.boot_filename_dhcp_boot, None);
+        assert_eq!(config.dhcp.default_lease_time, 3600);
+        assert_eq!(config.dhcp.max_lease_time, 86400);
+        assert!(config.dhcp.hosts.is_empty());
+    }
+
+    #[test]
+    fn test_config_with_custom_options() {
+        let config_str = r#"
+[dhcp]
+port = 67
+ip_pool_start = "192.168.1.100"
+ip_pool_end = "192.168.1.200"
+subnet_mask = "255.255.255.0"
+dns_servers = []
+next_server = "192.168.1.1"
+
+[[dhcp.options]]
+code = 42
+type = "ip-list"
+value = ["192.168.1.1", "192.168.1.2"]
+
+[[dhcp.options]]
+code = 26
+type = "u16"
+value = 9000
+
+[[dhcp.options]]
+code = 121
+type = "routes"
+value = "10.0.0.0/8 via 192.168.1.1"
+
+[dhcp.protocols]
+efi = true
+legacy = true
+dhcp_boot = true
+
+[tftp]
+port = 69
+root = "./tftp"
+
+[http]
+port = 8080
+root = "./http"
+"#;
+        let config: Config = toml::from_str(config_str).unwrap();
+        let options = &config.dhcp.options;
+        assert_eq!(options.len(), 3);
+        assert_eq!(options[0].kind, DhcpOptionType::IpList);
+        assert_eq!(options[1].value, DhcpOptionValue::Integer(9000));
+        assert_eq!(options[2].kind, DhcpOptionType::Routes);
+
+        let toml_str = toml::to_string(&config).unwrap();
+        let parsed: Config = toml::from_str(&toml_str).unwrap();
+        assert_eq!(parsed.dhcp.options.len(), 3);
+    }
+
+    #[test]
+    fn test_config_with_hosts() {
+        let config_str = r#"
+[dhcp]
+port = 67
+ip_pool_start = "192.168.1.100"
+ip_pool_end = "192.168.1.200"
+subnet_mask = "255.255.255.0"
+dns_servers = []
+next_server = "192.168.1.1"
+
+[dhcp.protocols]
+efi = true
+legacy = true
+dhcp_boot = true
+
+[[dhcp.hosts]]
+mac = "00:11:22:33:44:55"
+ip = "192.168.1.10"
+hostname = "rack1-node1"
+boot_filename = "rescue.efi"
+next_server = "192.168.1.2"
+
+[[dhcp.hosts]]
+uuid = "00112233-4455-6677-8899-aabbccddeeff"
+ip = "192.168.1.11"
+
+[tftp]
+port = 69
+root = "./tftp"
+
+[http]
+port = 8080
+root = "./http"
+"#;
+        let config: Config = toml::from_str(config_str).unwrap();
+        assert_eq!(config.dhcp.hosts.len(), 2);
+        assert_eq!(
+            config.dhcp.hosts[0].mac,
+            Some("00:11:22:33:44:55".to_string())
+        );
+        assert_eq!(
+            config.dhcp.hosts[0].boot_filename,
+            Some("rescue.efi".to_string())
+        );
+        assert_eq!(config.dhcp.hosts[1].mac, None);
+        assert_eq!(config.dhcp.hosts[1].ip, "192.168.1.11");
+
+        let toml_str = toml::to_string(&config).unwrap();
+        let parsed: Config = toml::from_str(&toml_str).unwrap();
+        assert_eq!(parsed.dhcp.hosts.len(), 2);
     }
 }
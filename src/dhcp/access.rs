@@ -0,0 +1,260 @@
+//! Which clients get answered, from `[dhcp.access]`
+
+use crate::config::{AccessConfig, ClientMatchConfig};
+use crate::dhcp::leases::parse_mac;
+use crate::dhcp::options::parse_hex;
+use crate::dhcp::server::DhcpMessage;
+use crate::dhcpv6::message::{duid_mac, duid_uuid, Dhcpv6Message};
+
+/// What the access lists look at in a DHCP or DHCPv6 request
+#[derive(Debug, Default)]
+pub struct Client<'a> {
+    pub mac: Option<[u8; 6]>,
+    pub vendor_classes: Vec<&'a [u8]>,
+    pub uuid: Option<&'a [u8]>,
+    pub arch: Option<u16>,
+}
+
+impl<'a> Client<'a> {
+    pub fn from_dhcp(request: &'a DhcpMessage) -> Self {
+        Client {
+            mac: Some(request.client_mac()),
+            vendor_classes: request.get_vendor_class().into_iter().collect(),
+            // Option 97 is a type byte (0) followed by the 16-byte UUID
+            uuid: request
+                .get_option(97)
+                .filter(|uuid| uuid.len() == 17)
+                .map(|uuid| &uuid[1..]),
+            arch: request.get_client_arch(),
+        }
+    }
+
+    /// DHCPv6 clients have the hardware address or UUID of their DUID
+    pub fn from_dhcpv6(request: &'a Dhcpv6Message) -> Self {
+        let duid = request.client_id();
+        Client {
+            mac: duid.and_then(duid_mac),
+            vendor_classes: request.vendor_classes(),
+            uuid: duid.and_then(duid_uuid),
+            arch: request.client_arch(),
+        }
+    }
+}
+
+/// Clients picked by hardware address, OUI, vendor class, UUID or
+/// architecture
+#[derive(Debug, Clone, Default)]
+struct ClientMatch {
+    macs: Vec<[u8; 6]>,
+    ouis: Vec<[u8; 3]>,
+    vendor_classes: Vec<String>,
+    uuids: Vec<Vec<u8>>,
+    archs: Vec<u16>,
+}
+
+impl ClientMatch {
+    fn from_config(config: &ClientMatchConfig) -> Result<Self, String> {
+        let macs = config
+            .mac
+            .iter()
+            .map(|mac| parse_mac(mac).ok_or_else(|| format!("Invalid MAC address: {}", mac)))
+            .collect::<Result<_, _>>()?;
+        let ouis = config
+            .oui
+            .iter()
+            .map(|oui| match parse_hex(oui) {
+                Some(bytes) if bytes.len() == 3 => Ok([bytes[0], bytes[1], bytes[2]]),
+                _ => Err(format!("Invalid OUI: {}", oui)),
+            })
+            .collect::<Result<_, _>>()?;
+        let uuids = config
+            .uuid
+            .iter()
+            .map(|uuid| match parse_hex(uuid) {
+                Some(bytes) if bytes.len() == 16 => Ok(bytes),
+                _ => Err(format!("Invalid UUID: {}", uuid)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ClientMatch {
+            macs,
+            ouis,
+            vendor_classes: config.vendor_class.clone(),
+            uuids,
+            archs: config.arch.clone(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.macs.is_empty()
+            && self.ouis.is_empty()
+            && self.vendor_classes.is_empty()
+            && self.uuids.is_empty()
+            && self.archs.is_empty()
+    }
+
+    fn matches(&self, client: &Client) -> bool {
+        if let Some(mac) = client.mac {
+            if self.macs.contains(&mac) || self.ouis.iter().any(|oui| mac.starts_with(oui)) {
+                return true;
+            }
+        }
+
+        // Vendor classes match by prefix, "PXEClient" covers all PXE ROMs
+        if client.vendor_classes.iter().any(|class| {
+            self.vendor_classes
+                .iter()
+                .any(|prefix| class.starts_with(prefix.as_bytes()))
+        }) {
+            return true;
+        }
+
+        client.arch.is_some_and(|arch| self.archs.contains(&arch))
+            || client
+                .uuid
+                .is_some_and(|uuid| self.uuids.iter().any(|u| u[..] == *uuid))
+    }
+}
+
+/// Allow and deny lists, checked before any request is handled
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    known_hosts_only: bool,
+    allow: ClientMatch,
+    deny: ClientMatch,
+}
+
+impl AccessControl {
+    pub fn from_config(config: &AccessConfig) -> Result<Self, String> {
+        Ok(AccessControl {
+            known_hosts_only: config.known_hosts_only,
+            allow: ClientMatch::from_config(&config.allow)
+                .map_err(|e| format!("Allow list: {}", e))?,
+            deny: ClientMatch::from_config(&config.deny)
+                .map_err(|e| format!("Deny list: {}", e))?,
+        })
+    }
+
+    /// Check whether a client may be answered; `known` tells whether it has a
+    /// host reservation. Denied clients get the reason.
+    pub fn check(&self, client: &Client, known: bool) -> Result<(), &'static str> {
+        if self.deny.matches(client) {
+            return Err("in the deny list");
+        }
+        if !self.allow.is_empty() && !self.allow.matches(client) {
+            return Err("not in the allow list");
+        }
+        if self.known_hosts_only && !known {
+            return Err("not a known host");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::server::tests::client_request as request;
+
+    fn check(
+        access: &AccessControl,
+        request: &DhcpMessage,
+        known: bool,
+    ) -> Result<(), &'static str> {
+        access.check(&Client::from_dhcp(request), known)
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let mut config = AccessConfig::default();
+        config.allow.oui = list(&["52:54:00"]);
+        config.allow.vendor_class = list(&["PXEClient"]);
+        config.deny.mac = list(&["52:54:00:00:00:02"]);
+        let access = AccessControl::from_config(&config).unwrap();
+
+        let vm = [0x52, 0x54, 0x00, 0, 0, 1];
+        let laptop = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let mut pxe = vec![60, 9];
+        pxe.extend_from_slice(b"PXEClient");
+
+        assert_eq!(check(&access, &request(vm, &[]), false), Ok(()));
+        assert_eq!(check(&access, &request(laptop, &pxe), false), Ok(()));
+        assert_eq!(
+            check(&access, &request(laptop, &[]), false),
+            Err("not in the allow list")
+        );
+        assert_eq!(
+            check(&access, &request([0x52, 0x54, 0x00, 0, 0, 2], &pxe), false),
+            Err("in the deny list")
+        );
+    }
+
+    #[test]
+    fn test_uuid_and_known_hosts() {
+        let mut config = AccessConfig::default();
+        config.deny.uuid = list(&["00112233-4455-6677-8899-aabbccddeeff"]);
+        config.known_hosts_only = true;
+        let access = AccessControl::from_config(&config).unwrap();
+
+        let mac = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let mut uuid = vec![97, 17, 0];
+        uuid.extend((0..16).map(|i| i * 0x11));
+        assert_eq!(check(&access, &request(mac, &[]), true), Ok(()));
+        assert_eq!(
+            check(&access, &request(mac, &[]), false),
+            Err("not a known host")
+        );
+        assert_eq!(
+            check(&access, &request(mac, &uuid), true),
+            Err("in the deny list")
+        );
+    }
+
+    #[test]
+    fn test_arch_and_dhcpv6_clients() {
+        use crate::dhcpv6::message::{OPTION_CLIENTID, OPTION_CLIENT_ARCH_TYPE, SOLICIT};
+
+        let mut config = AccessConfig::default();
+        config.allow.arch = vec![7];
+        config.deny.oui = list(&["00:11:22"]);
+        let access = AccessControl::from_config(&config).unwrap();
+
+        let mac = [0x52, 0x54, 0x00, 0, 0, 1];
+        assert_eq!(check(&access, &request(mac, &[93, 2, 0, 7]), false), Ok(()));
+        assert!(check(&access, &request(mac, &[93, 2, 0, 0]), false).is_err());
+
+        // DHCPv6 clients are matched on the hardware address of their DUID
+        let solicit = |mac: [u8; 6]| {
+            let mut message = Dhcpv6Message {
+                msg_type: SOLICIT,
+                transaction_id: [0; 3],
+                options: Vec::new(),
+            };
+            message.add_option(OPTION_CLIENTID, [&[0, 3, 0, 1][..], &mac].concat());
+            message.add_option(OPTION_CLIENT_ARCH_TYPE, vec![0, 7]);
+            message
+        };
+        let uefi = solicit(mac);
+        assert_eq!(access.check(&Client::from_dhcpv6(&uefi), false), Ok(()));
+        let denied = solicit([0x00, 0x11, 0x22, 0, 0, 1]);
+        assert_eq!(
+            access.check(&Client::from_dhcpv6(&denied), false),
+            Err("in the deny list")
+        );
+    }
+
+    #[test]
+    fn test_invalid_lists() {
+        let mut config = AccessConfig::default();
+        config.allow.oui = list(&["52:54"]);
+        assert!(AccessControl::from_config(&config).is_err());
+
+        let mut config = AccessConfig::default();
+        config.deny.mac = list(&["not a mac"]);
+        assert!(AccessControl::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,708 @@
+//! Dynamic DNS updates (RFC 2136): A and PTR records of clients are added
+//! when their lease is committed and removed when it is dropped. Updates
+//! are signed with TSIG (RFC 8945) when a key is configured.
+//!
+//! Names are claimed with a DHCID record (RFC 4701) next to the A record, and
+//! only changed while it is still the client's (RFC 4703), so a client can't
+//! take over a name that belongs to another host.
+
+use crate::config::{DdnsConfig, TsigConfig};
+use crate::dhcp::leases;
+use crate::dhcp::options::encode_domain;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const OPCODE_UPDATE: u8 = 5;
+
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_DHCID: u16 = 49;
+const TYPE_ANY: u16 = 255;
+const TYPE_TSIG: u16 = 250;
+
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+/// Allowed clock skew between us and the server for TSIG, in seconds
+const TSIG_FUDGE: u16 = 300;
+
+// Response codes answering a failed prerequisite (RFC 2136, section 2.2)
+const RCODE_YXDOMAIN: u8 = 6;
+const RCODE_NXRRSET: u8 = 8;
+
+/// DHCID identifier type of a client identified by its hardware address, and
+/// digest type (RFC 4701, section 3.3)
+const DHCID_HARDWARE_ADDRESS: [u8; 2] = [0, 0];
+const DHCID_SHA256: u8 = 1;
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A resource record of the prerequisite or update section (RFC 2136,
+/// sections 2.4 and 2.5)
+#[derive(Clone)]
+struct Record {
+    name: String,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn mac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+struct TsigKey {
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    fn from_config(config: &TsigConfig) -> Result<Self, String> {
+        let algorithm = match config.algorithm.trim_end_matches('.') {
+            "hmac-sha256" => TsigAlgorithm::HmacSha256,
+            "hmac-sha512" => TsigAlgorithm::HmacSha512,
+            other => return Err(format!("Unsupported TSIG algorithm: {}", other)),
+        };
+        let secret = base64::engine::general_purpose::STANDARD
+            .decode(&config.secret)
+            .map_err(|_| format!("Invalid TSIG secret for key {}", config.name))?;
+        Ok(TsigKey {
+            name: config.name.to_ascii_lowercase(),
+            algorithm,
+            secret,
+        })
+    }
+
+    /// Append the TSIG record to `message`, signed at `time`
+    fn sign(&self, message: &mut Vec<u8>, time: u64) -> Option<()> {
+        let mut key_name = Vec::new();
+        encode_domain(&self.name, &mut key_name)?;
+        let mut algorithm = Vec::new();
+        encode_domain(self.algorithm.name(), &mut algorithm)?;
+        let time = &time.to_be_bytes()[2..];
+
+        // The MAC covers the message and the TSIG variables (RFC 8945,
+        // section 4.3.3)
+        let mut signed = message.clone();
+        signed.extend_from_slice(&key_name);
+        signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        signed.extend_from_slice(&0u32.to_be_bytes());
+        signed.extend_from_slice(&algorithm);
+        signed.extend_from_slice(time);
+        signed.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        signed.extend_from_slice(&[0, 0, 0, 0]); // Error, other data length
+        let mac = self.algorithm.mac(&self.secret, &signed);
+
+        let mut rdata = algorithm;
+        rdata.extend_from_slice(time);
+        rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&message[..2]); // Original ID
+        rdata.extend_from_slice(&[0, 0, 0, 0]);
+        let tsig = Record {
+            name: self.name.clone(),
+            rtype: TYPE_TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata,
+        };
+        encode_record(&tsig, message)?;
+
+        let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+        message[10..12].copy_from_slice(&additional.to_be_bytes());
+        Some(())
+    }
+}
+
+/// Sends the updates to the authoritative server of the zones
+pub struct DnsUpdater {
+    server: SocketAddr,
+    forward_zone: String,
+    reverse_zone: Option<String>,
+    ttl: u32,
+    tsig: Option<TsigKey>,
+    next_id: AtomicU16,
+}
+
+impl DnsUpdater {
+    /// The forward zone defaults to the DHCP `domain_name`
+    pub fn from_config(config: &DdnsConfig, domain_name: Option<&str>) -> Result<Self, String> {
+        let server = config
+            .server
+            .parse::<SocketAddr>()
+            .or_else(|_| config.server.parse::<IpAddr>().map(|ip| (ip, 53).into()))
+            .map_err(|_| format!("Invalid DNS server: {}", config.server))?;
+        let forward_zone = config
+            .forward_zone
+            .as_deref()
+            .or(domain_name)
+            .ok_or("Dynamic DNS needs a forward_zone or a domain_name")?;
+        Ok(DnsUpdater {
+            server,
+            forward_zone: forward_zone.trim_end_matches('.').to_string(),
+            reverse_zone: config
+                .reverse_zone
+                .as_ref()
+                .map(|zone| zone.trim_end_matches('.').to_ascii_lowercase()),
+            ttl: config.ttl,
+            tsig: config.tsig.as_ref().map(TsigKey::from_config).transpose()?,
+            next_id: AtomicU16::new(leases::now() as u16),
+        })
+    }
+
+    /// Point `hostname` in the forward zone at `ip`, replacing its previous
+    /// addresses, and `ip` back at it. Fails without touching DNS when the
+    /// name belongs to another client.
+    pub async fn add(&self, hostname: &str, ip: Ipv4Addr, mac: [u8; 6]) -> Result<(), String> {
+        let fqdn = self.fqdn(hostname)?;
+        let dhcid = Record {
+            name: fqdn.clone(),
+            rtype: TYPE_DHCID,
+            class: CLASS_IN,
+            ttl: self.ttl,
+            rdata: dhcid(&fqdn, mac).ok_or("Invalid hostname")?,
+        };
+        let address = Record {
+            name: fqdn.clone(),
+            rtype: TYPE_A,
+            class: CLASS_IN,
+            ttl: self.ttl,
+            rdata: ip.octets().to_vec(),
+        };
+
+        // A name nobody uses yet is claimed along with the address
+        let not_in_use = Record::prerequisite(&fqdn, TYPE_ANY, CLASS_NONE, Vec::new());
+        let rcode = self
+            .exchange(
+                &self.forward_zone,
+                &[not_in_use],
+                &[dhcid.clone(), address.clone()],
+            )
+            .await?;
+        match rcode {
+            0 => {}
+            // A name the client already holds is pointed at the new address
+            RCODE_YXDOMAIN => {
+                let claimed = Record::prerequisite(&fqdn, TYPE_DHCID, CLASS_IN, dhcid.rdata);
+                let rcode = self
+                    .exchange(
+                        &self.forward_zone,
+                        &[claimed],
+                        &[Record::delete_rrset(&fqdn, TYPE_A), address],
+                    )
+                    .await?;
+                match rcode {
+                    0 => {}
+                    RCODE_NXRRSET => return Err(format!("{} belongs to another client", fqdn)),
+                    rcode => return Err(self.refused(&self.forward_zone, rcode)),
+                }
+            }
+            rcode => return Err(self.refused(&self.forward_zone, rcode)),
+        }
+
+        if let Some((zone, name)) = self.reverse_record(ip) {
+            let mut target = Vec::new();
+            encode_domain(&fqdn, &mut target).ok_or("Invalid hostname")?;
+            self.send(
+                zone,
+                &[],
+                &[
+                    Record::delete_rrset(&name, TYPE_PTR),
+                    Record {
+                        name,
+                        rtype: TYPE_PTR,
+                        class: CLASS_IN,
+                        ttl: self.ttl,
+                        rdata: target,
+                    },
+                ],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the A record of `hostname` for `ip` and the client's claim on
+    /// the name, unless it belongs to another client by now, and the PTR
+    /// record of `ip` either way
+    pub async fn remove(&self, hostname: &str, ip: Ipv4Addr, mac: [u8; 6]) -> Result<(), String> {
+        let fqdn = self.fqdn(hostname)?;
+        let claimed = Record::prerequisite(
+            &fqdn,
+            TYPE_DHCID,
+            CLASS_IN,
+            dhcid(&fqdn, mac).ok_or("Invalid hostname")?,
+        );
+        let rcode = self
+            .exchange(
+                &self.forward_zone,
+                &[claimed],
+                &[
+                    Record::delete_rrset(&fqdn, TYPE_DHCID),
+                    Record {
+                        name: fqdn,
+                        rtype: TYPE_A,
+                        class: CLASS_NONE,
+                        ttl: 0,
+                        rdata: ip.octets().to_vec(),
+                    },
+                ],
+            )
+            .await?;
+        if rcode != 0 && rcode != RCODE_NXRRSET {
+            return Err(self.refused(&self.forward_zone, rcode));
+        }
+
+        if let Some((zone, name)) = self.reverse_record(ip) {
+            self.send(zone, &[], &[Record::delete_rrset(&name, TYPE_PTR)])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The reverse zone and the name of the PTR record of `ip`, when the zone
+    /// holds it: addresses of other networks are left alone
+    fn reverse_record(&self, ip: Ipv4Addr) -> Option<(&str, String)> {
+        let zone = self.reverse_zone.as_deref()?;
+        let name = reverse_name(ip);
+        let in_zone = name == zone || name.ends_with(&format!(".{}", zone));
+        in_zone.then_some((zone, name))
+    }
+
+    /// Name of a client in the forward zone. Client-supplied hostnames may
+    /// be anything, only letters, digits and hyphens make it into DNS.
+    pub fn fqdn(&self, hostname: &str) -> Result<String, String> {
+        let label = hostname.split('.').next().unwrap_or_default();
+        let valid = !label.is_empty()
+            && label.len() <= 63
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if !valid {
+            return Err(format!("Hostname {} is not a valid DNS name", hostname));
+        }
+        Ok(format!("{}.{}", label, self.forward_zone))
+    }
+
+    async fn send(
+        &self,
+        zone: &str,
+        prerequisites: &[Record],
+        updates: &[Record],
+    ) -> Result<(), String> {
+        match self.exchange(zone, prerequisites, updates).await? {
+            0 => Ok(()),
+            rcode => Err(self.refused(zone, rcode)),
+        }
+    }
+
+    fn refused(&self, zone: &str, rcode: u8) -> String {
+        format!(
+            "Update of zone {} refused by {}: {}",
+            zone,
+            self.server,
+            rcode_name(rcode)
+        )
+    }
+
+    /// Send an update and wait for the response code
+    async fn exchange(
+        &self,
+        zone: &str,
+        prerequisites: &[Record],
+        updates: &[Record],
+    ) -> Result<u8, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut message = update_message(id, zone, prerequisites, updates)
+            .ok_or_else(|| format!("Cannot encode update of zone {}", zone))?;
+        if let Some(ref key) = self.tsig {
+            key.sign(&mut message, leases::now())
+                .ok_or("Cannot sign update")?;
+        }
+
+        let local = match self.server {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+        socket
+            .connect(self.server)
+            .await
+            .map_err(|e| e.to_string())?;
+        socket.send(&message).await.map_err(|e| e.to_string())?;
+
+        let mut buf = [0u8; 512];
+        loop {
+            let size = tokio::time::timeout(UPDATE_TIMEOUT, socket.recv(&mut buf))
+                .await
+                .map_err(|_| format!("No answer from {}", self.server))?
+                .map_err(|e| e.to_string())?;
+            // Skip stray answers to other messages
+            if size < 12 || buf[..2] != id.to_be_bytes() || buf[2] & 0x80 == 0 {
+                continue;
+            }
+            return Ok(buf[3] & 0x0f);
+        }
+    }
+}
+
+impl Record {
+    /// A prerequisite on the records of a type at `name` (RFC 2136, section
+    /// 2.4): class NONE for none to exist, class IN for one with `rdata`
+    fn prerequisite(name: &str, rtype: u16, class: u16, rdata: Vec<u8>) -> Self {
+        Record {
+            name: name.to_string(),
+            rtype,
+            class,
+            ttl: 0,
+            rdata,
+        }
+    }
+
+    /// Delete all records of a type at `name` (RFC 2136, section 2.5.2)
+    fn delete_rrset(name: &str, rtype: u16) -> Self {
+        Record {
+            name: name.to_string(),
+            rtype,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata: Vec::new(),
+        }
+    }
+}
+
+/// UPDATE message for `zone`
+fn update_message(
+    id: u16,
+    zone: &str,
+    prerequisites: &[Record],
+    updates: &[Record],
+) -> Option<Vec<u8>> {
+    let mut message = id.to_be_bytes().to_vec();
+    message.extend_from_slice(&[OPCODE_UPDATE << 3, 0]);
+    for count in [1, prerequisites.len() as u16, updates.len() as u16, 0] {
+        message.extend_from_slice(&count.to_be_bytes());
+    }
+    encode_domain(zone, &mut message)?;
+    message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    for record in prerequisites.iter().chain(updates) {
+        encode_record(record, &mut message)?;
+    }
+    Some(message)
+}
+
+fn encode_record(record: &Record, out: &mut Vec<u8>) -> Option<()> {
+    encode_domain(&record.name, out)?;
+    out.extend_from_slice(&record.rtype.to_be_bytes());
+    out.extend_from_slice(&record.class.to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+    out.extend_from_slice(&(record.rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(&record.rdata);
+    Some(())
+}
+
+/// DHCID record data of the client with hardware address `mac` for `fqdn`
+/// (RFC 4701, section 3.5): a digest of both, so the record identifies the
+/// client without revealing it
+fn dhcid(fqdn: &str, mac: [u8; 6]) -> Option<Vec<u8>> {
+    let mut name = Vec::new();
+    encode_domain(&fqdn.to_ascii_lowercase(), &mut name)?;
+    let mut digest = Sha256::new();
+    digest.update([1]); // Ethernet
+    digest.update(mac);
+    digest.update(&name);
+
+    let mut rdata = DHCID_HARDWARE_ADDRESS.to_vec();
+    rdata.push(DHCID_SHA256);
+    rdata.extend_from_slice(&digest.finalize());
+    Some(rdata)
+}
+
+/// "d.c.b.a.in-addr.arpa" for a.b.c.d
+fn reverse_name(ip: Ipv4Addr) -> String {
+    let [a, b, c, d] = ip.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+}
+
+fn rcode_name(rcode: u8) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    /// A stand-in authoritative server: answers the messages with `rcodes`
+    /// in turn, the last one repeated, and hands over what it received
+    pub(crate) async fn stand_in_server(
+        rcodes: &'static [u8],
+    ) -> (SocketAddr, tokio::sync::mpsc::Receiver<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            let mut n = 0;
+            while let Ok((size, peer)) = socket.recv_from(&mut buf).await {
+                let mut answer = buf[..12].to_vec();
+                answer[2] |= 0x80;
+                answer[3] = rcodes[n.min(rcodes.len() - 1)];
+                n += 1;
+                let _ = socket.send_to(&answer, peer).await;
+                if tx.send(buf[..size].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (addr, rx)
+    }
+
+    fn config(server: SocketAddr) -> DdnsConfig {
+        DdnsConfig {
+            server: server.to_string(),
+            forward_zone: None,
+            reverse_zone: Some("1.168.192.in-addr.arpa".to_string()),
+            ttl: 300,
+            tsig: None,
+        }
+    }
+
+    /// Offset of the first record of the update section
+    fn updates_offset(message: &[u8]) -> usize {
+        let mut i = 12;
+        while message[i] != 0 {
+            i += message[i] as usize + 1;
+        }
+        i + 5
+    }
+
+    /// DHCID of MAC for node-100.lab.example
+    fn expected_dhcid() -> Vec<u8> {
+        let mut digest = Sha256::new();
+        digest.update([1, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        digest.update(b"\x08node-100\x03lab\x07example\x00");
+        let mut rdata = vec![0, 0, 1];
+        rdata.extend_from_slice(&digest.finalize());
+        rdata
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove() {
+        let (server, mut received) = stand_in_server(&[0]).await;
+        let updater = DnsUpdater::from_config(&config(server), Some("lab.example")).unwrap();
+        let ip = Ipv4Addr::new(192, 168, 1, 100);
+        updater.add("node-100", ip, MAC).await.unwrap();
+
+        // The name must not be in use, and is claimed with a DHCID record
+        let forward = received.recv().await.unwrap();
+        assert_eq!(forward[2] >> 3, OPCODE_UPDATE);
+        assert_eq!(&forward[4..12], &[0, 1, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(&forward[12..25], b"\x03lab\x07example\x00");
+        let name = b"\x08node-100\x03lab\x07example\x00";
+        let mut expected = name.to_vec();
+        expected.extend_from_slice(&[0, 255, 0, 254, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 49, 0, 1, 0, 0, 1, 44, 0, 35]);
+        expected.extend_from_slice(&expected_dhcid());
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 168, 1, 100]);
+        assert_eq!(&forward[updates_offset(&forward)..], &expected[..]);
+
+        let reverse = received.recv().await.unwrap();
+        assert_eq!(
+            &reverse[12..36],
+            b"\x011\x03168\x03192\x07in-addr\x04arpa\x00"
+        );
+        assert_eq!(&reverse[4..12], &[0, 1, 0, 0, 0, 2, 0, 0]);
+        assert!(reverse.ends_with(name));
+        assert!(reverse[updates_offset(&reverse)..].starts_with(b"\x03100\x011\x03168\x03192"));
+
+        // Only while the name is still the client's
+        updater.remove("node-100", ip, MAC).await.unwrap();
+        let forward = received.recv().await.unwrap();
+        assert_eq!(&forward[4..12], &[0, 1, 0, 1, 0, 2, 0, 0]);
+        let mut expected = name.to_vec();
+        expected.extend_from_slice(&[0, 49, 0, 1, 0, 0, 0, 0, 0, 35]);
+        expected.extend_from_slice(&expected_dhcid());
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 49, 0, 255, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 1, 0, 254, 0, 0, 0, 0, 0, 4, 192, 168, 1, 100]);
+        assert_eq!(&forward[updates_offset(&forward)..], &expected[..]);
+        let reverse = received.recv().await.unwrap();
+        assert!(reverse.ends_with(&[0, 12, 0, 255, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_name_in_use() {
+        let ip = Ipv4Addr::new(192, 168, 1, 100);
+
+        // The client's own name moves to its new address
+        let (server, mut received) = stand_in_server(&[RCODE_YXDOMAIN, 0]).await;
+        let updater = DnsUpdater::from_config(&config(server), Some("lab.example")).unwrap();
+        updater.add("node-100", ip, MAC).await.unwrap();
+        received.recv().await.unwrap();
+        let forward = received.recv().await.unwrap();
+        assert_eq!(&forward[4..12], &[0, 1, 0, 1, 0, 2, 0, 0]);
+        let name = b"\x08node-100\x03lab\x07example\x00";
+        let mut expected = name.to_vec();
+        expected.extend_from_slice(&[0, 49, 0, 1, 0, 0, 0, 0, 0, 35]);
+        expected.extend_from_slice(&expected_dhcid());
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 1, 0, 255, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 168, 1, 100]);
+        assert_eq!(&forward[updates_offset(&forward)..], &expected[..]);
+        assert!(received.recv().await.is_some());
+
+        // Another host's name is left alone, and so is the reverse zone
+        let (server, mut received) =
+            stand_in_server(&[RCODE_YXDOMAIN, RCODE_NXRRSET, RCODE_NXRRSET, 0]).await;
+        let updater = DnsUpdater::from_config(&config(server), Some("lab.example")).unwrap();
+        let error = updater
+            .add("node-100", ip, [0x52, 0x54, 0, 0, 0, 1])
+            .await
+            .unwrap_err();
+        assert!(error.contains("belongs to another client"));
+        received.recv().await.unwrap();
+        received.recv().await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), received.recv())
+                .await
+                .is_err()
+        );
+
+        // Removing a name taken over since only drops the PTR record
+        updater.remove("node-100", ip, MAC).await.unwrap();
+        received.recv().await.unwrap();
+        let reverse = received.recv().await.unwrap();
+        assert!(reverse.ends_with(&[0, 12, 0, 255, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_refused_update() {
+        let (server, _received) = stand_in_server(&[5]).await;
+        let updater = DnsUpdater::from_config(&config(server), Some("lab.example")).unwrap();
+        let error = updater
+            .add("node-100", Ipv4Addr::new(192, 168, 1, 100), MAC)
+            .await
+            .unwrap_err();
+        assert!(error.contains("REFUSED"));
+    }
+
+    #[test]
+    fn test_tsig() {
+        let key = TsigKey::from_config(&TsigConfig {
+            name: "DHCP-Update".to_string(),
+            algorithm: "hmac-sha256".to_string(),
+            secret: "c2VjcmV0".to_string(), // "secret"
+        })
+        .unwrap();
+        let unsigned = update_message(0x1234, "lab.example", &[], &[]).unwrap();
+        let mut message = unsigned.clone();
+        key.sign(&mut message, 1_700_000_000).unwrap();
+
+        assert_eq!(&message[10..12], &[0, 1]);
+        let tsig = &message[unsigned.len()..];
+        assert!(tsig.starts_with(b"\x0bdhcp-update\x00\x00\xfa\x00\xff\x00\x00\x00\x00"));
+        let rdata = &tsig[23..];
+        assert!(rdata.starts_with(b"\x0bhmac-sha256\x00"));
+        assert_eq!(&rdata[13..19], &1_700_000_000u64.to_be_bytes()[2..]);
+        assert_eq!(&rdata[21..23], &[0, 32]);
+
+        // Same MAC as computed independently over message and variables
+        let mut signed = unsigned;
+        signed.extend_from_slice(b"\x0bdhcp-update\x00\x00\xff\x00\x00\x00\x00");
+        signed.extend_from_slice(&rdata[..21]);
+        signed.extend_from_slice(&[0, 0, 0, 0]);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(&signed);
+        mac.verify_slice(&rdata[23..55]).unwrap();
+        assert_eq!(&rdata[55..], &[0x12, 0x34, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_config() {
+        let addr: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let mut config = config(addr);
+        config.server = "127.0.0.1".to_string();
+        let updater = DnsUpdater::from_config(&config, Some("lab.example.")).unwrap();
+        assert_eq!(updater.server, addr);
+        assert_eq!(updater.fqdn("node-1").unwrap(), "node-1.lab.example");
+        assert_eq!(
+            updater.fqdn("node-1.other.example").unwrap(),
+            "node-1.lab.example"
+        );
+        assert!(updater.fqdn("My Laptop").is_err());
+
+        config.reverse_zone = Some("1.168.192.IN-ADDR.ARPA.".to_string());
+        let updater = DnsUpdater::from_config(&config, Some("lab.example")).unwrap();
+        assert_eq!(
+            updater.reverse_record(Ipv4Addr::new(192, 168, 1, 100)),
+            Some((
+                "1.168.192.in-addr.arpa",
+                "100.1.168.192.in-addr.arpa".to_string()
+            ))
+        );
+        assert_eq!(updater.reverse_record(Ipv4Addr::new(192, 168, 11, 1)), None);
+        assert_eq!(updater.reverse_record(Ipv4Addr::new(10, 0, 0, 1)), None);
+
+        assert!(DnsUpdater::from_config(&config, None).is_err());
+        config.tsig = Some(TsigConfig {
+            name: "key".to_string(),
+            algorithm: "hmac-md5".to_string(),
+            secret: "c2VjcmV0".to_string(),
+        });
+        assert!(DnsUpdater::from_config(&config, Some("lab.example")).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Unicast replies to clients that have no IP address yet: the UDP datagram
+//! goes out in a frame addressed to the client's hardware address (RFC 2131,
+//! section 4.1), over an AF_PACKET socket.
+
+use crate::dhcp::probe::checksum;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::mem;
+use std::net::SocketAddrV4;
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+const ETH_P_IP: u16 = 0x0800;
+const IPPROTO_UDP: u8 = 17;
+const TTL: u8 = 64;
+
+pub struct FrameSocket {
+    socket: AsyncFd<Socket>,
+}
+
+impl FrameSocket {
+    /// Open the packet socket, which needs CAP_NET_RAW
+    pub fn new() -> io::Result<Self> {
+        // Protocol 0: the socket only sends, no frames are received on it
+        let socket = Socket::new(Domain::PACKET, Type::DGRAM, None)?;
+        socket.set_nonblocking(true)?;
+        Ok(FrameSocket {
+            socket: AsyncFd::new(socket)?,
+        })
+    }
+
+    /// Send `data` from `src` to `dest` in a frame to `mac`, out of the
+    /// interface with index `ifindex`
+    pub async fn send_to(
+        &self,
+        data: &[u8],
+        src: SocketAddrV4,
+        dest: SocketAddrV4,
+        mac: [u8; 6],
+        ifindex: u32,
+    ) -> io::Result<usize> {
+        let packet = udp_packet(data, src, dest);
+        self.socket
+            .async_io(Interest::WRITABLE, |socket| {
+                sendto(socket.as_raw_fd(), &packet, mac, ifindex)
+            })
+            .await?;
+        Ok(data.len())
+    }
+}
+
+/// IPv4 header and UDP header in front of `data`
+fn udp_packet(data: &[u8], src: SocketAddrV4, dest: SocketAddrV4) -> Vec<u8> {
+    let udp_len = 8 + data.len() as u16;
+    let total_len = 20 + udp_len;
+
+    let mut packet = vec![0x45, 0]; // Version 4, 20-byte header
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]); // Identification, no fragments
+    packet.extend_from_slice(&[TTL, IPPROTO_UDP, 0, 0]);
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dest.ip().octets());
+    let header_checksum = checksum(&packet);
+    packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dest.port().to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(data);
+
+    // The UDP checksum covers a pseudo-header of addresses, protocol and length
+    let mut pseudo = Vec::with_capacity(12 + packet.len() - 20);
+    pseudo.extend_from_slice(&packet[12..20]);
+    pseudo.extend_from_slice(&[0, IPPROTO_UDP]);
+    pseudo.extend_from_slice(&udp_len.to_be_bytes());
+    pseudo.extend_from_slice(&packet[20..]);
+    let udp_checksum = match checksum(&pseudo) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[26..28].copy_from_slice(&udp_checksum.to_be_bytes());
+    packet
+}
+
+fn sendto(fd: RawFd, packet: &[u8], mac: [u8; 6], ifindex: u32) -> io::Result<usize> {
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+    addr.sll_protocol = ETH_P_IP.to_be();
+    addr.sll_ifindex = ifindex as libc::c_int;
+    addr.sll_halen = 6;
+    addr.sll_addr[..6].copy_from_slice(&mac);
+
+    let size = unsafe {
+        libc::sendto(
+            fd,
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &addr as *const _ as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::server::interface_index;
+    use std::io::Read;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_udp_packet() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 1), 67);
+        let dest = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 100), 68);
+        let packet = udp_packet(b"offer", src, dest);
+
+        assert_eq!(packet.len(), 20 + 8 + 5);
+        assert_eq!(&packet[2..4], &33u16.to_be_bytes());
+        assert_eq!(checksum(&packet[..20]), 0);
+        assert_eq!(&packet[20..24], &[0, 67, 0, 68]);
+        assert_eq!(&packet[24..26], &13u16.to_be_bytes());
+        assert_eq!(&packet[28..], b"offer");
+
+        let mut pseudo = packet[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, IPPROTO_UDP, 0, 13]);
+        pseudo.extend_from_slice(&packet[20..]);
+        assert_eq!(checksum(&pseudo), 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_over_loopback() {
+        // Needs CAP_NET_RAW
+        let Ok(frames) = FrameSocket::new() else {
+            return;
+        };
+        // Frames sent on an interface show up on packet sockets listening
+        // to all protocols (ETH_P_ALL)
+        let capture = Socket::new(
+            Domain::PACKET,
+            Type::RAW,
+            Some((0x0003u16.to_be() as i32).into()),
+        )
+        .unwrap();
+        capture
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+
+        let src = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 67);
+        let dest = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 100), 68);
+        let mac = [0x02, 0, 0, 0, 0, 0x01];
+        let loopback = interface_index("lo").unwrap();
+        frames
+            .send_to(b"finiky frame test", src, dest, mac, loopback)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 1500];
+        let frame = loop {
+            let size = (&capture).read(&mut buf).unwrap();
+            if buf[..size].ends_with(b"finiky frame test") {
+                break &buf[..size];
+            }
+        };
+        assert_eq!(&frame[..6], &mac);
+        assert_eq!(&frame[12..14], &ETH_P_IP.to_be_bytes());
+        assert_eq!(
+            &frame[14..],
+            &udp_packet(b"finiky frame test", src, dest)[..]
+        );
+    }
+}
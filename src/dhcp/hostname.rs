@@ -0,0 +1,136 @@
+//! Hostnames handed out in option 12, and the reply to the client FQDN
+//! option 81 (RFC 4702)
+
+use crate::dhcp::options::encode_domain;
+use std::net::Ipv4Addr;
+
+// Client FQDN flags (RFC 4702, section 2.1)
+const FQDN_SERVER_UPDATE: u8 = 0x01;
+const FQDN_OVERRIDE: u8 = 0x02;
+const FQDN_ENCODED: u8 = 0x04;
+const FQDN_NO_UPDATE: u8 = 0x08;
+
+/// Check that a hostname pattern only uses known placeholders
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    expand_with(pattern, |name| placeholder(name, &[0; 6], Ipv4Addr::UNSPECIFIED))
+        .map(|_| ())
+        .map_err(|name| {
+            format!(
+                "Unknown placeholder {{{}}} in hostname pattern {}, use {{mac}}, {{ip}} or {{ip_last_octet}}",
+                name, pattern
+            )
+        })
+}
+
+/// Hostname of a client from a pattern such as "node-{ip_last_octet}":
+/// `{mac}` is the hardware address as 12 hex digits and `{ip}` the address
+/// with dashes, e.g. "192-168-1-10"
+pub fn expand(pattern: &str, mac: &[u8; 6], ip: Ipv4Addr) -> String {
+    expand_with(pattern, |name| placeholder(name, mac, ip)).unwrap_or_else(|_| pattern.to_string())
+}
+
+fn placeholder(name: &str, mac: &[u8; 6], ip: Ipv4Addr) -> Option<String> {
+    match name {
+        "mac" => Some(mac.iter().map(|b| format!("{:02x}", b)).collect()),
+        "ip" => Some(ip.to_string().replace('.', "-")),
+        "ip_last_octet" => Some(ip.octets()[3].to_string()),
+        _ => None,
+    }
+}
+
+/// Replace the `{placeholders}` of `pattern`, or return the unknown one
+fn expand_with<F>(pattern: &str, value: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut hostname = String::new();
+    let mut rest = pattern;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[open + 1..open + close];
+        hostname.push_str(&rest[..open]);
+        hostname.push_str(&value(placeholder).ok_or_else(|| placeholder.to_string())?);
+        rest = &rest[open + close + 1..];
+    }
+    hostname.push_str(rest);
+    Ok(hostname)
+}
+
+/// Reply to the client FQDN option `request`, naming the client `fqdn`, or
+/// the name it sent when the server has none for it. `server_updates` tells
+/// whether the server publishes the name in DNS itself.
+pub fn client_fqdn(request: &[u8], fqdn: Option<&str>, server_updates: bool) -> Option<Vec<u8>> {
+    let flags = *request.first()?;
+    let client_asked = flags & FQDN_SERVER_UPDATE != 0;
+    let mut reply = vec![flags & FQDN_ENCODED, 255, 255];
+    if server_updates {
+        reply[0] |= FQDN_SERVER_UPDATE;
+    } else {
+        reply[0] |= FQDN_NO_UPDATE;
+    }
+    if client_asked != server_updates {
+        // Not what the client asked for
+        reply[0] |= FQDN_OVERRIDE;
+    }
+
+    match fqdn {
+        Some(fqdn) if flags & FQDN_ENCODED != 0 => {
+            let partial = !fqdn.contains('.');
+            encode_domain(fqdn, &mut reply)?;
+            if partial {
+                // Names without a domain go without the root label
+                reply.pop();
+            }
+        }
+        Some(fqdn) => reply.extend_from_slice(fqdn.as_bytes()),
+        None => reply.extend_from_slice(request.get(3..)?),
+    }
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef];
+
+    #[test]
+    fn test_expand() {
+        let ip = Ipv4Addr::new(192, 168, 1, 42);
+        assert_eq!(expand("node-{ip_last_octet}", &MAC, ip), "node-42");
+        assert_eq!(expand("build-{mac}", &MAC, ip), "build-525400abcdef");
+        assert_eq!(expand("{ip}.lab", &MAC, ip), "192-168-1-42.lab");
+        assert_eq!(expand("static", &MAC, ip), "static");
+
+        assert!(check_pattern("node-{ip_last_octet}").is_ok());
+        assert!(check_pattern("node-{serial}").is_err());
+    }
+
+    #[test]
+    fn test_client_fqdn() {
+        // Canonical wire format, asking the server to update DNS
+        let mut request = vec![FQDN_ENCODED | FQDN_SERVER_UPDATE, 0, 0];
+        request.extend_from_slice(b"\x06laptop\x00");
+        let reply = client_fqdn(&request, Some("node-42.lab.example"), false).unwrap();
+        assert_eq!(reply[0], FQDN_ENCODED | FQDN_NO_UPDATE | FQDN_OVERRIDE);
+        assert_eq!(&reply[1..3], &[255, 255]);
+        assert_eq!(&reply[3..], b"\x07node-42\x03lab\x07example\x00");
+
+        let reply = client_fqdn(&request, Some("node-42"), true).unwrap();
+        assert_eq!(reply[0], FQDN_ENCODED | FQDN_SERVER_UPDATE);
+        assert_eq!(&reply[3..], b"\x07node-42");
+
+        // Deprecated ASCII encoding, the client's name echoed back
+        let mut request = vec![0, 0, 0];
+        request.extend_from_slice(b"laptop");
+        let reply = client_fqdn(&request, None, false).unwrap();
+        assert_eq!(
+            reply,
+            [FQDN_NO_UPDATE, 255, 255, b'l', b'a', b'p', b't', b'o', b'p']
+        );
+
+        assert!(client_fqdn(&[], None, false).is_none());
+    }
+}
@@ -0,0 +1,542 @@
+use crate::dhcp::pool::PoolState;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseState {
+    /// Address has been offered but the client has not requested it yet
+    Offered,
+    /// Address has been acknowledged and is in use by the client
+    Bound,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    #[serde(with = "mac_format")]
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    pub state: LeaseState,
+    /// Lease start, in seconds since the Unix epoch
+    pub start: u64,
+    /// Lease expiry, in seconds since the Unix epoch
+    pub expiry: u64,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub client_arch: Option<u16>,
+}
+
+impl Lease {
+    pub fn new(mac: [u8; 6], ip: Ipv4Addr, state: LeaseState, duration: u32) -> Self {
+        let start = now();
+        Lease {
+            mac,
+            ip,
+            state,
+            start,
+            expiry: start.saturating_add(duration as u64),
+            hostname: None,
+            client_arch: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry <= now
+    }
+
+    /// Restart the lease clock with a new state and duration
+    pub fn renew(&mut self, state: LeaseState, duration: u32) {
+        self.state = state;
+        self.start = now();
+        self.expiry = self.start.saturating_add(duration as u64);
+    }
+}
+
+/// A record of a lease database
+pub trait Record: Clone + Serialize + DeserializeOwned {
+    /// Identifies the client holding the record, for the journal
+    fn key(&self) -> String;
+    /// Orders the records of the database file
+    fn address(&self) -> IpAddr;
+}
+
+impl Record for Lease {
+    fn key(&self) -> String {
+        format_mac(&self.mac)
+    }
+
+    fn address(&self) -> IpAddr {
+        IpAddr::V4(self.ip)
+    }
+}
+
+/// A table of records, written out whole now and then
+pub trait Snapshot<T> {
+    /// Number of records in the table
+    fn count(&self) -> usize;
+    fn snapshot(&self) -> Vec<T>;
+}
+
+/// A change to the lease database, as written to its journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseChange<T = Lease> {
+    /// A new or updated lease
+    Lease(T),
+    /// The lease of the client with the given key was dropped
+    Remove(String),
+}
+
+/// Journal entries always allowed before the table is written out
+const MIN_JOURNAL_LEN: usize = 1024;
+
+#[derive(Default)]
+struct Journal {
+    file: Option<fs::File>,
+    /// Entries written since the table was last written out
+    len: usize,
+}
+
+/// On-disk lease database.
+///
+/// Changes are appended to a journal next to the database, one JSON line
+/// each. Now and then the whole lease table is written out instead: it is
+/// serialized to a temporary file, flushed, and then atomically renamed over
+/// the previous version so a crash never leaves a truncated file behind, and
+/// the journal starts over. A store without a path keeps leases in memory
+/// only.
+pub struct LeaseStore<T = Lease> {
+    path: Option<PathBuf>,
+    journal: Mutex<Journal>,
+    records: PhantomData<T>,
+}
+
+impl<T: Record> LeaseStore<T> {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        LeaseStore {
+            path,
+            journal: Mutex::new(Journal::default()),
+            records: PhantomData,
+        }
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// The saved leases with the journal replayed on top, in the order they
+    /// were last changed
+    pub fn load(&self) -> io::Result<Vec<T>> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(Vec::new()),
+        };
+
+        let saved: Vec<T> = match fs::read_to_string(path) {
+            Ok(content) if content.trim().is_empty() => Vec::new(),
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut leases: HashMap<String, (usize, T)> = saved
+            .into_iter()
+            .enumerate()
+            .map(|(n, lease)| (lease.key(), (n, lease)))
+            .collect();
+
+        let journal = match fs::File::open(journal_path(path)) {
+            Ok(file) => io::BufReader::new(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(in_order(leases));
+            }
+            Err(e) => return Err(e),
+        };
+        for (n, line) in (leases.len()..).zip(journal.lines()) {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(LeaseChange::Lease(lease)) => {
+                    leases.insert(lease.key(), (n, lease));
+                }
+                Ok(LeaseChange::<T>::Remove(key)) => {
+                    leases.remove(&key);
+                }
+                // The last line is cut short when a write was interrupted
+                Err(e) => tracing::warn!("Skipping lease journal entry: {}", e),
+            }
+        }
+        Ok(in_order(leases))
+    }
+
+    /// Append a change to the journal
+    #[cfg(test)]
+    pub fn append(&self, change: &LeaseChange<T>) -> io::Result<()> {
+        self.append_to(&mut self.journal.lock().unwrap(), change)
+    }
+
+    fn append_to(&self, journal: &mut Journal, change: &LeaseChange<T>) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let mut line = serde_json::to_vec(change)?;
+        line.push(b'\n');
+        let file = match journal.file {
+            Some(ref mut file) => file,
+            None => journal.file.insert(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(journal_path(path))?,
+            ),
+        };
+        file.write_all(&line)?;
+        journal.len += 1;
+        file.sync_data()
+    }
+
+    /// Write out all the leases, replacing the database and its journal
+    pub fn save<'a, I>(&self, leases: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        self.save_to(&mut self.journal.lock().unwrap(), leases)
+    }
+
+    fn save_to<'a, I>(&self, journal: &mut Journal, leases: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let mut leases: Vec<&T> = leases.into_iter().collect();
+        leases.sort_by_key(|lease| lease.address());
+        let content = serde_json::to_vec_pretty(&leases)?;
+
+        let tmp_path = sibling_path(path, ".tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(&content)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+
+        *journal = Journal::default();
+        match fs::remove_file(journal_path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write a change of the locked lease table to disk. The table is
+    /// unlocked first, so allocations don't wait for the disk; the journal
+    /// is locked before that, so changes reach the disk in the order they
+    /// were made.
+    pub fn commit<S: Snapshot<T>>(&self, table: MutexGuard<'_, S>, change: LeaseChange<T>) {
+        if !self.is_persistent() {
+            return;
+        }
+
+        let mut journal = self.journal.lock().unwrap();
+        // Writing the table out once the journal is as long keeps the cost
+        // of a change constant on average
+        let result = if journal.len >= table.count().max(MIN_JOURNAL_LEN) {
+            let snapshot = table.snapshot();
+            drop(table);
+            self.save_to(&mut journal, &snapshot)
+        } else {
+            drop(table);
+            self.append_to(&mut journal, &change)
+        };
+        if let Err(e) = result {
+            tracing::error!(
+                "Failed to write lease database {}: {}",
+                self.path.as_deref().unwrap_or(Path::new("")).display(),
+                e
+            );
+        }
+    }
+}
+
+fn in_order<T>(leases: HashMap<String, (usize, T)>) -> Vec<T> {
+    let mut leases: Vec<_> = leases.into_values().collect();
+    leases.sort_by_key(|(n, _)| *n);
+    leases.into_iter().map(|(_, lease)| lease).collect()
+}
+
+/// Leases by client MAC, indexed by address. Holds at most one lease per
+/// address, and the allocation state of the pools sharing the table.
+#[derive(Debug, Default)]
+pub struct Leases {
+    by_mac: HashMap<[u8; 6], Lease>,
+    by_ip: HashMap<Ipv4Addr, [u8; 6]>,
+    pools: Vec<PoolState>,
+}
+
+impl Leases {
+    pub fn get(&self, mac: &[u8; 6]) -> Option<&Lease> {
+        self.by_mac.get(mac)
+    }
+
+    /// The lease on `ip`, expired or not
+    pub fn holder(&self, ip: Ipv4Addr) -> Option<&Lease> {
+        self.by_ip.get(&ip).and_then(|mac| self.by_mac.get(mac))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Lease> {
+        self.by_mac.values()
+    }
+
+    /// Add a lease, replacing the client's previous lease and any other
+    /// lease on the same address
+    pub fn insert(&mut self, lease: Lease) {
+        self.remove(&lease.mac);
+        if let Some(other) = self.by_ip.get(&lease.ip).copied() {
+            self.remove(&other);
+        }
+        self.by_ip.insert(lease.ip, lease.mac);
+        for pool in &mut self.pools {
+            pool.held.insert(lease.ip);
+        }
+        self.by_mac.insert(lease.mac, lease);
+    }
+
+    pub fn remove(&mut self, mac: &[u8; 6]) -> Option<Lease> {
+        let lease = self.by_mac.remove(mac)?;
+        self.by_ip.remove(&lease.ip);
+        for pool in &mut self.pools {
+            pool.held.remove(lease.ip);
+        }
+        Some(lease)
+    }
+
+    /// Change the lease held by `mac`, if any
+    pub fn update<F: FnOnce(&mut Lease)>(&mut self, mac: &[u8; 6], f: F) -> bool {
+        let Some(lease) = self.by_mac.get_mut(mac) else {
+            return false;
+        };
+        let ip = lease.ip;
+        f(lease);
+        if lease.ip != ip || lease.mac != *mac {
+            let lease = lease.clone();
+            self.remove(mac);
+            self.insert(lease);
+        }
+        true
+    }
+
+    pub(crate) fn pool(&mut self, id: usize) -> &mut PoolState {
+        &mut self.pools[id]
+    }
+}
+
+impl Snapshot<Lease> for Leases {
+    fn count(&self) -> usize {
+        self.by_mac.len()
+    }
+
+    fn snapshot(&self) -> Vec<Lease> {
+        self.by_mac.values().cloned().collect()
+    }
+}
+
+/// Lease table shared by all address pools, keyed by client MAC
+pub struct LeaseTable {
+    leases: Mutex<Leases>,
+    store: LeaseStore,
+}
+
+impl LeaseTable {
+    pub fn load(store: LeaseStore) -> io::Result<Self> {
+        let mut leases = Leases::default();
+        for lease in store.load()? {
+            leases.insert(lease);
+        }
+        // Fold the journal into the database
+        store.save(leases.values())?;
+
+        Ok(LeaseTable {
+            leases: Mutex::new(leases),
+            store,
+        })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Leases> {
+        self.leases.lock().unwrap()
+    }
+
+    /// Track the addresses of `start..=end` held by leases, for a pool to
+    /// allocate from. Returns the pool's id.
+    pub(crate) fn add_pool(&self, start: Ipv4Addr, end: Ipv4Addr) -> usize {
+        let mut leases = self.lock();
+        let mut pool = PoolState::new(start, end);
+        for ip in leases.by_ip.keys() {
+            pool.held.insert(*ip);
+        }
+        leases.pools.push(pool);
+        leases.pools.len() - 1
+    }
+
+    /// Write a change of the locked lease table to disk
+    pub fn commit(&self, leases: MutexGuard<'_, Leases>, change: LeaseChange) {
+        self.store.commit(leases, change);
+    }
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = OsString::from(path.as_os_str());
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    sibling_path(path, ".journal")
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn format_mac(mac: &[u8]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split([':', '-']);
+    for byte in bytes.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(bytes)
+}
+
+mod mac_format {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_mac(mac))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 6], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        super::parse_mac(&s).ok_or_else(|| D::Error::custom(format!("invalid MAC address: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mac_roundtrip() {
+        let mac = [0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc];
+        assert_eq!(format_mac(&mac), "00:11:22:aa:bb:cc");
+        assert_eq!(parse_mac("00:11:22:aa:bb:cc"), Some(mac));
+        assert_eq!(parse_mac("00-11-22-AA-BB-CC"), Some(mac));
+        assert_eq!(parse_mac("00:11:22:aa:bb"), None);
+        assert_eq!(parse_mac("00:11:22:aa:bb:cc:dd"), None);
+    }
+
+    #[test]
+    fn test_lease_store_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = LeaseStore::new(Some(temp_dir.path().join("leases.json")));
+        assert!(store.load().unwrap().is_empty());
+
+        let mut lease = Lease::new(
+            [0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+            "192.168.1.100".parse().unwrap(),
+            LeaseState::Bound,
+            3600,
+        );
+        lease.hostname = Some("node1".to_string());
+        lease.client_arch = Some(7);
+
+        store.save([&lease]).unwrap();
+        assert!(!temp_dir.path().join("leases.json.tmp").exists());
+
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded, vec![lease]);
+    }
+
+    #[test]
+    fn test_lease_journal() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("leases.json");
+        let store = LeaseStore::new(Some(path.clone()));
+        let lease = |n: u8, ip: &str| {
+            Lease::new(
+                [0x00, 0x11, 0x22, 0x33, 0x44, n],
+                ip.parse().unwrap(),
+                LeaseState::Bound,
+                3600,
+            )
+        };
+        let first = lease(1, "192.168.1.100");
+        let second = lease(2, "192.168.1.101");
+        store.save([&first, &second]).unwrap();
+
+        // The address of the first client goes to a third one
+        let third = lease(3, "192.168.1.100");
+        store
+            .append(&LeaseChange::Remove(format_mac(&first.mac)))
+            .unwrap();
+        store.append(&LeaseChange::Lease(third.clone())).unwrap();
+        let mut renewed = second.clone();
+        renewed.hostname = Some("node2".to_string());
+        store.append(&LeaseChange::Lease(renewed.clone())).unwrap();
+        // Torn write
+        let mut journal = fs::OpenOptions::new()
+            .append(true)
+            .open(journal_path(&path))
+            .unwrap();
+        journal.write_all(b"{\"lease\": {\"mac\"").unwrap();
+
+        assert_eq!(store.load().unwrap(), vec![third.clone(), renewed.clone()]);
+
+        // Loading the table folds the journal into the database
+        let table = LeaseTable::load(LeaseStore::new(Some(path.clone()))).unwrap();
+        assert_eq!(table.lock().count(), 2);
+        assert!(!journal_path(&path).exists());
+        assert_eq!(store.load().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = LeaseStore::new(None);
+        let lease = Lease::new(
+            [0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+            "192.168.1.100".parse().unwrap(),
+            LeaseState::Offered,
+            60,
+        );
+        store.save([&lease]).unwrap();
+        assert!(store.load().unwrap().is_empty());
+    }
+}
@@ -1,5 +1,17 @@
+pub mod access;
+pub mod ddns;
+#[cfg(target_os = "linux")]
+pub mod frame;
+pub mod hostname;
+pub mod leases;
 pub mod options;
+#[cfg(target_os = "linux")]
+pub mod pktinfo;
+pub mod pool;
+pub mod probe;
 pub mod protocols;
+pub mod pxe_menu;
+pub mod reservations;
 pub mod server;
 
 pub use server::DhcpServer;
@@ -1,71 +1,651 @@
-use crate::config::DhcpConfig;
+use crate::config::{DhcpConfig, DhcpOptionConfig, DhcpOptionType, DhcpOptionValue};
+use std::fmt;
 use std::net::Ipv4Addr;
 
+// DHCP message types (option 53)
+pub const DHCP_DISCOVER: u8 = 1;
+pub const DHCP_OFFER: u8 = 2;
+pub const DHCP_REQUEST: u8 = 3;
+pub const DHCP_DECLINE: u8 = 4;
+pub const DHCP_ACK: u8 = 5;
+pub const DHCP_NAK: u8 = 6;
+pub const DHCP_RELEASE: u8 = 7;
+pub const DHCP_INFORM: u8 = 8;
+
+// Option codes with special meaning to the encoder
+pub const OPTION_PAD: u8 = 0;
+pub const OPTION_OVERLOAD: u8 = 52;
+pub const OPTION_END: u8 = 255;
+
+// Option overload (option 52) values
+pub const OVERLOAD_FILE: u8 = 1;
+pub const OVERLOAD_SNAME: u8 = 2;
+pub const OVERLOAD_BOTH: u8 = 3;
+
+/// Vendor class identifier (option 60) sent by PXE firmware
+pub const PXE_CLIENT_CLASS: &[u8] = b"PXEClient";
+
+/// Option 43 with PXE_DISCOVERY_CONTROL = 8: boot straight from the filename
+/// in the reply, without boot server discovery
+pub const PXE_BOOT_FILE_ONLY: &[u8] = &[6, 1, 8, 255];
+
+/// User class (option 77) sent by iPXE
+pub const IPXE_USER_CLASS: &[u8] = b"iPXE";
+
+/// Vendor class identifier (option 60) sent by UEFI HTTP Boot firmware
+pub const HTTP_CLIENT_CLASS: &[u8] = b"HTTPClient";
+
+/// Options sent whether or not the client lists them in option 55: the ones
+/// RFC 2131 requires, and the ones PXE and HTTP Boot firmware boot from
+const MANDATORY_OPTIONS: &[u8] = &[1, 43, 51, 53, 54, 58, 59, 60, 67, 82, 97];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// The options area ends in the middle of an option
+    Truncated(u8),
+    /// The option's length doesn't match its type
+    InvalidLength { code: u8, len: usize },
+    /// The option's value is out of range
+    InvalidValue(u8),
+    /// The options don't fit in the message, even with option overload
+    TooLong,
+    /// A configured value can't be put in an option
+    InvalidConfig(String),
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::Truncated(code) => write!(f, "option {} is truncated", code),
+            OptionError::InvalidLength { code, len } => {
+                write!(f, "option {} has invalid length {}", code, len)
+            }
+            OptionError::InvalidValue(code) => write!(f, "option {} has an invalid value", code),
+            OptionError::TooLong => write!(f, "options do not fit in the message"),
+            OptionError::InvalidConfig(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+/// A DHCP option (RFC 2132 and extensions), decoded from or encoded to its
+/// wire format. Options without a dedicated variant are kept as raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpOption {
+    SubnetMask(Ipv4Addr),
+    TimeOffset(i32),
+    Router(Vec<Ipv4Addr>),
+    TimeServer(Vec<Ipv4Addr>),
+    DomainNameServer(Vec<Ipv4Addr>),
+    HostName(String),
+    BootFileSize(u16),
+    DomainName(String),
+    RootPath(String),
+    InterfaceMtu(u16),
+    BroadcastAddress(Ipv4Addr),
+    NtpServers(Vec<Ipv4Addr>),
+    VendorSpecific(Vec<u8>),
+    RequestedIpAddress(Ipv4Addr),
+    LeaseTime(u32),
+    Overload(u8),
+    MessageType(u8),
+    ServerIdentifier(Ipv4Addr),
+    ParameterRequestList(Vec<u8>),
+    Message(String),
+    MaxMessageSize(u16),
+    RenewalTime(u32),
+    RebindingTime(u32),
+    VendorClassIdentifier(Vec<u8>),
+    ClientIdentifier(Vec<u8>),
+    TftpServerName(String),
+    BootfileName(String),
+    UserClass(Vec<u8>),
+    ClientFqdn(Vec<u8>),
+    RelayAgentInformation(Vec<u8>),
+    ClientArchitecture(Vec<u16>),
+    ClientMachineIdentifier(Vec<u8>),
+    Unknown(u8, Vec<u8>),
+}
+
+impl DhcpOption {
+    pub fn code(&self) -> u8 {
+        match self {
+            DhcpOption::SubnetMask(_) => 1,
+            DhcpOption::TimeOffset(_) => 2,
+            DhcpOption::Router(_) => 3,
+            DhcpOption::TimeServer(_) => 4,
+            DhcpOption::DomainNameServer(_) => 6,
+            DhcpOption::HostName(_) => 12,
+            DhcpOption::BootFileSize(_) => 13,
+            DhcpOption::DomainName(_) => 15,
+            DhcpOption::RootPath(_) => 17,
+            DhcpOption::InterfaceMtu(_) => 26,
+            DhcpOption::BroadcastAddress(_) => 28,
+            DhcpOption::NtpServers(_) => 42,
+            DhcpOption::VendorSpecific(_) => 43,
+            DhcpOption::RequestedIpAddress(_) => 50,
+            DhcpOption::LeaseTime(_) => 51,
+            DhcpOption::Overload(_) => OPTION_OVERLOAD,
+            DhcpOption::MessageType(_) => 53,
+            DhcpOption::ServerIdentifier(_) => 54,
+            DhcpOption::ParameterRequestList(_) => 55,
+            DhcpOption::Message(_) => 56,
+            DhcpOption::MaxMessageSize(_) => 57,
+            DhcpOption::RenewalTime(_) => 58,
+            DhcpOption::RebindingTime(_) => 59,
+            DhcpOption::VendorClassIdentifier(_) => 60,
+            DhcpOption::ClientIdentifier(_) => 61,
+            DhcpOption::TftpServerName(_) => 66,
+            DhcpOption::BootfileName(_) => 67,
+            DhcpOption::UserClass(_) => 77,
+            DhcpOption::ClientFqdn(_) => 81,
+            DhcpOption::RelayAgentInformation(_) => 82,
+            DhcpOption::ClientArchitecture(_) => 93,
+            DhcpOption::ClientMachineIdentifier(_) => 97,
+            DhcpOption::Unknown(code, _) => *code,
+        }
+    }
+
+    /// Decode the (concatenated) value of option `code`
+    pub fn decode(code: u8, data: &[u8]) -> Result<Self, OptionError> {
+        let invalid = || OptionError::InvalidLength {
+            code,
+            len: data.len(),
+        };
+        let ip = || -> Result<Ipv4Addr, OptionError> {
+            let octets: [u8; 4] = data.try_into().map_err(|_| invalid())?;
+            Ok(Ipv4Addr::from(octets))
+        };
+        let ips = || -> Result<Vec<Ipv4Addr>, OptionError> {
+            if data.is_empty() || !data.len().is_multiple_of(4) {
+                return Err(invalid());
+            }
+            Ok(data
+                .chunks(4)
+                .map(|c| Ipv4Addr::new(c[0], c[1], c[2], c[3]))
+                .collect())
+        };
+        let u16 = || -> Result<u16, OptionError> {
+            Ok(u16::from_be_bytes(data.try_into().map_err(|_| invalid())?))
+        };
+        let u32 = || -> Result<u32, OptionError> {
+            Ok(u32::from_be_bytes(data.try_into().map_err(|_| invalid())?))
+        };
+        let u8 = || -> Result<u8, OptionError> {
+            match data {
+                [value] => Ok(*value),
+                _ => Err(invalid()),
+            }
+        };
+        // Some clients NUL-terminate their strings
+        let string = || {
+            String::from_utf8_lossy(data)
+                .trim_end_matches('\0')
+                .to_string()
+        };
+
+        Ok(match code {
+            1 => DhcpOption::SubnetMask(ip()?),
+            2 => DhcpOption::TimeOffset(u32()? as i32),
+            3 => DhcpOption::Router(ips()?),
+            4 => DhcpOption::TimeServer(ips()?),
+            6 => DhcpOption::DomainNameServer(ips()?),
+            12 => DhcpOption::HostName(string()),
+            13 => DhcpOption::BootFileSize(u16()?),
+            15 => DhcpOption::DomainName(string()),
+            17 => DhcpOption::RootPath(string()),
+            26 => DhcpOption::InterfaceMtu(u16()?),
+            28 => DhcpOption::BroadcastAddress(ip()?),
+            42 => DhcpOption::NtpServers(ips()?),
+            43 => DhcpOption::VendorSpecific(data.to_vec()),
+            50 => DhcpOption::RequestedIpAddress(ip()?),
+            51 => DhcpOption::LeaseTime(u32()?),
+            OPTION_OVERLOAD => match u8()? {
+                value @ OVERLOAD_FILE..=OVERLOAD_BOTH => DhcpOption::Overload(value),
+                _ => return Err(OptionError::InvalidValue(code)),
+            },
+            53 => DhcpOption::MessageType(u8()?),
+            54 => DhcpOption::ServerIdentifier(ip()?),
+            55 => DhcpOption::ParameterRequestList(data.to_vec()),
+            56 => DhcpOption::Message(string()),
+            57 => match u16()? {
+                size if size >= 576 => DhcpOption::MaxMessageSize(size),
+                _ => return Err(OptionError::InvalidValue(code)),
+            },
+            58 => DhcpOption::RenewalTime(u32()?),
+            59 => DhcpOption::RebindingTime(u32()?),
+            60 => DhcpOption::VendorClassIdentifier(data.to_vec()),
+            61 => DhcpOption::ClientIdentifier(data.to_vec()),
+            66 => DhcpOption::TftpServerName(string()),
+            67 => DhcpOption::BootfileName(string()),
+            77 => DhcpOption::UserClass(data.to_vec()),
+            81 => DhcpOption::ClientFqdn(data.to_vec()),
+            82 => DhcpOption::RelayAgentInformation(data.to_vec()),
+            93 => {
+                if data.is_empty() || !data.len().is_multiple_of(2) {
+                    return Err(invalid());
+                }
+                DhcpOption::ClientArchitecture(
+                    data.chunks(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect(),
+                )
+            }
+            97 => DhcpOption::ClientMachineIdentifier(data.to_vec()),
+            _ => DhcpOption::Unknown(code, data.to_vec()),
+        })
+    }
+
+    /// Wire value of the option, without code and length
+    pub fn value(&self) -> Vec<u8> {
+        let ips = |ips: &[Ipv4Addr]| ips.iter().flat_map(|ip| ip.octets()).collect();
+        match self {
+            DhcpOption::SubnetMask(ip)
+            | DhcpOption::BroadcastAddress(ip)
+            | DhcpOption::RequestedIpAddress(ip)
+            | DhcpOption::ServerIdentifier(ip) => ip.octets().to_vec(),
+            DhcpOption::Router(list)
+            | DhcpOption::TimeServer(list)
+            | DhcpOption::DomainNameServer(list)
+            | DhcpOption::NtpServers(list) => ips(list),
+            DhcpOption::TimeOffset(offset) => offset.to_be_bytes().to_vec(),
+            DhcpOption::BootFileSize(value)
+            | DhcpOption::InterfaceMtu(value)
+            | DhcpOption::MaxMessageSize(value) => value.to_be_bytes().to_vec(),
+            DhcpOption::LeaseTime(value)
+            | DhcpOption::RenewalTime(value)
+            | DhcpOption::RebindingTime(value) => value.to_be_bytes().to_vec(),
+            DhcpOption::Overload(value) | DhcpOption::MessageType(value) => vec![*value],
+            DhcpOption::HostName(s)
+            | DhcpOption::DomainName(s)
+            | DhcpOption::RootPath(s)
+            | DhcpOption::Message(s)
+            | DhcpOption::TftpServerName(s)
+            | DhcpOption::BootfileName(s) => s.as_bytes().to_vec(),
+            DhcpOption::ClientArchitecture(arches) => {
+                arches.iter().flat_map(|arch| arch.to_be_bytes()).collect()
+            }
+            DhcpOption::VendorSpecific(data)
+            | DhcpOption::ParameterRequestList(data)
+            | DhcpOption::VendorClassIdentifier(data)
+            | DhcpOption::ClientIdentifier(data)
+            | DhcpOption::UserClass(data)
+            | DhcpOption::ClientFqdn(data)
+            | DhcpOption::RelayAgentInformation(data)
+            | DhcpOption::ClientMachineIdentifier(data)
+            | DhcpOption::Unknown(_, data) => data.clone(),
+        }
+    }
+
+    /// Append the option to `out`. Values longer than 255 bytes are split
+    /// over several instances of the option (RFC 3396).
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let code = self.code();
+        let value = self.value();
+        if value.is_empty() {
+            out.extend_from_slice(&[code, 0]);
+            return;
+        }
+        for chunk in value.chunks(255) {
+            out.push(code);
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+}
+
+/// Encode a list of options, followed by the end option
+pub fn encode_options(options: &[DhcpOption]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for option in options {
+        option.encode(&mut out);
+    }
+    out.push(OPTION_END);
+    out
+}
+
+/// Iterator over the raw `(code, value)` pairs of an options area. Pad
+/// options are skipped and iteration stops at the end option.
+pub struct RawOptions<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+pub fn raw_options(data: &[u8]) -> RawOptions<'_> {
+    RawOptions { data, pos: 0 }
+}
+
+impl<'a> Iterator for RawOptions<'a> {
+    type Item = Result<(u8, &'a [u8]), OptionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let code = *self.data.get(self.pos)?;
+            match code {
+                OPTION_PAD => self.pos += 1,
+                OPTION_END => {
+                    self.pos = self.data.len();
+                    return None;
+                }
+                _ => break,
+            }
+        }
+
+        let code = self.data[self.pos];
+        let value = self
+            .data
+            .get(self.pos + 1)
+            .and_then(|&len| self.data.get(self.pos + 2..self.pos + 2 + len as usize));
+        match value {
+            Some(value) => {
+                self.pos += 2 + value.len();
+                Some(Ok((code, value)))
+            }
+            None => {
+                self.pos = self.data.len();
+                Some(Err(OptionError::Truncated(code)))
+            }
+        }
+    }
+}
+
+/// Decode all options of an options area. Options that appear several times
+/// are concatenated into one, in order (RFC 3396).
+pub fn decode_options(data: &[u8]) -> Result<Vec<DhcpOption>, OptionError> {
+    let mut values: Vec<(u8, Vec<u8>)> = Vec::new();
+    for option in raw_options(data) {
+        let (code, value) = option?;
+        match values.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, existing)) => existing.extend_from_slice(value),
+            None => values.push((code, value.to_vec())),
+        }
+    }
+    values
+        .into_iter()
+        .map(|(code, value)| DhcpOption::decode(code, &value))
+        .collect()
+}
+
+/// Parse a custom option from the configuration
+pub fn custom_option(config: &DhcpOptionConfig) -> Result<DhcpOption, OptionError> {
+    let code = config.code;
+    let invalid = || {
+        OptionError::InvalidConfig(format!(
+            "Invalid value for option {} of type {:?}",
+            code, config.kind
+        ))
+    };
+    if matches!(code, OPTION_PAD | OPTION_OVERLOAD | 53 | OPTION_END) {
+        return Err(OptionError::InvalidConfig(format!(
+            "Option {} cannot be configured",
+            code
+        )));
+    }
+
+    // Strings and lists; a single string may hold a comma separated list
+    let items: Vec<&str> = match config.value {
+        DhcpOptionValue::String(ref s) => s.split(',').map(str::trim).collect(),
+        DhcpOptionValue::List(ref list) => list.iter().map(|s| s.trim()).collect(),
+        DhcpOptionValue::Integer(_) => Vec::new(),
+    };
+    let ip = |s: &str| s.parse::<Ipv4Addr>().map_err(|_| invalid());
+    let integer = |max: u64| match config.value {
+        DhcpOptionValue::Integer(value) if value <= max => Ok(value),
+        DhcpOptionValue::String(ref s) => s
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value <= max)
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    };
+
+    let data = match config.kind {
+        DhcpOptionType::Ip => match items[..] {
+            [single] => ip(single)?.octets().to_vec(),
+            _ => return Err(invalid()),
+        },
+        DhcpOptionType::IpList if !items.is_empty() => items
+            .iter()
+            .map(|s| ip(s).map(|ip| ip.octets()))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat(),
+        DhcpOptionType::String => match config.value {
+            DhcpOptionValue::String(ref s) => s.as_bytes().to_vec(),
+            _ => return Err(invalid()),
+        },
+        DhcpOptionType::U8 => vec![integer(u8::MAX as u64)? as u8],
+        DhcpOptionType::U16 => (integer(u16::MAX as u64)? as u16).to_be_bytes().to_vec(),
+        DhcpOptionType::U32 => (integer(u32::MAX as u64)? as u32).to_be_bytes().to_vec(),
+        DhcpOptionType::Hex => match config.value {
+            DhcpOptionValue::String(ref s) => parse_hex(s).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        },
+        DhcpOptionType::Routes if !items.is_empty() => {
+            let mut data = Vec::new();
+            for route in &items {
+                encode_route(route, &mut data).ok_or_else(invalid)?;
+            }
+            data
+        }
+        DhcpOptionType::DomainList if !items.is_empty() => {
+            let mut data = Vec::new();
+            for domain in &items {
+                encode_domain(domain, &mut data).ok_or_else(invalid)?;
+            }
+            data
+        }
+        _ => return Err(invalid()),
+    };
+
+    DhcpOption::decode(code, &data)
+}
+
+/// Encode a classless static route, "10.0.0.0/8 via 192.168.1.1" (RFC 3442)
+fn encode_route(route: &str, out: &mut Vec<u8>) -> Option<()> {
+    let (destination, router) = route.split_once(" via ")?;
+    let (network, prefix) = destination.trim().split_once('/')?;
+    let network: Ipv4Addr = network.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+    let router: Ipv4Addr = router.trim().parse().ok()?;
+
+    out.push(prefix);
+    out.extend_from_slice(&network.octets()[..prefix.div_ceil(8) as usize]);
+    out.extend_from_slice(&router.octets());
+    Some(())
+}
+
+/// Encode a domain name as a sequence of DNS labels (RFC 1035)
+pub(crate) fn encode_domain(domain: &str, out: &mut Vec<u8>) -> Option<()> {
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    Some(())
+}
+
+/// Parse a hex string, ignoring `:` and `-` separators
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| *b != b':' && *b != b'-').collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Drop the DHCP extensions (RFC 2132, section 9) from a reply to a BOOTP
+/// client, and the options that don't fit its 64 byte vendor area next to
+/// the `reserved` bytes of options appended later
+pub fn bootp_options(options: &mut Vec<DhcpOption>, reserved: usize) {
+    // Magic cookie and end option
+    let mut room = (64 - 4 - 1usize).saturating_sub(reserved);
+    options.retain(|o| {
+        let mut data = Vec::new();
+        o.encode(&mut data);
+        let keep = !(50..=61).contains(&o.code()) && data.len() <= room;
+        if keep {
+            room -= data.len();
+        }
+        keep
+    });
+}
+
+/// Drop the options the client didn't ask for in its parameter request list
+/// (option 55) and put the rest in the order it asked for them. The message
+/// type always comes first, unrequested mandatory options last.
+pub fn order_options(options: &mut Vec<DhcpOption>, requested: &[u8]) {
+    options.retain(|o| MANDATORY_OPTIONS.contains(&o.code()) || requested.contains(&o.code()));
+    options.sort_by_key(|o| match o.code() {
+        53 => 0,
+        code => requested
+            .iter()
+            .position(|r| *r == code)
+            .map_or(usize::MAX, |position| position + 1),
+    });
+}
+
+/// Add options to a list, replacing options with the same code
+pub fn merge_options<I>(options: &mut Vec<DhcpOption>, overrides: I)
+where
+    I: IntoIterator<Item = DhcpOption>,
+{
+    for option in overrides {
+        match options.iter_mut().find(|o| o.code() == option.code()) {
+            Some(existing) => *existing = option,
+            None => options.push(option),
+        }
+    }
+}
+
 pub struct DhcpOptions;
 
 impl DhcpOptions {
-    pub fn build_options(config: &DhcpConfig, _client_ip: Ipv4Addr, msg_type: u8) -> Vec<u8> {
+    /// Lease time to grant: the client's request (option 51) capped at the
+    /// configured maximum, or the configured default
+    pub fn lease_time(config: &DhcpConfig, requested: Option<u32>) -> u32 {
+        match requested {
+            Some(requested) if requested > 0 => requested.min(config.max_lease_time),
+            _ => config.default_lease_time,
+        }
+    }
+
+    /// Address we identify ourselves with in option 54
+    pub fn server_id(config: &DhcpConfig) -> Option<Ipv4Addr> {
+        parse_ip(&config.next_server, "next_server").ok()
+    }
+
+    /// Check that the addresses handed out in every reply can be encoded
+    pub fn check_config(config: &DhcpConfig) -> Result<(), OptionError> {
+        Self::build_options(config, DHCP_ACK, None).map(|_| ())
+    }
+
+    /// Build the options of a reply. `lease_time` is omitted for DHCPINFORM
+    /// replies, which carry configuration only.
+    pub fn build_options(
+        config: &DhcpConfig,
+        msg_type: u8,
+        lease_time: Option<u32>,
+    ) -> Result<Vec<DhcpOption>, OptionError> {
         let mut options = vec![
-            // Message type: DHCP Offer (2) or ACK (5)
-            53, // DHCP Message Type
-            1, msg_type, 1, // Subnet Mask
-            4,
+            DhcpOption::MessageType(msg_type),
+            DhcpOption::SubnetMask(parse_ip(&config.subnet_mask, "subnet_mask")?),
         ];
 
-        let mask = parse_ip(&config.subnet_mask).unwrap();
-        options.extend_from_slice(&mask.octets());
-
         // Router (gateway)
         if let Some(ref gateway) = config.gateway {
-            if let Ok(gw_ip) = gateway.parse::<Ipv4Addr>() {
-                options.push(3); // Router
-                options.push(4);
-                options.extend_from_slice(&gw_ip.octets());
-            }
+            options.push(DhcpOption::Router(vec![parse_ip(gateway, "gateway")?]));
         }
 
         // DNS servers
         if !config.dns_servers.is_empty() {
-            options.push(6); // DNS Servers
-            options.push((config.dns_servers.len() * 4) as u8);
-            for dns in &config.dns_servers {
-                if let Ok(dns_ip) = dns.parse::<Ipv4Addr>() {
-                    options.extend_from_slice(&dns_ip.octets());
-                }
+            let dns_servers = config
+                .dns_servers
+                .iter()
+                .map(|dns| parse_ip(dns, "dns_servers"))
+                .collect::<Result<_, _>>()?;
+            options.push(DhcpOption::DomainNameServer(dns_servers));
+        }
+
+        if let Some(lease_time) = lease_time {
+            options.push(DhcpOption::LeaseTime(lease_time));
+
+            // Renewal (T1) and Rebinding (T2) times, at 50% and 87.5% of the lease
+            if lease_time != u32::MAX {
+                let t1 = lease_time / 2;
+                let t2 = (lease_time as u64 * 7 / 8) as u32;
+                options.push(DhcpOption::RenewalTime(t1));
+                options.push(DhcpOption::RebindingTime(t2));
             }
         }
 
-        // IP Address Lease Time (1 hour)
-        options.push(51); // IP Address Lease Time
-        options.push(4);
-        let lease_time: u32 = 3600;
-        options.extend_from_slice(&lease_time.to_be_bytes());
+        if let Some(ref domain_name) = config.domain_name {
+            options.push(DhcpOption::DomainName(domain_name.clone()));
+        }
 
         // Server Identifier (next-server)
-        options.push(54); // Server Identifier
-        options.push(4);
-        if let Ok(server_ip) = config.next_server.parse::<Ipv4Addr>() {
-            options.extend_from_slice(&server_ip.octets());
+        options.push(DhcpOption::ServerIdentifier(parse_ip(
+            &config.next_server,
+            "next_server",
+        )?));
+
+        // Custom options, which may replace the ones above
+        let custom = config
+            .options
+            .iter()
+            .map(custom_option)
+            .collect::<Result<Vec<_>, _>>()?;
+        merge_options(&mut options, custom);
+
+        Ok(options)
+    }
+
+    /// Options for proxyDHCP and PXE boot server replies: no addressing
+    /// information, just enough for the PXE ROM to recognise a boot server.
+    /// `vendor` is the content of option 43, including its end marker.
+    pub fn build_proxy_options(
+        config: &DhcpConfig,
+        msg_type: u8,
+        client_uuid: Option<&[u8]>,
+        vendor: &[u8],
+    ) -> Vec<DhcpOption> {
+        let mut options = vec![DhcpOption::MessageType(msg_type)];
+
+        if let Some(server_ip) = Self::server_id(config) {
+            options.push(DhcpOption::ServerIdentifier(server_ip));
         }
+        options.push(DhcpOption::VendorClassIdentifier(PXE_CLIENT_CLASS.to_vec()));
 
-        // End option
-        options.push(255);
+        // Client Machine Identifier, echoed back as required by the PXE spec
+        if let Some(uuid) = client_uuid {
+            options.push(DhcpOption::ClientMachineIdentifier(uuid.to_vec()));
+        }
 
+        options.push(DhcpOption::VendorSpecific(vendor.to_vec()));
         options
     }
 
-    pub fn build_filename_option(filename: &str) -> Vec<u8> {
-        let mut options = Vec::new();
-        options.push(67); // Bootfile Name
-        options.push(filename.len() as u8);
-        options.extend_from_slice(filename.as_bytes());
-        options.push(255); // End
+    pub fn build_nak_options(config: &DhcpConfig, message: &str) -> Vec<DhcpOption> {
+        let mut options = vec![DhcpOption::MessageType(DHCP_NAK)];
+        if let Some(server_ip) = Self::server_id(config) {
+            options.push(DhcpOption::ServerIdentifier(server_ip));
+        }
+        options.push(DhcpOption::Message(message.to_string()));
         options
     }
 }
 
-fn parse_ip(ip_str: &str) -> Result<Ipv4Addr, std::net::AddrParseError> {
-    ip_str.parse::<Ipv4Addr>()
+fn parse_ip(ip_str: &str, field: &str) -> Result<Ipv4Addr, OptionError> {
+    ip_str
+        .parse::<Ipv4Addr>()
+        .map_err(|_| OptionError::InvalidConfig(format!("Invalid {}: {}", field, ip_str)))
 }
 
 #[cfg(test)]
@@ -76,10 +656,226 @@ mod tests {
     #[test]
     fn test_build_options() {
         let config = Config::default();
-        let client_ip = "192.168.1.100".parse().unwrap();
-        let options = DhcpOptions::build_options(&config.dhcp, client_ip, 2); // Offer
+        let options = DhcpOptions::build_options(&config.dhcp, DHCP_OFFER, Some(3600)).unwrap();
 
         assert!(!options.is_empty());
-        assert_eq!(options[0], 53); // Message Type
+        assert_eq!(options[0], DhcpOption::MessageType(DHCP_OFFER));
+    }
+
+    #[test]
+    fn test_build_options_invalid_config() {
+        let mut config = Config::default();
+        config.dhcp.subnet_mask = "255.255.255".to_string();
+        assert!(DhcpOptions::build_options(&config.dhcp, DHCP_OFFER, None).is_err());
+
+        let mut config = Config::default();
+        config.dhcp.dns_servers = vec!["8.8.8.8".to_string(), "dns.example".to_string()];
+        assert!(DhcpOptions::check_config(&config.dhcp).is_err());
+    }
+
+    #[test]
+    fn test_lease_time() {
+        let config = Config::default();
+        assert_eq!(DhcpOptions::lease_time(&config.dhcp, None), 3600);
+        assert_eq!(DhcpOptions::lease_time(&config.dhcp, Some(600)), 600);
+        assert_eq!(DhcpOptions::lease_time(&config.dhcp, Some(u32::MAX)), 86400);
+    }
+
+    #[test]
+    fn test_renewal_timers() {
+        let config = Config::default();
+        let options = DhcpOptions::build_options(&config.dhcp, DHCP_ACK, Some(1000)).unwrap();
+
+        assert!(options.contains(&DhcpOption::LeaseTime(1000)));
+        assert!(options.contains(&DhcpOption::RenewalTime(500)));
+        assert!(options.contains(&DhcpOption::RebindingTime(875)));
+
+        // DHCPINFORM replies carry no lease information
+        let options = DhcpOptions::build_options(&config.dhcp, DHCP_ACK, None).unwrap();
+        assert!(!options
+            .iter()
+            .any(|o| matches!(o, DhcpOption::LeaseTime(_))));
+    }
+
+    fn custom(code: u8, kind: DhcpOptionType, value: DhcpOptionValue) -> DhcpOptionConfig {
+        DhcpOptionConfig { code, kind, value }
+    }
+
+    fn string(s: &str) -> DhcpOptionValue {
+        DhcpOptionValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_custom_options() {
+        let option = |code, kind, value| custom_option(&custom(code, kind, value)).unwrap();
+
+        assert_eq!(
+            option(42, DhcpOptionType::IpList, string("10.0.0.1, 10.0.0.2")),
+            DhcpOption::NtpServers(vec![
+                "10.0.0.1".parse().unwrap(),
+                "10.0.0.2".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            option(15, DhcpOptionType::String, string("lab.example")),
+            DhcpOption::DomainName("lab.example".to_string())
+        );
+        assert_eq!(
+            option(26, DhcpOptionType::U16, DhcpOptionValue::Integer(9000)),
+            DhcpOption::InterfaceMtu(9000)
+        );
+        assert_eq!(
+            option(150, DhcpOptionType::Ip, string("10.0.0.5")),
+            DhcpOption::Unknown(150, vec![10, 0, 0, 5])
+        );
+        assert_eq!(
+            option(224, DhcpOptionType::Hex, string("01:02:ff")),
+            DhcpOption::Unknown(224, vec![1, 2, 0xff])
+        );
+        assert_eq!(
+            option(
+                121,
+                DhcpOptionType::Routes,
+                DhcpOptionValue::List(vec![
+                    "10.0.0.0/8 via 192.168.1.1".to_string(),
+                    "0.0.0.0/0 via 192.168.1.254".to_string(),
+                ])
+            ),
+            DhcpOption::Unknown(121, vec![8, 10, 192, 168, 1, 1, 0, 192, 168, 1, 254])
+        );
+        let mut search = vec![3];
+        search.extend_from_slice(b"lab");
+        search.push(7);
+        search.extend_from_slice(b"example");
+        search.push(0);
+        assert_eq!(
+            option(119, DhcpOptionType::DomainList, string("lab.example")),
+            DhcpOption::Unknown(119, search)
+        );
+    }
+
+    #[test]
+    fn test_invalid_custom_options() {
+        let invalid = |code, kind, value| custom_option(&custom(code, kind, value)).is_err();
+
+        assert!(invalid(3, DhcpOptionType::Ip, string("router")));
+        assert!(invalid(
+            23,
+            DhcpOptionType::U8,
+            DhcpOptionValue::Integer(256)
+        ));
+        assert!(invalid(1, DhcpOptionType::String, string("255.255.255.0")));
+        assert!(invalid(53, DhcpOptionType::U8, DhcpOptionValue::Integer(5)));
+        assert!(invalid(
+            121,
+            DhcpOptionType::Routes,
+            string("10.0.0.0/33 via 10.0.0.1")
+        ));
+        assert!(invalid(224, DhcpOptionType::Hex, string("abc")));
+    }
+
+    #[test]
+    fn test_custom_options_override() {
+        let mut config = Config::default();
+        config.dhcp.options = vec![
+            custom(6, DhcpOptionType::IpList, string("10.0.0.53")),
+            custom(42, DhcpOptionType::Ip, string("10.0.0.123")),
+        ];
+        let options = DhcpOptions::build_options(&config.dhcp, DHCP_ACK, None).unwrap();
+
+        let dns: Vec<_> = options.iter().filter(|o| o.code() == 6).collect();
+        assert_eq!(
+            dns,
+            vec![&DhcpOption::DomainNameServer(vec!["10.0.0.53"
+                .parse()
+                .unwrap()])]
+        );
+        assert!(options.contains(&DhcpOption::NtpServers(vec!["10.0.0.123".parse().unwrap()])));
+    }
+
+    #[test]
+    fn test_order_options() {
+        let mut config = Config::default();
+        config.dhcp.gateway = Some("192.168.1.1".to_string());
+        config.dhcp.dns_servers = vec!["192.168.1.1".to_string()];
+        let mut options = DhcpOptions::build_options(&config.dhcp, DHCP_ACK, Some(3600)).unwrap();
+        options.push(DhcpOption::HostName("node1".to_string()));
+
+        order_options(&mut options, &[6, 3, 12, 1]);
+        let codes: Vec<u8> = options.iter().map(DhcpOption::code).collect();
+        assert_eq!(codes, vec![53, 6, 3, 12, 1, 51, 58, 59, 54]);
+
+        // Nothing requested: only the mandatory options are left
+        order_options(&mut options, &[]);
+        let codes: Vec<u8> = options.iter().map(DhcpOption::code).collect();
+        assert_eq!(codes, vec![53, 1, 51, 58, 59, 54]);
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("01:00:11"), Some(vec![0x01, 0x00, 0x11]));
+        assert_eq!(parse_hex("0a0B"), Some(vec![0x0a, 0x0b]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_option_roundtrip() {
+        let options = vec![
+            DhcpOption::MessageType(DHCP_OFFER),
+            DhcpOption::SubnetMask("255.255.255.0".parse().unwrap()),
+            DhcpOption::Router(vec!["10.0.0.1".parse().unwrap()]),
+            DhcpOption::DomainNameServer(vec![
+                "10.0.0.2".parse().unwrap(),
+                "10.0.0.3".parse().unwrap(),
+            ]),
+            DhcpOption::HostName("node1".to_string()),
+            DhcpOption::TimeOffset(-3600),
+            DhcpOption::LeaseTime(3600),
+            DhcpOption::MaxMessageSize(1500),
+            DhcpOption::ClientArchitecture(vec![7]),
+            DhcpOption::Unknown(224, vec![1, 2, 3]),
+        ];
+        let encoded = encode_options(&options);
+        assert_eq!(&encoded[..3], &[53, 1, DHCP_OFFER]);
+        assert_eq!(decode_options(&encoded).unwrap(), options);
+    }
+
+    #[test]
+    fn test_long_option_split() {
+        let long = DhcpOption::VendorSpecific(vec![0xab; 600]);
+        let encoded = encode_options(std::slice::from_ref(&long));
+
+        let parts: Vec<_> = raw_options(&encoded).map(Result::unwrap).collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|(code, _)| *code == 43));
+        assert_eq!(parts[0].1.len(), 255);
+        assert_eq!(parts[2].1.len(), 90);
+
+        assert_eq!(decode_options(&encoded).unwrap(), vec![long]);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // Pad options are skipped
+        let options = [0, 0, 53, 1, DHCP_DISCOVER, 0, 255, 12, 3];
+        assert_eq!(
+            decode_options(&options).unwrap(),
+            vec![DhcpOption::MessageType(DHCP_DISCOVER)]
+        );
+
+        assert_eq!(
+            decode_options(&[53, 1, 1, 12, 5, b'a']),
+            Err(OptionError::Truncated(12))
+        );
+        assert_eq!(
+            decode_options(&[1, 3, 255, 255, 255]),
+            Err(OptionError::InvalidLength { code: 1, len: 3 })
+        );
+        assert_eq!(
+            decode_options(&[52, 1, 4]),
+            Err(OptionError::InvalidValue(52))
+        );
+        assert!(decode_options(&[57, 2, 0x01, 0x00]).is_err());
     }
 }
@@ -0,0 +1,177 @@
+//! IP_PKTINFO support, so a single socket bound to 0.0.0.0 can tell which
+//! interface a request arrived on and send the reply back out of it.
+
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+/// Ask the kernel to attach the receiving interface to every datagram
+pub fn enable(socket: &socket2::Socket) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive a datagram along with the index of the interface it arrived on
+pub async fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    socket
+        .async_io(Interest::READABLE, || recvmsg(socket.as_raw_fd(), buf))
+        .await
+}
+
+/// Send a datagram out of the interface with index `ifindex`, which also
+/// selects the interface for limited broadcasts (255.255.255.255)
+pub async fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    dest: SocketAddrV4,
+    ifindex: u32,
+) -> io::Result<usize> {
+    socket
+        .async_io(Interest::WRITABLE, || {
+            sendmsg(socket.as_raw_fd(), buf, dest, ifindex)
+        })
+        .await
+}
+
+fn recvmsg(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control = [0u64; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let size = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut ifindex = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info =
+                    std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                ifindex = Some(info.ipi_ifindex as u32);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let peer = SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        u16::from_be(addr.sin_port),
+    );
+    Ok((size as usize, SocketAddr::V4(peer), ifindex))
+}
+
+fn sendmsg(fd: RawFd, buf: &[u8], dest: SocketAddrV4, ifindex: u32) -> io::Result<usize> {
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = dest.port().to_be();
+    addr.sin_addr.s_addr = u32::from(*dest.ip()).to_be();
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control = [0u64; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen =
+        unsafe { libc::CMSG_SPACE(mem::size_of::<libc::in_pktinfo>() as u32) } as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::in_pktinfo>() as u32) as _;
+        let info = libc::in_pktinfo {
+            ipi_ifindex: ifindex as libc::c_int,
+            ipi_spec_dst: libc::in_addr { s_addr: 0 },
+            ipi_addr: libc::in_addr { s_addr: 0 },
+        };
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, info);
+    }
+
+    let size = unsafe { libc::sendmsg(fd, &msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::server::interface_index;
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    #[tokio::test]
+    async fn test_receiving_interface() {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        enable(&socket).unwrap();
+        socket
+            .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+            .unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let server = UdpSocket::from_std(std::net::UdpSocket::from(socket)).unwrap();
+        let server_addr = match server.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+
+        let loopback = interface_index("lo").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+        client.send_to(b"ping", server_addr).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (size, peer, ifindex) = recv_from(&server, &mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"ping");
+        assert_eq!(peer, client_addr);
+        assert_eq!(ifindex, Some(loopback));
+
+        let client_addr = match client_addr {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        send_to(&server, b"pong", client_addr, loopback)
+            .await
+            .unwrap();
+        let (size, _) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"pong");
+    }
+}
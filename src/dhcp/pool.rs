@@ -0,0 +1,512 @@
+//! Dynamic address allocation. Addresses are tracked by their offset in the
+//! pool's range, in bitmaps, so finding a free address skips 64 taken ones at
+//! a time whatever the size of the pool.
+
+use crate::dhcp::leases::{self, format_mac, Lease, LeaseChange, LeaseState, LeaseTable, Leases};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tracing as log;
+
+/// How long an offered address is held for a client before it may be reused
+pub const OFFER_HOLD_TIME: u32 = 60;
+
+/// How long an address declined by a client is kept out of the pool
+pub const DECLINE_HOLD_TIME: u32 = 3600;
+
+/// Largest pool, a /8
+const MAX_POOL_SIZE: u64 = 1 << 24;
+
+/// A set of addresses out of a range, as a bitmap
+#[derive(Debug, Clone)]
+pub struct AddressSet {
+    start: u32,
+    len: u32,
+    words: Vec<u64>,
+}
+
+impl AddressSet {
+    /// An empty set over `start..=end`, at most `MAX_POOL_SIZE` addresses
+    fn new(start: Ipv4Addr, end: Ipv4Addr) -> Self {
+        let len = u32::from(end) - u32::from(start) + 1;
+        AddressSet {
+            start: u32::from(start),
+            len,
+            words: vec![0; len.div_ceil(64) as usize],
+        }
+    }
+
+    fn offset(&self, ip: Ipv4Addr) -> Option<u32> {
+        let offset = u32::from(ip).wrapping_sub(self.start);
+        (offset < self.len).then_some(offset)
+    }
+
+    fn address(&self, offset: u32) -> Ipv4Addr {
+        Ipv4Addr::from(self.start + offset)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        self.offset(ip)
+            .is_some_and(|offset| self.words[offset as usize / 64] & (1 << (offset % 64)) != 0)
+    }
+
+    /// Add an address, ignoring addresses out of the range
+    pub fn insert(&mut self, ip: Ipv4Addr) {
+        if let Some(offset) = self.offset(ip) {
+            self.words[offset as usize / 64] |= 1 << (offset % 64);
+        }
+    }
+
+    pub fn remove(&mut self, ip: Ipv4Addr) {
+        if let Some(offset) = self.offset(ip) {
+            self.words[offset as usize / 64] &= !(1 << (offset % 64));
+        }
+    }
+
+    /// Add the addresses of `start..=end` that are in the range
+    pub fn insert_range(&mut self, start: Ipv4Addr, end: Ipv4Addr) {
+        let first = u32::from(start).max(self.start);
+        let last = u32::from(end).min(self.start + (self.len - 1));
+        for ip in first..=last {
+            self.insert(Ipv4Addr::from(ip));
+        }
+    }
+
+    /// First offset from `from` on, wrapping around, that is in neither this
+    /// set nor `other`, which covers the same range
+    fn next_clear(&self, other: &AddressSet, from: u32) -> Option<u32> {
+        let words = self.words.len();
+        let first = from as usize / 64;
+        // The first word is visited twice: its bits from `from` on, and
+        // after wrapping around, the bits before
+        for i in 0..=words {
+            let index = (first + i) % words;
+            let mut taken = self.words[index] | other.words[index];
+            if i == 0 {
+                taken |= (1u64 << (from % 64)) - 1;
+            }
+            if index == words - 1 && !self.len.is_multiple_of(64) {
+                // Bits past the end of the range
+                taken |= !0u64 << (self.len % 64);
+            }
+            if taken != !0 {
+                return Some(index as u32 * 64 + (!taken).trailing_zeros());
+            }
+        }
+        None
+    }
+}
+
+/// Allocation state of a pool, kept with the leases under the lease table's
+/// lock
+#[derive(Debug)]
+pub struct PoolState {
+    /// Addresses held by a lease, expired or not
+    pub(crate) held: AddressSet,
+    /// Addresses kept out of circulation, until when
+    quarantined: HashMap<Ipv4Addr, u64>,
+    /// Offset the search for a free address starts from
+    next: u32,
+}
+
+impl PoolState {
+    pub(crate) fn new(start: Ipv4Addr, end: Ipv4Addr) -> Self {
+        PoolState {
+            held: AddressSet::new(start, end),
+            quarantined: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    fn is_quarantined(&mut self, ip: Ipv4Addr, now: u64) -> bool {
+        match self.quarantined.get(&ip) {
+            Some(&until) if until > now => true,
+            Some(_) => {
+                self.quarantined.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// An address held by no lease, excluded or quarantined
+    fn find_free(&mut self, excluded: &AddressSet, now: u64) -> Option<Ipv4Addr> {
+        let mut from = self.next;
+        let mut skipped = 0;
+        while let Some(offset) = self.held.next_clear(excluded, from) {
+            let ip = self.held.address(offset);
+            if !self.is_quarantined(ip, now) {
+                self.next = (offset + 1) % self.held.len;
+                return Some(ip);
+            }
+            // Every free address may be quarantined
+            skipped += 1;
+            if skipped > self.quarantined.len() {
+                break;
+            }
+            from = (offset + 1) % self.held.len;
+        }
+        None
+    }
+}
+
+/// Addresses handed out from `start..=end`, out of a lease table that may be
+/// shared with other pools
+pub struct IpPool {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+    /// Index of the pool's state in the lease table
+    id: usize,
+    leases: Arc<LeaseTable>,
+    excluded: AddressSet,
+}
+
+impl IpPool {
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr, leases: Arc<LeaseTable>) -> Result<Self, String> {
+        if start > end {
+            return Err(format!("Address pool {}-{} is empty", start, end));
+        }
+        if (u32::from(end) - u32::from(start)) as u64 >= MAX_POOL_SIZE {
+            return Err(format!(
+                "Address pool {}-{} is larger than a /8",
+                start, end
+            ));
+        }
+
+        Ok(IpPool {
+            start,
+            end,
+            id: leases.add_pool(start, end),
+            leases,
+            excluded: AddressSet::new(start, end),
+        })
+    }
+
+    /// Keep `ip` out of dynamic allocation
+    pub fn exclude(&mut self, ip: Ipv4Addr) {
+        self.excluded.insert(ip);
+    }
+
+    /// Keep `start..=end` out of dynamic allocation
+    pub fn exclude_range(&mut self, start: Ipv4Addr, end: Ipv4Addr) {
+        self.excluded.insert_range(start, end);
+    }
+
+    /// Address for the client, held for it for a while, and the expired
+    /// lease of another client the address was taken back from, if any
+    pub fn allocate(&self, mac: [u8; 6]) -> Option<(Ipv4Addr, Option<Lease>)> {
+        let mut leases = self.leases.lock();
+
+        // Check if MAC already has a lease
+        if let Some(lease) = leases.get(&mac) {
+            if self.contains(lease.ip) && !self.excluded.contains(lease.ip) {
+                return Some((lease.ip, None));
+            }
+            // Client moved to another network, or the address was reserved
+            // for another host since: start over
+            leases.remove(&mac);
+        }
+
+        let now = leases::now();
+        let (ip, reclaimed) = match leases.pool(self.id).find_free(&self.excluded, now) {
+            Some(ip) => (ip, None),
+            None => {
+                let lease = self.reclaim(&mut leases, now)?;
+                (lease.ip, Some(lease))
+            }
+        };
+        leases.insert(Lease::new(mac, ip, LeaseState::Offered, OFFER_HOLD_TIME));
+        Some((ip, reclaimed))
+    }
+
+    /// Take back an expired lease. Only done once no address is free, so
+    /// returning clients find theirs for as long as possible.
+    fn reclaim(&self, leases: &mut Leases, now: u64) -> Option<Lease> {
+        let len = u32::from(self.end) - u32::from(self.start) + 1;
+        let next = leases.pool(self.id).next;
+        for i in 0..len {
+            let offset = (next + i) % len;
+            let ip = Ipv4Addr::from(u32::from(self.start) + offset);
+            let Some(old) = leases
+                .holder(ip)
+                .filter(|lease| lease.is_expired(now))
+                .map(|lease| lease.mac)
+            else {
+                continue;
+            };
+            if self.excluded.contains(ip) || leases.pool(self.id).is_quarantined(ip, now) {
+                continue;
+            }
+
+            log::info!(
+                "Reclaiming expired lease {} from MAC: {}",
+                ip,
+                format_mac(&old)
+            );
+            leases.pool(self.id).next = (offset + 1) % len;
+            return leases.remove(&old);
+        }
+        None
+    }
+
+    /// Update the lease held by `mac` and write the lease table to disk
+    pub fn update<F: FnOnce(&mut Lease)>(&self, mac: [u8; 6], f: F) {
+        let mut leases = self.leases.lock();
+        if leases.update(&mac, f) {
+            let change = match leases.get(&mac) {
+                Some(lease) => LeaseChange::Lease(lease.clone()),
+                None => LeaseChange::Remove(format_mac(&mac)),
+            };
+            self.leases.commit(leases, change);
+        }
+    }
+
+    /// Give `mac` a fixed address, taking it over from any other lease
+    pub fn assign(&self, mac: [u8; 6], ip: Ipv4Addr) -> Lease {
+        let mut leases = self.leases.lock();
+        if let Some(lease) = leases.get(&mac).filter(|lease| lease.ip == ip) {
+            return lease.clone();
+        }
+
+        let lease = Lease::new(mac, ip, LeaseState::Offered, OFFER_HOLD_TIME);
+        leases.insert(lease.clone());
+        self.leases
+            .commit(leases, LeaseChange::Lease(lease.clone()));
+        lease
+    }
+
+    /// The client's lease, if it is for an address in this pool
+    pub fn lease(&self, mac: [u8; 6]) -> Option<Lease> {
+        self.leases
+            .lock()
+            .get(&mac)
+            .filter(|lease| self.contains(lease.ip))
+            .cloned()
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        ip >= self.start && ip <= self.end
+    }
+
+    /// Free the lease held by `mac`. When `ip` is specified it must match the
+    /// leased address, so a stray RELEASE can't free someone else's binding.
+    pub fn release(&self, mac: [u8; 6], ip: Ipv4Addr) -> bool {
+        let mut leases = self.leases.lock();
+        match leases.get(&mac) {
+            Some(lease) if ip.is_unspecified() || lease.ip == ip => {
+                leases.remove(&mac);
+                self.leases
+                    .commit(leases, LeaseChange::Remove(format_mac(&mac)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop the client's lease on `ip` and keep the address out of circulation
+    /// for a while, since another host on the network is using it. Returns
+    /// the dropped lease.
+    pub fn decline(&self, mac: [u8; 6], ip: Ipv4Addr) -> Option<Lease> {
+        let mut leases = self.leases.lock();
+        if leases.get(&mac)?.ip != ip {
+            return None;
+        }
+        let lease = leases.remove(&mac);
+        let until = leases::now().saturating_add(DECLINE_HOLD_TIME as u64);
+        leases.pool(self.id).quarantined.insert(ip, until);
+        self.leases
+            .commit(leases, LeaseChange::Remove(format_mac(&mac)));
+        lease
+    }
+
+    #[cfg(test)]
+    pub fn is_quarantined(&self, ip: Ipv4Addr, now: u64) -> bool {
+        self.leases.lock().pool(self.id).is_quarantined(ip, now)
+    }
+}
+
+/// Parse an address range, "10.0.0.10-10.0.0.20", or a single address
+pub fn parse_range(range: &str) -> Result<(Ipv4Addr, Ipv4Addr), String> {
+    let invalid = || format!("Invalid address range: {}", range);
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start: Ipv4Addr = start.trim().parse().map_err(|_| invalid())?;
+    let end: Ipv4Addr = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::leases::LeaseStore;
+
+    fn pool(start: &str, end: &str) -> IpPool {
+        let leases = Arc::new(LeaseTable::load(LeaseStore::new(None)).unwrap());
+        IpPool::new(start.parse().unwrap(), end.parse().unwrap(), leases).unwrap()
+    }
+
+    fn mac(n: u32) -> [u8; 6] {
+        let [a, b, c, d] = n.to_be_bytes();
+        [0x02, 0x00, a, b, c, d]
+    }
+
+    #[test]
+    fn test_ip_pool() {
+        let pool = pool("192.168.1.100", "192.168.1.110");
+
+        let mac1 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let (ip1, _) = pool.allocate(mac1).unwrap();
+        assert_eq!(ip1, pool.start);
+
+        let (ip1_again, _) = pool.allocate(mac1).unwrap();
+        assert_eq!(ip1_again, ip1); // Same MAC gets same IP
+    }
+
+    #[test]
+    fn test_ip_pool_reclaims_expired_leases() {
+        let pool = pool("192.168.1.100", "192.168.1.101");
+
+        let mac1 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x01];
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x02];
+        let mac3 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x03];
+        pool.allocate(mac1).unwrap();
+        pool.allocate(mac2).unwrap();
+        pool.update(mac1, |lease| lease.renew(LeaseState::Bound, 3600));
+        pool.update(mac2, |lease| lease.renew(LeaseState::Bound, 3600));
+        assert_eq!(pool.allocate(mac3), None); // Pool exhausted
+
+        pool.update(mac1, |lease| lease.expiry = 0);
+        let (ip, reclaimed) = pool.allocate(mac3).unwrap();
+        assert_eq!(ip, pool.start);
+        assert_eq!(reclaimed.map(|lease| lease.mac), Some(mac1));
+        assert!(pool.leases.lock().get(&mac1).is_none());
+    }
+
+    #[test]
+    fn test_ip_pool_persistence() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("leases.json");
+        let start = "192.168.1.100".parse().unwrap();
+        let end = "192.168.1.110".parse().unwrap();
+        let load = || {
+            let store = LeaseStore::new(Some(path.clone()));
+            IpPool::new(start, end, Arc::new(LeaseTable::load(store).unwrap())).unwrap()
+        };
+
+        let mac1 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        {
+            let pool = load();
+            pool.allocate(mac1).unwrap();
+            pool.allocate(mac2).unwrap();
+            pool.update(mac2, |lease| lease.renew(LeaseState::Bound, 3600));
+        }
+
+        // A restarted pool hands out the same addresses
+        let pool = load();
+        assert_eq!(
+            pool.allocate(mac2),
+            Some(("192.168.1.101".parse().unwrap(), None))
+        );
+        assert!(pool.release(mac2, "192.168.1.101".parse().unwrap()));
+
+        let pool = load();
+        assert!(pool.leases.lock().get(&mac2).is_none());
+        // Loaded leases are taken into account, released ones are free
+        assert_eq!(pool.allocate(mac1), Some((start, None)));
+        assert_eq!(
+            pool.allocate(mac(1)),
+            Some(("192.168.1.101".parse().unwrap(), None))
+        );
+    }
+
+    #[test]
+    fn test_large_pool() {
+        // A /16 whose range doesn't start on an octet boundary
+        let pool = pool("10.0.255.250", "10.1.255.249");
+        for n in 0..65536 {
+            assert!(pool.allocate(mac(n)).is_some(), "address {}", n);
+        }
+        assert_eq!(pool.allocate(mac(65536)), None);
+
+        let leases = pool.leases.lock();
+        assert_eq!(leases.get(&mac(0)).unwrap().ip, pool.start);
+        assert_eq!(
+            leases.get(&mac(6)).unwrap().ip,
+            "10.1.0.0".parse::<Ipv4Addr>().unwrap()
+        );
+        assert_eq!(leases.get(&mac(65535)).unwrap().ip, pool.end);
+    }
+
+    #[test]
+    fn test_next_free_wraps() {
+        let pool = pool("10.0.0.1", "10.0.0.100");
+        for n in 0..100 {
+            pool.allocate(mac(n)).unwrap();
+        }
+        assert!(pool.release(mac(3), Ipv4Addr::UNSPECIFIED));
+        assert!(pool.release(mac(90), Ipv4Addr::UNSPECIFIED));
+
+        // The search goes on from the last allocation and wraps around
+        assert_eq!(
+            pool.allocate(mac(100)),
+            Some(("10.0.0.4".parse().unwrap(), None))
+        );
+        assert_eq!(
+            pool.allocate(mac(101)),
+            Some(("10.0.0.91".parse().unwrap(), None))
+        );
+        assert_eq!(pool.allocate(mac(102)), None);
+    }
+
+    #[test]
+    fn test_excluded_and_quarantined() {
+        let mut pool = pool("10.0.0.1", "10.0.0.10");
+        pool.exclude_range("10.0.0.1".parse().unwrap(), "10.0.0.4".parse().unwrap());
+        pool.exclude("10.0.0.6".parse().unwrap());
+        // Out of the pool, ignored
+        pool.exclude_range("10.0.0.9".parse().unwrap(), "10.0.1.0".parse().unwrap());
+
+        assert_eq!(
+            pool.allocate(mac(1)),
+            Some(("10.0.0.5".parse().unwrap(), None))
+        );
+        assert!(pool.decline(mac(1), "10.0.0.5".parse().unwrap()).is_some());
+        assert_eq!(
+            pool.allocate(mac(2)),
+            Some(("10.0.0.7".parse().unwrap(), None))
+        );
+        assert_eq!(
+            pool.allocate(mac(3)),
+            Some(("10.0.0.8".parse().unwrap(), None))
+        );
+        assert_eq!(pool.allocate(mac(4)), None);
+        assert!(pool.is_quarantined("10.0.0.5".parse().unwrap(), leases::now()));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        assert_eq!(
+            parse_range("10.0.0.10-10.0.0.20"),
+            Ok((ip("10.0.0.10"), ip("10.0.0.20")))
+        );
+        assert_eq!(
+            parse_range("10.0.0.5"),
+            Ok((ip("10.0.0.5"), ip("10.0.0.5")))
+        );
+        assert!(parse_range("10.0.0.20-10.0.0.10").is_err());
+        assert!(parse_range("10.0.0.0/24").is_err());
+    }
+
+    #[test]
+    fn test_invalid_pools() {
+        let leases = Arc::new(LeaseTable::load(LeaseStore::new(None)).unwrap());
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        assert!(IpPool::new(ip("10.0.0.2"), ip("10.0.0.1"), Arc::clone(&leases)).is_err());
+        assert!(IpPool::new(ip("10.0.0.0"), ip("11.0.0.0"), Arc::clone(&leases)).is_err());
+        assert!(IpPool::new(ip("10.0.0.0"), ip("10.255.255.255"), leases).is_ok());
+    }
+}
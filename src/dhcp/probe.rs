@@ -0,0 +1,200 @@
+//! Address conflict detection: before a fresh address is offered, an ICMP
+//! echo request checks that no other host already uses it (RFC 2131,
+//! section 3.1, step 2). Probes run in the background and the client's
+//! retransmitted DISCOVER gets the offer, as in ISC dhcpd.
+
+use crate::dhcp::leases;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing as log;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// How long an address that didn't answer is considered free, so clients
+/// retransmitting their DISCOVER aren't probed again
+const FREE_CACHE_TIME: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProbeState {
+    Probing,
+    /// Nobody answered, until when the result holds
+    Free(u64),
+    InUse,
+}
+
+pub struct ConflictProbe {
+    timeout: Duration,
+    states: Mutex<HashMap<Ipv4Addr, ProbeState>>,
+}
+
+impl ConflictProbe {
+    pub fn new(timeout: Duration) -> Self {
+        ConflictProbe {
+            timeout,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Result of the probe of `ip`, if it was probed. An address found in
+    /// use is only reported once.
+    pub fn state(&self, ip: Ipv4Addr) -> Option<ProbeState> {
+        let now = leases::now();
+        let mut states = self.states.lock().unwrap();
+        match states.get(&ip).copied() {
+            Some(ProbeState::Free(until)) if until <= now => {
+                states.remove(&ip);
+                None
+            }
+            Some(ProbeState::InUse) => states.remove(&ip),
+            state => state,
+        }
+    }
+
+    /// Start probing `ip` in the background. Probes that can't be sent, for
+    /// lack of privileges for instance, find the address free.
+    pub fn start(self: &Arc<Self>, ip: Ipv4Addr) {
+        {
+            let mut states = self.states.lock().unwrap();
+            if states.get(&ip) == Some(&ProbeState::Probing) {
+                return;
+            }
+            let now = leases::now();
+            states.retain(|_, state| !matches!(state, ProbeState::Free(until) if *until <= now));
+            states.insert(ip, ProbeState::Probing);
+        }
+
+        let probe = Arc::clone(self);
+        tokio::spawn(async move {
+            let timeout = probe.timeout;
+            let answered = match tokio::task::spawn_blocking(move || ping(ip, timeout)).await {
+                Ok(Ok(answered)) => answered,
+                Ok(Err(e)) => {
+                    log::debug!("Cannot probe {}: {}", ip, e);
+                    false
+                }
+                Err(_) => false,
+            };
+            let state = if answered {
+                ProbeState::InUse
+            } else {
+                ProbeState::Free(leases::now() + FREE_CACHE_TIME)
+            };
+            probe.states.lock().unwrap().insert(ip, state);
+        });
+    }
+
+    /// Whether probes can be sent, which needs a ping socket or CAP_NET_RAW
+    #[cfg(test)]
+    pub(crate) fn can_ping() -> bool {
+        ping(Ipv4Addr::LOCALHOST, Duration::from_millis(200)).is_ok()
+    }
+
+    /// Wait for the probe of `ip` to finish
+    #[cfg(test)]
+    pub(crate) async fn finished(&self, ip: Ipv4Addr) {
+        while self.states.lock().unwrap().get(&ip) == Some(&ProbeState::Probing) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Send an ICMP echo request to `ip` and wait up to `timeout` for the reply
+fn ping(ip: Ipv4Addr, timeout: Duration) -> io::Result<bool> {
+    // Unprivileged ping sockets where allowed, raw sockets otherwise. Only raw
+    // sockets hand over the IP header.
+    let (mut socket, raw) = match Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)) {
+        Ok(socket) => (socket, false),
+        Err(_) => (
+            Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+            true,
+        ),
+    };
+    // Only receive packets from the probed address
+    socket.connect(&SockAddr::from(SocketAddrV4::new(ip, 0)))?;
+    socket.send(&echo_request(std::process::id() as u16, 1))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.read(&mut buf) {
+            Ok(size) => size,
+            // Timeout, or an ICMP error such as host unreachable
+            Err(_) => return Ok(false),
+        };
+        let header = if raw {
+            ((buf[0] & 0x0f) as usize) * 4
+        } else {
+            0
+        };
+        if buf[header..size].first() == Some(&ICMP_ECHO_REPLY) {
+            return Ok(true);
+        }
+    }
+}
+
+fn echo_request(id: u16, seq: u16) -> Vec<u8> {
+    let mut packet = vec![ICMP_ECHO_REQUEST, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(b"finiky conflict probe");
+    let checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+/// Internet checksum (RFC 1071)
+pub(crate) fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_request_checksum() {
+        let packet = echo_request(0x1234, 1);
+        assert_eq!(packet[0], ICMP_ECHO_REQUEST);
+        assert_eq!(&packet[4..8], &[0x12, 0x34, 0, 1]);
+        // A packet including its checksum sums to zero
+        assert_eq!(checksum(&packet), 0);
+    }
+
+    #[tokio::test]
+    async fn test_probe_loopback() {
+        let probe = Arc::new(ConflictProbe::new(Duration::from_millis(500)));
+        let loopback = Ipv4Addr::LOCALHOST;
+        assert_eq!(probe.state(loopback), None);
+        probe.start(loopback);
+        assert_eq!(probe.state(loopback), Some(ProbeState::Probing));
+        probe.finished(loopback).await;
+
+        // Without a ping socket or CAP_NET_RAW the address is taken as free
+        if ConflictProbe::can_ping() {
+            assert_eq!(probe.state(loopback), Some(ProbeState::InUse));
+            assert_eq!(probe.state(loopback), None);
+        } else {
+            assert!(matches!(
+                probe.state(loopback),
+                Some(ProbeState::Free(until)) if until > leases::now()
+            ));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::config::ProtocolConfig;
+use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BootProtocol {
@@ -7,6 +8,206 @@ pub enum BootProtocol {
     DhcpBoot,
 }
 
+/// Client system architecture (option 93), from the IANA "Processor
+/// Architecture Types" registry (RFC 4578 and later assignments)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientArch {
+    X86Bios,
+    NecPc98,
+    Itanium,
+    DecAlpha,
+    ArcX86,
+    IntelLeanClient,
+    X86Uefi,
+    X64Uefi,
+    EfiXscale,
+    Ebc,
+    Arm32Uefi,
+    Arm64Uefi,
+    PowerPcOpenFirmware,
+    PowerPcEpapr,
+    PowerOpalV3,
+    X86UefiHttp,
+    X64UefiHttp,
+    EbcHttp,
+    Arm32UefiHttp,
+    Arm64UefiHttp,
+    X86BiosHttp,
+    Arm32Uboot,
+    Arm64Uboot,
+    Arm32UbootHttp,
+    Arm64UbootHttp,
+    RiscV32Uefi,
+    RiscV32UefiHttp,
+    RiscV64Uefi,
+    RiscV64UefiHttp,
+    RiscV128Uefi,
+    RiscV128UefiHttp,
+    S390Basic,
+    S390Extended,
+    Mips32Uefi,
+    Mips64Uefi,
+    Sunway32Uefi,
+    Sunway64Uefi,
+    LoongArch32Uefi,
+    LoongArch32UefiHttp,
+    LoongArch64Uefi,
+    LoongArch64UefiHttp,
+    ArmRpiboot,
+    Unknown(u16),
+}
+
+/// Registered architectures with their option 93 code and the name used for
+/// them in `[dhcp.protocols.boot_filenames]`
+const ARCHITECTURES: &[(ClientArch, u16, &str)] = &[
+    (ClientArch::X86Bios, 0, "x86_bios"),
+    (ClientArch::NecPc98, 1, "nec_pc98"),
+    (ClientArch::Itanium, 2, "itanium"),
+    (ClientArch::DecAlpha, 3, "dec_alpha"),
+    (ClientArch::ArcX86, 4, "arc_x86"),
+    (ClientArch::IntelLeanClient, 5, "intel_lean_client"),
+    (ClientArch::X86Uefi, 6, "x86_uefi"),
+    (ClientArch::X64Uefi, 7, "x64_uefi"),
+    (ClientArch::EfiXscale, 8, "efi_xscale"),
+    (ClientArch::Ebc, 9, "ebc"),
+    (ClientArch::Arm32Uefi, 10, "arm32_uefi"),
+    (ClientArch::Arm64Uefi, 11, "arm64_uefi"),
+    (ClientArch::PowerPcOpenFirmware, 12, "powerpc_open_firmware"),
+    (ClientArch::PowerPcEpapr, 13, "powerpc_epapr"),
+    (ClientArch::PowerOpalV3, 14, "power_opal_v3"),
+    (ClientArch::X86UefiHttp, 15, "x86_uefi_http"),
+    (ClientArch::X64UefiHttp, 16, "x64_uefi_http"),
+    (ClientArch::EbcHttp, 17, "ebc_http"),
+    (ClientArch::Arm32UefiHttp, 18, "arm32_uefi_http"),
+    (ClientArch::Arm64UefiHttp, 19, "arm64_uefi_http"),
+    (ClientArch::X86BiosHttp, 20, "x86_bios_http"),
+    (ClientArch::Arm32Uboot, 21, "arm32_uboot"),
+    (ClientArch::Arm64Uboot, 22, "arm64_uboot"),
+    (ClientArch::Arm32UbootHttp, 23, "arm32_uboot_http"),
+    (ClientArch::Arm64UbootHttp, 24, "arm64_uboot_http"),
+    (ClientArch::RiscV32Uefi, 25, "riscv32_uefi"),
+    (ClientArch::RiscV32UefiHttp, 26, "riscv32_uefi_http"),
+    (ClientArch::RiscV64Uefi, 27, "riscv64_uefi"),
+    (ClientArch::RiscV64UefiHttp, 28, "riscv64_uefi_http"),
+    (ClientArch::RiscV128Uefi, 29, "riscv128_uefi"),
+    (ClientArch::RiscV128UefiHttp, 30, "riscv128_uefi_http"),
+    (ClientArch::S390Basic, 31, "s390_basic"),
+    (ClientArch::S390Extended, 32, "s390_extended"),
+    (ClientArch::Mips32Uefi, 33, "mips32_uefi"),
+    (ClientArch::Mips64Uefi, 34, "mips64_uefi"),
+    (ClientArch::Sunway32Uefi, 35, "sunway32_uefi"),
+    (ClientArch::Sunway64Uefi, 36, "sunway64_uefi"),
+    (ClientArch::LoongArch32Uefi, 37, "loongarch32_uefi"),
+    (ClientArch::LoongArch32UefiHttp, 38, "loongarch32_uefi_http"),
+    (ClientArch::LoongArch64Uefi, 39, "loongarch64_uefi"),
+    (ClientArch::LoongArch64UefiHttp, 40, "loongarch64_uefi_http"),
+    (ClientArch::ArmRpiboot, 41, "arm_rpiboot"),
+];
+
+impl ClientArch {
+    pub fn from_code(code: u16) -> Self {
+        ARCHITECTURES
+            .iter()
+            .find(|(_, c, _)| *c == code)
+            .map(|(arch, _, _)| *arch)
+            .unwrap_or(ClientArch::Unknown(code))
+    }
+
+    /// Parse an architecture name such as `arm64_uefi`, or its numeric code
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Ok(code) = name.parse::<u16>() {
+            return Some(Self::from_code(code));
+        }
+        ARCHITECTURES
+            .iter()
+            .find(|(_, _, n)| n.eq_ignore_ascii_case(name))
+            .map(|(arch, _, _)| *arch)
+    }
+
+    pub fn code(self) -> u16 {
+        match self {
+            ClientArch::Unknown(code) => code,
+            arch => ARCHITECTURES
+                .iter()
+                .find(|(a, _, _)| *a == arch)
+                .map(|(_, code, _)| *code)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn name(self) -> Option<&'static str> {
+        ARCHITECTURES
+            .iter()
+            .find(|(a, _, _)| *a == self)
+            .map(|(_, _, name)| *name)
+    }
+
+    /// Firmware that boots through PC BIOS style PXE
+    pub fn is_bios(self) -> bool {
+        matches!(
+            self,
+            ClientArch::X86Bios | ClientArch::NecPc98 | ClientArch::X86BiosHttp
+        )
+    }
+
+    pub fn is_uefi(self) -> bool {
+        matches!(
+            self,
+            ClientArch::X86Uefi
+                | ClientArch::X64Uefi
+                | ClientArch::Ebc
+                | ClientArch::Arm32Uefi
+                | ClientArch::Arm64Uefi
+                | ClientArch::X86UefiHttp
+                | ClientArch::X64UefiHttp
+                | ClientArch::EbcHttp
+                | ClientArch::Arm32UefiHttp
+                | ClientArch::Arm64UefiHttp
+                | ClientArch::RiscV32Uefi
+                | ClientArch::RiscV32UefiHttp
+                | ClientArch::RiscV64Uefi
+                | ClientArch::RiscV64UefiHttp
+                | ClientArch::RiscV128Uefi
+                | ClientArch::RiscV128UefiHttp
+                | ClientArch::Mips32Uefi
+                | ClientArch::Mips64Uefi
+                | ClientArch::Sunway32Uefi
+                | ClientArch::Sunway64Uefi
+                | ClientArch::LoongArch32Uefi
+                | ClientArch::LoongArch32UefiHttp
+                | ClientArch::LoongArch64Uefi
+                | ClientArch::LoongArch64UefiHttp
+        )
+    }
+
+    /// Default UEFI loader for the architecture, from the removable media
+    /// file names of the UEFI specification
+    pub fn default_efi_filename(self) -> Option<&'static str> {
+        match self {
+            ClientArch::X86Uefi | ClientArch::X86UefiHttp => Some("bootia32.efi"),
+            ClientArch::X64Uefi | ClientArch::X64UefiHttp => Some("bootx64.efi"),
+            ClientArch::Arm32Uefi | ClientArch::Arm32UefiHttp => Some("bootarm.efi"),
+            ClientArch::Arm64Uefi | ClientArch::Arm64UefiHttp => Some("bootaa64.efi"),
+            ClientArch::RiscV32Uefi | ClientArch::RiscV32UefiHttp => Some("bootriscv32.efi"),
+            ClientArch::RiscV64Uefi | ClientArch::RiscV64UefiHttp => Some("bootriscv64.efi"),
+            ClientArch::RiscV128Uefi | ClientArch::RiscV128UefiHttp => Some("bootriscv128.efi"),
+            ClientArch::LoongArch32Uefi | ClientArch::LoongArch32UefiHttp => {
+                Some("bootloongarch32.efi")
+            }
+            ClientArch::LoongArch64Uefi | ClientArch::LoongArch64UefiHttp => {
+                Some("bootloongarch64.efi")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name().unwrap_or("unknown"), self.code())
+    }
+}
+
 pub struct ProtocolHandler;
 
 impl ProtocolHandler {
@@ -15,23 +216,20 @@ impl ProtocolHandler {
         client_arch: Option<u16>,
     ) -> Option<BootProtocol> {
         // Check client architecture option (option 93)
-        if let Some(arch) = client_arch {
-            match arch {
-                6 => {
-                    return if config.efi {
-                        Some(BootProtocol::Efi)
-                    } else {
-                        None
-                    }
-                }
-                0 | 1 => {
-                    return if config.legacy {
-                        Some(BootProtocol::Legacy)
-                    } else {
-                        None
-                    }
-                }
-                _ => {}
+        if let Some(arch) = client_arch.map(ClientArch::from_code) {
+            if arch.is_uefi() {
+                return if config.efi {
+                    Some(BootProtocol::Efi)
+                } else {
+                    None
+                };
+            }
+            if arch.is_bios() {
+                return if config.legacy {
+                    Some(BootProtocol::Legacy)
+                } else {
+                    None
+                };
             }
         }
 
@@ -47,6 +245,43 @@ impl ProtocolHandler {
         }
     }
 
+    /// Boot filename for a client: the `boot_filenames` entry for its
+    /// architecture, or the file for the protocol matching it
+    pub fn select_boot_filename(
+        config: &ProtocolConfig,
+        client_arch: Option<u16>,
+    ) -> Option<(BootProtocol, String)> {
+        let protocol = Self::select_protocol(config, client_arch)?;
+        let arch = client_arch.map(ClientArch::from_code);
+
+        if let Some(arch) = arch {
+            let filename = config.boot_filenames.iter().find_map(|(name, filename)| {
+                (ClientArch::from_name(name) == Some(arch)).then(|| filename.clone())
+            });
+            if let Some(filename) = filename {
+                return Some((protocol, filename));
+            }
+        }
+
+        if protocol == BootProtocol::Efi && config.boot_filename_efi.is_none() {
+            if let Some(filename) = arch.and_then(ClientArch::default_efi_filename) {
+                return Some((protocol, filename.to_string()));
+            }
+        }
+
+        Some((protocol, Self::get_boot_filename(protocol, config)))
+    }
+
+    /// Check that every `boot_filenames` key names a known architecture
+    pub fn validate(config: &ProtocolConfig) -> Result<(), String> {
+        for name in config.boot_filenames.keys() {
+            if ClientArch::from_name(name).is_none() {
+                return Err(format!("Unknown client architecture: {}", name));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_boot_filename(protocol: BootProtocol, config: &ProtocolConfig) -> String {
         match protocol {
             BootProtocol::Efi => config
@@ -79,6 +314,8 @@ mod tests {
             boot_filename_efi: None,
             boot_filename_legacy: None,
             boot_filename_dhcp_boot: None,
+            boot_filenames: Default::default(),
+            ipxe_script: None,
         };
 
         assert_eq!(
@@ -100,6 +337,8 @@ mod tests {
             boot_filename_efi: None,
             boot_filename_legacy: None,
             boot_filename_dhcp_boot: None,
+            boot_filenames: Default::default(),
+            ipxe_script: None,
         };
 
         assert_eq!(
@@ -121,6 +360,8 @@ mod tests {
             boot_filename_efi: Some("custom_efi.efi".to_string()),
             boot_filename_legacy: Some("custom_legacy.0".to_string()),
             boot_filename_dhcp_boot: Some("custom_dhcp.0".to_string()),
+            boot_filenames: Default::default(),
+            ipxe_script: None,
         };
 
         assert_eq!(
@@ -136,4 +377,95 @@ mod tests {
             "custom_dhcp.0"
         );
     }
+
+    #[test]
+    fn test_client_arch() {
+        assert_eq!(ClientArch::from_code(7), ClientArch::X64Uefi);
+        assert_eq!(ClientArch::from_code(11), ClientArch::Arm64Uefi);
+        assert_eq!(ClientArch::from_code(0x1234), ClientArch::Unknown(0x1234));
+        assert_eq!(ClientArch::Arm64UefiHttp.code(), 19);
+        assert_eq!(ClientArch::Unknown(99).code(), 99);
+        assert_eq!(
+            ClientArch::from_name("ARM64_UEFI"),
+            Some(ClientArch::Arm64Uefi)
+        );
+        assert_eq!(ClientArch::from_name("16"), Some(ClientArch::X64UefiHttp));
+        assert_eq!(ClientArch::from_name("vax"), None);
+
+        for code in 0..=41 {
+            let arch = ClientArch::from_code(code);
+            assert_eq!(arch.code(), code);
+            assert_eq!(ClientArch::from_name(arch.name().unwrap()), Some(arch));
+        }
+
+        assert!(ClientArch::X64Uefi.is_uefi());
+        assert_eq!(ClientArch::Arm64Uefi.to_string(), "arm64_uefi (11)");
+        assert_eq!(ClientArch::Unknown(99).to_string(), "unknown (99)");
+        assert!(ClientArch::X86Bios.is_bios());
+        assert!(!ClientArch::Arm64Uboot.is_uefi());
+    }
+
+    #[test]
+    fn test_select_protocol_by_arch() {
+        let config = ProtocolConfig {
+            efi: true,
+            legacy: true,
+            dhcp_boot: true,
+            boot_filename_efi: None,
+            boot_filename_legacy: None,
+            boot_filename_dhcp_boot: None,
+            boot_filenames: Default::default(),
+            ipxe_script: None,
+        };
+
+        for arch in [7, 9, 10, 11, 16, 19, 27] {
+            assert_eq!(
+                ProtocolHandler::select_protocol(&config, Some(arch)),
+                Some(BootProtocol::Efi)
+            );
+        }
+        assert_eq!(
+            ProtocolHandler::select_boot_filename(&config, Some(11)),
+            Some((BootProtocol::Efi, "bootaa64.efi".to_string()))
+        );
+        assert_eq!(
+            ProtocolHandler::select_boot_filename(&config, Some(0)),
+            Some((BootProtocol::Legacy, "pxelinux.0".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_boot_filename_table() {
+        let mut config = ProtocolConfig {
+            efi: true,
+            legacy: true,
+            dhcp_boot: true,
+            boot_filename_efi: Some("grubx64.efi".to_string()),
+            boot_filename_legacy: None,
+            boot_filename_dhcp_boot: None,
+            boot_filenames: Default::default(),
+            ipxe_script: None,
+        };
+        config
+            .boot_filenames
+            .insert("arm64_uefi".to_string(), "grubaa64.efi".to_string());
+        config
+            .boot_filenames
+            .insert("10".to_string(), "grubarm.efi".to_string());
+        assert!(ProtocolHandler::validate(&config).is_ok());
+
+        let filename = |arch| {
+            ProtocolHandler::select_boot_filename(&config, Some(arch))
+                .unwrap()
+                .1
+        };
+        assert_eq!(filename(11), "grubaa64.efi");
+        assert_eq!(filename(10), "grubarm.efi");
+        assert_eq!(filename(7), "grubx64.efi");
+
+        config
+            .boot_filenames
+            .insert("vax".to_string(), "vmunix".to_string());
+        assert!(ProtocolHandler::validate(&config).is_err());
+    }
 }
@@ -0,0 +1,218 @@
+use crate::config::PxeMenuConfig;
+use std::net::Ipv4Addr;
+
+// PXE vendor options (option 43) sub-options, PXE specification 2.1
+pub const PXE_DISCOVERY_CONTROL: u8 = 6;
+pub const PXE_BOOT_SERVERS: u8 = 8;
+pub const PXE_BOOT_MENU: u8 = 9;
+pub const PXE_MENU_PROMPT: u8 = 10;
+pub const PXE_BOOT_ITEM: u8 = 71;
+
+/// Boot server type 0 tells the PXE ROM to boot from local disk
+const LOCAL_BOOT: u16 = 0;
+/// First boot server type of the vendor-specific range
+const VENDOR_BOOT_TYPE: u16 = 0x8000;
+
+/// Discovery control bits: no broadcast, no multicast, only the listed servers
+const DEFAULT_DISCOVERY_CONTROL: u8 = 0x07;
+
+#[derive(Debug, Clone)]
+pub struct PxeMenuItem {
+    pub boot_type: u16,
+    pub label: String,
+    pub boot_filename: Option<String>,
+    pub boot_server: Option<Ipv4Addr>,
+}
+
+/// Firmware-native PXE boot menu from `[dhcp.pxe_menu]`
+#[derive(Debug, Clone)]
+pub struct PxeMenu {
+    prompt: String,
+    timeout: u8,
+    discovery_control: u8,
+    items: Vec<PxeMenuItem>,
+}
+
+impl PxeMenu {
+    /// Build the menu. Items booting a file get boot server types from the
+    /// vendor range in order, local disk items get type 0.
+    pub fn from_config(config: &PxeMenuConfig, next_server: Ipv4Addr) -> Result<Self, String> {
+        if config.items.is_empty() {
+            return Err("PXE menu needs at least one item".to_string());
+        }
+
+        let mut items = Vec::new();
+        let mut next_type = VENDOR_BOOT_TYPE;
+        for item in &config.items {
+            let (boot_type, boot_server) = match item.boot_filename {
+                Some(_) => {
+                    let server = match item.boot_server {
+                        Some(ref server) => server
+                            .parse()
+                            .map_err(|_| format!("Invalid PXE menu boot server: {}", server))?,
+                        None => next_server,
+                    };
+                    next_type += 1;
+                    (next_type - 1, Some(server))
+                }
+                None => (LOCAL_BOOT, None),
+            };
+            items.push(PxeMenuItem {
+                boot_type,
+                label: item.label.clone(),
+                boot_filename: item.boot_filename.clone(),
+                boot_server,
+            });
+        }
+
+        let menu = PxeMenu {
+            prompt: config.prompt.clone(),
+            timeout: config.timeout,
+            discovery_control: config
+                .discovery_control
+                .unwrap_or(DEFAULT_DISCOVERY_CONTROL),
+            items,
+        };
+        if menu.encode().len() > 255 {
+            return Err("PXE menu does not fit in option 43, shorten the labels".to_string());
+        }
+        Ok(menu)
+    }
+
+    /// Encode the menu as option 43 contents
+    pub fn encode(&self) -> Vec<u8> {
+        let mut vendor = vec![PXE_DISCOVERY_CONTROL, 1, self.discovery_control];
+
+        let mut servers = Vec::new();
+        for item in &self.items {
+            if let Some(server) = item.boot_server {
+                servers.extend_from_slice(&item.boot_type.to_be_bytes());
+                servers.push(1); // IP address count
+                servers.extend_from_slice(&server.octets());
+            }
+        }
+        if !servers.is_empty() {
+            push_suboption(&mut vendor, PXE_BOOT_SERVERS, &servers);
+        }
+
+        let mut menu = Vec::new();
+        for item in &self.items {
+            let label = &item.label.as_bytes()[..item.label.len().min(255)];
+            menu.extend_from_slice(&item.boot_type.to_be_bytes());
+            menu.push(label.len() as u8);
+            menu.extend_from_slice(label);
+        }
+        push_suboption(&mut vendor, PXE_BOOT_MENU, &menu);
+
+        let mut prompt = vec![self.timeout];
+        prompt.extend_from_slice(self.prompt.as_bytes());
+        push_suboption(&mut vendor, PXE_MENU_PROMPT, &prompt);
+
+        vendor.push(255);
+        vendor
+    }
+
+    pub fn find(&self, boot_type: u16) -> Option<&PxeMenuItem> {
+        self.items
+            .iter()
+            .find(|item| item.boot_type == boot_type && item.boot_filename.is_some())
+    }
+}
+
+fn push_suboption(vendor: &mut Vec<u8>, code: u8, data: &[u8]) {
+    let data = &data[..data.len().min(255)];
+    vendor.push(code);
+    vendor.push(data.len() as u8);
+    vendor.extend_from_slice(data);
+}
+
+/// Boot server type and layer a client selected from the menu, from the
+/// PXE_BOOT_ITEM sub-option of its option 43
+pub fn boot_item(vendor: &[u8]) -> Option<(u16, u16)> {
+    let mut i = 0;
+    while i < vendor.len() {
+        let code = vendor[i];
+        if code == 255 {
+            break;
+        }
+        if code == 0 {
+            i += 1;
+            continue;
+        }
+        let len = *vendor.get(i + 1)? as usize;
+        let data = vendor.get(i + 2..i + 2 + len)?;
+        if code == PXE_BOOT_ITEM && len == 4 {
+            return Some((
+                u16::from_be_bytes([data[0], data[1]]),
+                u16::from_be_bytes([data[2], data[3]]),
+            ));
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PxeMenuItemConfig;
+
+    fn menu_config() -> PxeMenuConfig {
+        let item = |label: &str, filename: Option<&str>| PxeMenuItemConfig {
+            label: label.to_string(),
+            boot_filename: filename.map(str::to_string),
+            boot_server: None,
+        };
+        PxeMenuConfig {
+            prompt: "Boot".to_string(),
+            timeout: 5,
+            discovery_control: None,
+            items: vec![
+                item("Install", Some("install.efi")),
+                item("Rescue", Some("rescue.efi")),
+                item("Local disk", None),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_encode_menu() {
+        let server = "10.0.0.1".parse().unwrap();
+        let menu = PxeMenu::from_config(&menu_config(), server).unwrap();
+        let vendor = menu.encode();
+
+        let mut expected = vec![6, 1, 7];
+        expected.extend_from_slice(&[8, 14, 0x80, 0, 1, 10, 0, 0, 1, 0x80, 1, 1, 10, 0, 0, 1]);
+        expected.extend_from_slice(&[9, 32, 0x80, 0, 7]);
+        expected.extend_from_slice(b"Install");
+        expected.extend_from_slice(&[0x80, 1, 6]);
+        expected.extend_from_slice(b"Rescue");
+        expected.extend_from_slice(&[0, 0, 10]);
+        expected.extend_from_slice(b"Local disk");
+        expected.extend_from_slice(&[10, 5, 5]);
+        expected.extend_from_slice(b"Boot");
+        expected.push(255);
+        assert_eq!(vendor, expected);
+
+        assert_eq!(
+            menu.find(0x8001).unwrap().boot_filename.as_deref(),
+            Some("rescue.efi")
+        );
+        assert!(menu.find(LOCAL_BOOT).is_none());
+    }
+
+    #[test]
+    fn test_boot_item() {
+        assert_eq!(boot_item(&[71, 4, 0x80, 1, 0, 0, 255]), Some((0x8001, 0)));
+        assert_eq!(boot_item(&[6, 1, 8, 71, 4, 0, 0, 0, 1]), Some((0, 1)));
+        assert_eq!(boot_item(&[6, 1, 8, 255]), None);
+        assert_eq!(boot_item(&[71, 4, 0x80]), None);
+    }
+
+    #[test]
+    fn test_menu_too_long() {
+        let mut config = menu_config();
+        config.items[0].label = "x".repeat(250);
+        assert!(PxeMenu::from_config(&config, Ipv4Addr::LOCALHOST).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::config::HostConfig;
+use crate::dhcp::access::Client;
+use crate::dhcp::leases::parse_mac;
+use crate::dhcp::options::{custom_option, parse_hex, DhcpOption};
+use crate::dhcp::server::DhcpMessage;
+use std::net::Ipv4Addr;
+
+/// A static host entry from `[[dhcp.hosts]]`
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub mac: Option<[u8; 6]>,
+    pub client_id: Option<Vec<u8>>,
+    pub uuid: Option<Vec<u8>>,
+    pub ip: Ipv4Addr,
+    pub hostname: Option<String>,
+    pub boot_filename: Option<String>,
+    pub next_server: Option<Ipv4Addr>,
+    pub options: Vec<DhcpOption>,
+}
+
+impl Reservation {
+    pub fn from_config(host: &HostConfig) -> Result<Self, String> {
+        let mac = host
+            .mac
+            .as_deref()
+            .map(|mac| parse_mac(mac).ok_or_else(|| format!("Invalid host MAC address: {}", mac)))
+            .transpose()?;
+        let client_id = host
+            .client_id
+            .as_deref()
+            .map(|id| parse_hex(id).ok_or_else(|| format!("Invalid host client-id: {}", id)))
+            .transpose()?;
+        let uuid = host
+            .uuid
+            .as_deref()
+            .map(|uuid| match parse_hex(uuid) {
+                Some(bytes) if bytes.len() == 16 => Ok(bytes),
+                _ => Err(format!("Invalid host UUID: {}", uuid)),
+            })
+            .transpose()?;
+
+        if mac.is_none() && client_id.is_none() && uuid.is_none() {
+            return Err(format!(
+                "Host reservation for {} needs a mac, client_id or uuid",
+                host.ip
+            ));
+        }
+
+        let ip = host
+            .ip
+            .parse()
+            .map_err(|_| format!("Invalid host IP address: {}", host.ip))?;
+        let next_server = host
+            .next_server
+            .as_deref()
+            .map(|ip| {
+                ip.parse()
+                    .map_err(|_| format!("Invalid host next_server: {}", ip))
+            })
+            .transpose()?;
+        let options = host
+            .options
+            .iter()
+            .map(custom_option)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Host {}: {}", host.ip, e))?;
+
+        Ok(Reservation {
+            mac,
+            client_id,
+            uuid,
+            ip,
+            hostname: host.hostname.clone(),
+            boot_filename: host.boot_filename.clone(),
+            next_server,
+            options,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Reservations {
+    hosts: Vec<Reservation>,
+}
+
+impl Reservations {
+    pub fn from_config(hosts: &[HostConfig]) -> Result<Self, String> {
+        let hosts = hosts
+            .iter()
+            .map(Reservation::from_config)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (i, host) in hosts.iter().enumerate() {
+            if hosts[..i].iter().any(|other| other.ip == host.ip) {
+                return Err(format!("Address {} is reserved more than once", host.ip));
+            }
+        }
+
+        Ok(Reservations { hosts })
+    }
+
+    /// Find the reservation for a client. The client identifier (option 61)
+    /// and machine UUID (option 97) take precedence over the hardware address,
+    /// so hosts with several NICs keep one address whichever one boots.
+    pub fn find(&self, request: &DhcpMessage) -> Option<&Reservation> {
+        if let Some(client_id) = request.get_option(61) {
+            if let Some(host) = self
+                .hosts
+                .iter()
+                .find(|host| host.client_id.as_deref() == Some(client_id))
+            {
+                return Some(host);
+            }
+        }
+
+        // Option 97 is a type byte (0) followed by the 16-byte UUID
+        if let Some(uuid) = request.get_option(97).filter(|v| v.len() == 17) {
+            if let Some(host) = self
+                .hosts
+                .iter()
+                .find(|host| host.uuid.as_deref() == Some(&uuid[1..]))
+            {
+                return Some(host);
+            }
+        }
+
+        let mac = request.client_mac();
+        self.hosts.iter().find(|host| host.mac == Some(mac))
+    }
+
+    /// Whether a client has a reservation for its hardware address or UUID,
+    /// for DHCPv6 where the reserved address doesn't apply
+    pub fn knows(&self, client: &Client) -> bool {
+        self.hosts.iter().any(|host| {
+            (host.mac.is_some() && host.mac == client.mac)
+                || (host.uuid.is_some() && host.uuid.as_deref() == client.uuid)
+        })
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.hosts.iter().map(|host| host.ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::server::tests::client_request as request;
+
+    fn host(ip: &str) -> HostConfig {
+        HostConfig {
+            mac: None,
+            client_id: None,
+            uuid: None,
+            ip: ip.to_string(),
+            hostname: None,
+            boot_filename: None,
+            next_server: None,
+            options: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_find_reservation() {
+        let mut by_mac = host("192.168.1.10");
+        by_mac.mac = Some("00:11:22:33:44:55".to_string());
+        let mut by_client_id = host("192.168.1.11");
+        by_client_id.client_id = Some("01:aa:bb:cc:dd:ee:ff".to_string());
+        let mut by_uuid = host("192.168.1.12");
+        by_uuid.uuid = Some("00112233-4455-6677-8899-aabbccddeeff".to_string());
+
+        let reservations = Reservations::from_config(&[by_mac, by_client_id, by_uuid]).unwrap();
+
+        let mac = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let found = reservations.find(&request(mac, &[])).unwrap();
+        assert_eq!(found.ip, "192.168.1.10".parse::<Ipv4Addr>().unwrap());
+
+        let other_mac = [0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let options = [61, 7, 0x01, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+        let found = reservations.find(&request(other_mac, &options)).unwrap();
+        assert_eq!(found.ip, "192.168.1.11".parse::<Ipv4Addr>().unwrap());
+
+        let mut options = vec![97, 17, 0];
+        options.extend_from_slice(&parse_hex("00112233445566778899aabbccddeeff").unwrap());
+        let found = reservations.find(&request(other_mac, &options)).unwrap();
+        assert_eq!(found.ip, "192.168.1.12".parse::<Ipv4Addr>().unwrap());
+
+        assert!(reservations.find(&request(other_mac, &[])).is_none());
+    }
+
+    #[test]
+    fn test_invalid_reservations() {
+        // No identifier
+        assert!(Reservations::from_config(&[host("192.168.1.10")]).is_err());
+
+        // Duplicate address
+        let mut a = host("192.168.1.10");
+        a.mac = Some("00:11:22:33:44:55".to_string());
+        let mut b = host("192.168.1.10");
+        b.mac = Some("00:11:22:33:44:66".to_string());
+        assert!(Reservations::from_config(&[a, b]).is_err());
+    }
+}
@@ -1,9 +1,31 @@
-use crate::config::DhcpConfig;
-use crate::dhcp::options::DhcpOptions;
-use crate::dhcp::protocols::ProtocolHandler;
+use crate::config::{DhcpConfig, HttpConfig, ScopeConfig};
+use crate::dhcp::access::{AccessControl, Client};
+use crate::dhcp::ddns::DnsUpdater;
+#[cfg(target_os = "linux")]
+use crate::dhcp::frame::FrameSocket;
+use crate::dhcp::hostname;
+use crate::dhcp::leases::{self, format_mac, Lease, LeaseState, LeaseStore, LeaseTable};
+use crate::dhcp::options::{
+    self, encode_options, merge_options, order_options, raw_options, DhcpOption, DhcpOptions,
+    OptionError, DHCP_ACK, DHCP_DECLINE, DHCP_DISCOVER, DHCP_INFORM, DHCP_NAK, DHCP_OFFER,
+    DHCP_RELEASE, DHCP_REQUEST, HTTP_CLIENT_CLASS, IPXE_USER_CLASS, OPTION_END, OPTION_OVERLOAD,
+    OPTION_PAD, OVERLOAD_BOTH, OVERLOAD_FILE, OVERLOAD_SNAME, PXE_BOOT_FILE_ONLY, PXE_CLIENT_CLASS,
+};
+#[cfg(target_os = "linux")]
+use crate::dhcp::pktinfo;
+use crate::dhcp::pool::{self, IpPool, OFFER_HOLD_TIME};
+use crate::dhcp::probe::{ConflictProbe, ProbeState};
+use crate::dhcp::protocols::{ClientArch, ProtocolHandler};
+use crate::dhcp::pxe_menu::{self, PxeMenu, PXE_BOOT_ITEM};
+use crate::dhcp::reservations::{Reservation, Reservations};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
 use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::net::UdpSocket;
 use tracing as log;
 
@@ -12,7 +34,41 @@ use libc::{c_int, setsockopt, SOL_SOCKET};
 #[cfg(target_os = "linux")]
 const SO_BINDTODEVICE: c_int = 25;
 
-const DHCP_SERVER_PORT: u16 = 67;
+const PXE_BOOT_SERVER_PORT: u16 = 4011;
+
+/// Broadcast bit of the BOOTP flags field
+const BROADCAST_FLAG: u16 = 0x8000;
+
+// Relay agent information (option 82) sub-options
+const RELAY_CIRCUIT_ID: u8 = 1;
+const RELAY_REMOTE_ID: u8 = 2;
+
+/// Addresses probed for a single client before giving up until it retries
+const MAX_CONFLICT_PROBES: usize = 4;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+/// Lease time of BOOTP clients, which never give their address back
+const INFINITE_LEASE: u32 = u32::MAX;
+
+/// Smallest BOOTP message, replies are padded up to it (RFC 1542, section 2.1)
+const BOOTP_MIN_LEN: usize = 300;
+
+// Offsets of the BOOTP header fields that can carry overloaded options
+const SNAME_FIELD: std::ops::Range<usize> = 44..108;
+const FILE_FIELD: std::ops::Range<usize> = 108..236;
+const COOKIE_FIELD: std::ops::Range<usize> = 236..240;
+const OPTIONS_OFFSET: usize = 240;
+
+/// Marks the start of the options in the BOOTP vendor area (RFC 2131, section 3)
+pub const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+/// Largest IP datagram every client must accept (RFC 2131, section 2)
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 576;
+
+/// IP and UDP headers, counted in option 57 and the 576 bytes above
+const IP_UDP_HEADERS: usize = 28;
 
 #[derive(Debug, Clone)]
 pub struct DhcpMessage {
@@ -28,6 +84,10 @@ pub struct DhcpMessage {
     pub siaddr: Ipv4Addr,
     pub giaddr: Ipv4Addr,
     pub chaddr: [u8; 16],
+    /// Server host name, NUL terminated
+    pub sname: [u8; 64],
+    /// Boot file name, NUL terminated
+    pub file: [u8; 128],
     pub options: Vec<u8>,
 }
 
@@ -46,12 +106,24 @@ impl DhcpMessage {
         let mut chaddr = [0u8; 16];
         chaddr[..16].copy_from_slice(&data[28..44]);
 
-        let options = if data.len() > 240 {
-            data[240..].to_vec()
+        // Without the magic cookie the vendor area holds no options, as
+        // with some BOOTP clients
+        let (options, overload) = if data[COOKIE_FIELD] == MAGIC_COOKIE {
+            Self::read_options(data).map_err(|e| e.to_string())?
         } else {
-            Vec::new()
+            (vec![OPTION_END], 0)
         };
 
+        // Fields holding overloaded options carry no name
+        let mut sname = [0u8; 64];
+        let mut file = [0u8; 128];
+        if overload & OVERLOAD_SNAME == 0 {
+            sname.copy_from_slice(&data[SNAME_FIELD]);
+        }
+        if overload & OVERLOAD_FILE == 0 {
+            file.copy_from_slice(&data[FILE_FIELD]);
+        }
+
         Ok(DhcpMessage {
             op: data[0],
             htype: data[1],
@@ -65,10 +137,52 @@ impl DhcpMessage {
             giaddr,
             siaddr,
             chaddr,
+            sname,
+            file,
             options,
         })
     }
 
+    /// Collect the options of a message, including those overloaded into the
+    /// file and sname fields (option 52), in that order. Also returns the
+    /// overload flags.
+    fn read_options(data: &[u8]) -> Result<(Vec<u8>, u8), OptionError> {
+        let options = &data[OPTIONS_OFFSET..];
+        let mut overload = None;
+        for option in raw_options(options) {
+            let (code, value) = option?;
+            if code == OPTION_OVERLOAD {
+                overload = Some(value.first().copied().unwrap_or(0));
+            }
+        }
+
+        let mut areas = vec![options];
+        match overload {
+            None => return Ok((options.to_vec(), 0)),
+            Some(OVERLOAD_FILE) => areas.push(&data[FILE_FIELD]),
+            Some(OVERLOAD_SNAME) => areas.push(&data[SNAME_FIELD]),
+            Some(OVERLOAD_BOTH) => {
+                areas.push(&data[FILE_FIELD]);
+                areas.push(&data[SNAME_FIELD]);
+            }
+            Some(_) => return Err(OptionError::InvalidValue(OPTION_OVERLOAD)),
+        }
+
+        let mut merged = Vec::new();
+        for area in areas {
+            for option in raw_options(area) {
+                let (code, value) = option?;
+                if code != OPTION_OVERLOAD {
+                    merged.push(code);
+                    merged.push(value.len() as u8);
+                    merged.extend_from_slice(value);
+                }
+            }
+        }
+        merged.push(OPTION_END);
+        Ok((merged, overload.unwrap_or(0)))
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut data = vec![0u8; 240];
         data[0] = self.op;
@@ -83,30 +197,129 @@ impl DhcpMessage {
         data[20..24].copy_from_slice(&self.siaddr.octets());
         data[24..28].copy_from_slice(&self.giaddr.octets());
         data[28..44].copy_from_slice(&self.chaddr);
+        data[SNAME_FIELD].copy_from_slice(&self.sname);
+        data[FILE_FIELD].copy_from_slice(&self.file);
+        data[COOKIE_FIELD].copy_from_slice(&MAGIC_COOKIE);
         data.extend_from_slice(&self.options);
+        if self.op == BOOTREPLY {
+            data.resize(data.len().max(BOOTP_MIN_LEN), OPTION_PAD);
+        }
         data
     }
 
-    pub fn get_option(&self, option: u8) -> Option<&[u8]> {
-        let mut i = 0;
-        while i < self.options.len() {
-            if self.options[i] == 255 {
-                break; // End option
-            }
-            if self.options[i] == option && i + 1 < self.options.len() {
-                let len = self.options[i + 1] as usize;
-                if i + 2 + len <= self.options.len() {
-                    return Some(&self.options[i + 2..i + 2 + len]);
-                }
+    /// Serialize the message into at most `max_size` bytes, moving options
+    /// that don't fit into the file and sname fields (option 52). Fields
+    /// holding a name are only given up when the options don't fit otherwise;
+    /// boot replies carry the same information in options 66 and 67.
+    pub fn to_bytes_with_max_size(&self, max_size: usize) -> Result<Vec<u8>, OptionError> {
+        let mut data = self.to_bytes();
+        if data.len() <= max_size {
+            return Ok(data);
+        }
+
+        let areas = match self.overload_areas(max_size, false) {
+            Err(OptionError::TooLong) => self.overload_areas(max_size, true)?,
+            areas => areas?,
+        };
+
+        let [main, file, sname] = areas;
+        let overload = match (file.is_empty(), sname.is_empty()) {
+            (false, true) => OVERLOAD_FILE,
+            (true, false) => OVERLOAD_SNAME,
+            _ => OVERLOAD_BOTH,
+        };
+        data.truncate(OPTIONS_OFFSET);
+        data.extend_from_slice(&[OPTION_OVERLOAD, 1, overload]);
+        data.extend_from_slice(&main);
+        data.push(OPTION_END);
+        for (field, options) in [(FILE_FIELD, file), (SNAME_FIELD, sname)] {
+            if !options.is_empty() {
+                data[field.clone()].fill(0);
+                data[field.start..field.start + options.len()].copy_from_slice(&options);
+                data[field.start + options.len()] = OPTION_END;
             }
-            if i + 1 < self.options.len() {
-                let len = self.options[i + 1] as usize;
-                i += 2 + len;
+        }
+        if self.op == BOOTREPLY {
+            data.resize(data.len().max(BOOTP_MIN_LEN), OPTION_PAD);
+        }
+        Ok(data)
+    }
+
+    /// Split the options over the options, file and sname areas
+    fn overload_areas(
+        &self,
+        max_size: usize,
+        replace_names: bool,
+    ) -> Result<[Vec<u8>; 3], OptionError> {
+        let available = |field: &[u8], len: usize| {
+            if replace_names || field.iter().all(|b| *b == 0) {
+                len - 1
             } else {
-                break;
+                0
+            }
+        };
+        // Room left in each area once the end option (and option 52 in the
+        // options field) are accounted for
+        let capacity = [
+            max_size.saturating_sub(OPTIONS_OFFSET + 3 + 1),
+            available(&self.file, FILE_FIELD.len()),
+            available(&self.sname, SNAME_FIELD.len()),
+        ];
+        let mut areas: [Vec<u8>; 3] = Default::default();
+        let mut area = 0;
+        for option in raw_options(&self.options) {
+            let (code, value) = option?;
+            let len = 2 + value.len();
+            while areas[area].len() + len > capacity[area] {
+                area += 1;
+                if area == areas.len() {
+                    return Err(OptionError::TooLong);
+                }
             }
+            areas[area].push(code);
+            areas[area].push(value.len() as u8);
+            areas[area].extend_from_slice(value);
+        }
+        Ok(areas)
+    }
+
+    /// Put the boot file name and its server in the BOOTP header, for ROMs
+    /// that don't look at options 66 and 67. Names that don't fit are left out.
+    pub fn set_boot_info(&mut self, filename: &str, server: Ipv4Addr) {
+        set_header_string(&mut self.file, filename);
+        if !server.is_unspecified() {
+            set_header_string(&mut self.sname, &server.to_string());
         }
-        None
+    }
+
+    /// Raw value of the first instance of an option
+    pub fn get_option(&self, option: u8) -> Option<&[u8]> {
+        raw_options(&self.options)
+            .map_while(Result::ok)
+            .find(|(code, _)| *code == option)
+            .map(|(_, value)| value)
+    }
+
+    /// Decoded value of an option, with split options joined. Malformed
+    /// options are treated as absent.
+    pub fn option(&self, code: u8) -> Option<DhcpOption> {
+        let mut value: Option<Vec<u8>> = None;
+        for (_, data) in raw_options(&self.options)
+            .map_while(Result::ok)
+            .filter(|(c, _)| *c == code)
+        {
+            value.get_or_insert_with(Vec::new).extend_from_slice(data);
+        }
+        DhcpOption::decode(code, &value?).ok()
+    }
+
+    /// All options of the message, decoded, with split options joined
+    pub fn decode_options(&self) -> Result<Vec<DhcpOption>, OptionError> {
+        options::decode_options(&self.options)
+    }
+
+    pub fn set_options(&mut self, options: &[DhcpOption]) {
+        self.options = encode_options(options);
     }
 
     pub fn get_message_type(&self) -> Option<u8> {
@@ -114,6 +327,88 @@ impl DhcpMessage {
             .map(|v| if !v.is_empty() { v[0] } else { 0 })
     }
 
+    pub fn get_hostname(&self) -> Option<String> {
+        self.get_option(12)
+            .map(|v| {
+                String::from_utf8_lossy(v)
+                    .trim_end_matches('\0')
+                    .to_string()
+            })
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Add an option before the end marker
+    pub fn append_option(&mut self, option: &DhcpOption) {
+        if self.options.last() == Some(&OPTION_END) {
+            self.options.pop();
+        }
+        option.encode(&mut self.options);
+        self.options.push(OPTION_END);
+    }
+
+    /// Vendor class identifier (option 60)
+    pub fn get_vendor_class(&self) -> Option<&[u8]> {
+        self.get_option(60)
+    }
+
+    pub fn is_pxe_client(&self) -> bool {
+        self.get_vendor_class()
+            .is_some_and(|class| class.starts_with(PXE_CLIENT_CLASS))
+    }
+
+    /// Requests from iPXE, which sets user class (option 77) "iPXE" and sends
+    /// its feature list in the encapsulated option 175
+    pub fn is_ipxe(&self) -> bool {
+        let user_class = self.get_option(77).is_some_and(|class| {
+            // Either the raw string or an RFC 3004 length-prefixed entry
+            class == IPXE_USER_CLASS || class.get(1..) == Some(IPXE_USER_CLASS)
+        });
+        user_class || self.get_option(175).is_some()
+    }
+
+    /// UEFI HTTP Boot firmware, which expects a URL as its boot file
+    pub fn is_http_client(&self) -> bool {
+        self.get_vendor_class()
+            .is_some_and(|class| class.starts_with(HTTP_CLIENT_CLASS))
+    }
+
+    pub fn get_requested_ip(&self) -> Option<Ipv4Addr> {
+        match self.option(50)? {
+            DhcpOption::RequestedIpAddress(ip) => Some(ip),
+            _ => None,
+        }
+    }
+
+    pub fn get_server_id(&self) -> Option<Ipv4Addr> {
+        match self.option(54)? {
+            DhcpOption::ServerIdentifier(ip) => Some(ip),
+            _ => None,
+        }
+    }
+
+    /// Largest DHCP message the client accepts. Option 57 gives the size of
+    /// the whole IP datagram, never below the 576 bytes every client handles.
+    pub fn max_message_size(&self) -> usize {
+        let datagram = match self.option(57) {
+            Some(DhcpOption::MaxMessageSize(size)) => (size as usize).max(DEFAULT_MAX_MESSAGE_SIZE),
+            _ => DEFAULT_MAX_MESSAGE_SIZE,
+        };
+        datagram - IP_UDP_HEADERS
+    }
+
+    pub fn get_requested_lease_time(&self) -> Option<u32> {
+        match self.option(51)? {
+            DhcpOption::LeaseTime(lease_time) => Some(lease_time),
+            _ => None,
+        }
+    }
+
+    pub fn client_mac(&self) -> [u8; 6] {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&self.chaddr[..6]);
+        mac
+    }
+
     pub fn get_client_arch(&self) -> Option<u16> {
         self.get_option(93).map(|v| {
             if v.len() >= 2 {
@@ -128,85 +423,292 @@ impl DhcpMessage {
 pub struct DhcpServer {
     config: Arc<DhcpConfig>,
     ip_pool: IpPool,
+    ifindex: Option<u32>,
+    scopes: Vec<Scope>,
+    reservations: Reservations,
+    access: AccessControl,
+    pxe_menu: Option<PxeMenu>,
+    http: Option<HttpConfig>,
+    probe: Option<Arc<ConflictProbe>>,
+    ddns: Option<Arc<DnsUpdater>>,
+    /// Opened on first use, `None` without CAP_NET_RAW
+    #[cfg(target_os = "linux")]
+    frames: OnceLock<Option<FrameSocket>>,
 }
 
-struct IpPool {
-    start: Ipv4Addr,
-    end: Ipv4Addr,
-    current: std::sync::Mutex<Ipv4Addr>,
-    leases: std::sync::Mutex<std::collections::HashMap<[u8; 6], Ipv4Addr>>,
+/// Where a reply goes (RFC 2131, section 4.1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Destination {
+    Udp(SocketAddrV4),
+    /// Unicast to a client without an address yet, in a frame to its
+    /// hardware address
+    Frame {
+        mac: [u8; 6],
+        src: SocketAddrV4,
+        dest: SocketAddrV4,
+    },
 }
 
-impl IpPool {
-    fn new(start: Ipv4Addr, end: Ipv4Addr) -> Self {
-        IpPool {
-            start,
-            end,
-            current: std::sync::Mutex::new(start),
-            leases: std::sync::Mutex::new(std::collections::HashMap::new()),
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Destination::Udp(addr) => write!(f, "{}", addr),
+            Destination::Frame { mac, dest, .. } => write!(f, "{} ({})", dest, format_mac(mac)),
         }
     }
+}
+
+/// An additional subnet, served on its own interface or through a relay agent
+struct Scope {
+    name: String,
+    ifindex: Option<u32>,
+    network: Ipv4Addr,
+    mask: Ipv4Addr,
+    circuit_id: Option<Vec<u8>>,
+    remote_id: Option<Vec<u8>>,
+    config: Arc<DhcpConfig>,
+    ip_pool: IpPool,
+}
+
+impl Scope {
+    fn new(
+        base: &DhcpConfig,
+        scope: &ScopeConfig,
+        leases: Arc<LeaseTable>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let start = scope.ip_pool_start.parse::<Ipv4Addr>()?;
+        let end = scope.ip_pool_end.parse::<Ipv4Addr>()?;
+        let mask = scope.subnet_mask.parse::<Ipv4Addr>()?;
+
+        // The scope's options replace the subnet specific ones of `[dhcp]`
+        let mut config = base.clone();
+        config.scopes.clear();
+        config.ip_pool_start = scope.ip_pool_start.clone();
+        config.ip_pool_end = scope.ip_pool_end.clone();
+        config.subnet_mask = scope.subnet_mask.clone();
+        config.gateway = scope.gateway.clone();
+        config.interface = scope.interface.clone();
+        config.exclude = scope.exclude.clone();
+        if let Some(ref dns_servers) = scope.dns_servers {
+            config.dns_servers = dns_servers.clone();
+        }
+        if scope.domain_name.is_some() {
+            config.domain_name = scope.domain_name.clone();
+        }
+        if scope.hostname_pattern.is_some() {
+            config.hostname_pattern = scope.hostname_pattern.clone();
+        }
+        if let Some(ref next_server) = scope.next_server {
+            config.next_server = next_server.clone();
+        }
+        if let Some(ref protocols) = scope.protocols {
+            config.protocols = protocols.clone();
+        }
+        if let Some(lease_time) = scope.default_lease_time {
+            config.default_lease_time = lease_time;
+        }
+        if let Some(lease_time) = scope.max_lease_time {
+            config.max_lease_time = lease_time;
+        }
+        for option in &scope.options {
+            config.options.retain(|o| o.code != option.code);
+            config.options.push(option.clone());
+        }
+        ProtocolHandler::validate(&config.protocols)?;
+        if let Some(ref pattern) = config.hostname_pattern {
+            hostname::check_pattern(pattern)?;
+        }
+
+        let name = scope
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", start, end));
+        DhcpOptions::check_config(&config).map_err(|e| format!("Scope {}: {}", name, e))?;
+        let ifindex =
+            configured_ifindex(&scope.interface).map_err(|e| format!("Scope {}: {}", name, e))?;
 
-    fn allocate(&self, mac: [u8; 6]) -> Option<Ipv4Addr> {
-        let mut leases = self.leases.lock().unwrap();
+        let ip_pool = address_pool(&config, start, end, leases)
+            .map_err(|e| format!("Scope {}: {}", name, e))?;
 
-        // Check if MAC already has a lease
-        if let Some(&ip) = leases.get(&mac) {
-            return Some(ip);
+        Ok(Scope {
+            name,
+            ifindex,
+            network: network(start, mask),
+            mask,
+            circuit_id: scope
+                .relay_circuit_id
+                .as_ref()
+                .map(|id| id.as_bytes().to_vec()),
+            remote_id: scope
+                .relay_remote_id
+                .as_ref()
+                .map(|id| id.as_bytes().to_vec()),
+            config: Arc::new(config),
+            ip_pool,
+        })
+    }
+
+    /// Whether a request relayed by `giaddr` belongs to this scope
+    fn matches(&self, giaddr: Ipv4Addr, relay_info: Option<&[u8]>) -> bool {
+        if network(giaddr, self.mask) != self.network {
+            return false;
         }
+        let suboption = |code| relay_info.and_then(|info| relay_suboption(info, code));
+        self.circuit_id
+            .as_deref()
+            .is_none_or(|id| suboption(RELAY_CIRCUIT_ID) == Some(id))
+            && self
+                .remote_id
+                .as_deref()
+                .is_none_or(|id| suboption(RELAY_REMOTE_ID) == Some(id))
+    }
+}
 
-        // Allocate new IP
-        let mut current = self.current.lock().unwrap();
-        let mut candidate = *current;
+/// Interface name from the config, where an empty string means none
+pub(crate) fn configured_interface(interface: &Option<String>) -> Option<&str> {
+    interface.as_deref().filter(|name| !name.is_empty())
+}
 
-        loop {
-            if candidate > self.end {
-                candidate = self.start;
-            }
-
-            // Check if IP is already leased
-            let is_leased = leases.values().any(|&ip| ip == candidate);
-            if !is_leased {
-                *current = {
-                    let octets = candidate.octets();
-                    let last = octets[3].wrapping_add(1);
-                    if last == 0 {
-                        Ipv4Addr::new(octets[0], octets[1], octets[2].wrapping_add(1), 0)
-                    } else {
-                        Ipv4Addr::new(octets[0], octets[1], octets[2], last)
-                    }
-                };
-                leases.insert(mac, candidate);
-                return Some(candidate);
-            }
+/// Index of the configured interface, which has to exist
+fn configured_ifindex(interface: &Option<String>) -> Result<Option<u32>, String> {
+    configured_interface(interface)
+        .map(|name| interface_index(name).ok_or_else(|| format!("Unknown interface {}", name)))
+        .transpose()
+}
 
-            let octets = candidate.octets();
-            let last = octets[3].wrapping_add(1);
-            candidate = if last == 0 {
-                Ipv4Addr::new(octets[0], octets[1], octets[2].wrapping_add(1), 0)
-            } else {
-                Ipv4Addr::new(octets[0], octets[1], octets[2], last)
-            };
+/// Index of the network interface called `name`
+pub(crate) fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
 
-            if candidate == *current {
-                return None; // Pool exhausted
-            }
+/// The pool of `start..=end`, less the ranges excluded by `config`
+fn address_pool(
+    config: &DhcpConfig,
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+    leases: Arc<LeaseTable>,
+) -> Result<IpPool, String> {
+    let mut ip_pool = IpPool::new(start, end, leases)?;
+    for range in &config.exclude {
+        let (first, last) = pool::parse_range(range)?;
+        ip_pool.exclude_range(first, last);
+    }
+    Ok(ip_pool)
+}
+
+fn network(ip: Ipv4Addr, mask: Ipv4Addr) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(ip) & u32::from(mask))
+}
+
+/// Find a sub-option of the relay agent information option (82)
+fn relay_suboption(info: &[u8], code: u8) -> Option<&[u8]> {
+    let mut i = 0;
+    while i + 1 < info.len() {
+        let len = info[i + 1] as usize;
+        let data = info.get(i + 2..i + 2 + len)?;
+        if info[i] == code {
+            return Some(data);
         }
+        i += 2 + len;
     }
+    None
 }
 
 impl DhcpServer {
     pub fn new(config: DhcpConfig) -> Result<Self, Box<dyn std::error::Error>> {
         let start = config.ip_pool_start.parse::<Ipv4Addr>()?;
         let end = config.ip_pool_end.parse::<Ipv4Addr>()?;
+        ProtocolHandler::validate(&config.protocols)?;
+        if let Some(ref pattern) = config.hostname_pattern {
+            hostname::check_pattern(pattern)?;
+        }
+        DhcpOptions::check_config(&config)?;
+        let store = LeaseStore::new(config.lease_file.as_ref().map(PathBuf::from));
+        let leases = Arc::new(LeaseTable::load(store)?);
+        let mut ip_pool = address_pool(&config, start, end, Arc::clone(&leases))?;
+        let mut scopes = config
+            .scopes
+            .iter()
+            .map(|scope| Scope::new(&config, scope, Arc::clone(&leases)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let reservations = Reservations::from_config(&config.hosts)?;
+        for ip in reservations.addresses() {
+            ip_pool.exclude(ip);
+            for scope in scopes.iter_mut() {
+                scope.ip_pool.exclude(ip);
+            }
+        }
+
+        let access = AccessControl::from_config(&config.access)?;
+        let ddns = config
+            .ddns
+            .as_ref()
+            .map(|ddns| DnsUpdater::from_config(ddns, config.domain_name.as_deref()))
+            .transpose()?;
+        let ifindex = configured_ifindex(&config.interface)?;
+
+        let pxe_menu = match config.pxe_menu {
+            Some(ref menu) => {
+                let next_server = config
+                    .next_server
+                    .parse()
+                    .map_err(|_| format!("Invalid next_server: {}", config.next_server))?;
+                Some(PxeMenu::from_config(menu, next_server)?)
+            }
+            None => None,
+        };
 
         Ok(DhcpServer {
+            ip_pool,
+            ifindex,
+            scopes,
+            reservations,
+            access,
+            pxe_menu,
+            http: None,
+            probe: config.conflict_detection.then(|| {
+                Arc::new(ConflictProbe::new(Duration::from_millis(
+                    config.conflict_probe_timeout_ms,
+                )))
+            }),
+            ddns: ddns.map(Arc::new),
+            #[cfg(target_os = "linux")]
+            frames: OnceLock::new(),
             config: Arc::new(config),
-            ip_pool: IpPool::new(start, end),
         })
     }
 
+    /// Serve UEFI HTTP Boot clients from the built-in HTTP server
+    pub fn with_http(mut self, http: HttpConfig) -> Self {
+        self.http = Some(http);
+        self
+    }
+
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let udp_socket = self.bind_socket(self.config.port)?;
+        log::info!("DHCP server listening on port {}", self.config.port);
+
+        if self.config.proxy_dhcp || self.pxe_menu.is_some() {
+            let boot_socket = self.bind_socket(PXE_BOOT_SERVER_PORT)?;
+            if self.config.proxy_dhcp {
+                log::info!("Running in proxyDHCP mode");
+            }
+            log::info!("PXE boot server listening on port {}", PXE_BOOT_SERVER_PORT);
+            tokio::join!(
+                self.serve_dhcp(&udp_socket),
+                self.serve_boot_server(&boot_socket)
+            );
+        } else {
+            self.serve_dhcp(&udp_socket).await;
+        }
+
+        Ok(())
+    }
+
+    fn bind_socket(&self, port: u16) -> Result<UdpSocket, Box<dyn std::error::Error>> {
         // Create raw socket for DHCP
         let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
 
@@ -214,9 +716,14 @@ impl DhcpServer {
         socket.set_broadcast(true)?;
         socket.set_reuse_address(true)?;
 
-        // Bind to specific network interface if configured
+        // Report the receiving interface of each request, to pick its scope
+        #[cfg(target_os = "linux")]
+        pktinfo::enable(&socket)?;
+
+        // Bind to specific network interface if configured. With per-interface
+        // scopes the socket listens everywhere and IP_PKTINFO tells them apart.
         #[cfg(target_os = "linux")]
-        if let Some(ref interface) = self.config.interface {
+        if let Some(interface) = self.bind_interface() {
             use std::os::unix::io::AsRawFd;
             let interface_bytes = interface.as_bytes();
             let interface_cstr = std::ffi::CString::new(interface_bytes)?;
@@ -241,40 +748,59 @@ impl DhcpServer {
             log::info!("DHCP server bound to interface: {}", interface);
         }
 
-        // Bind to DHCP server port
-        let addr = SocketAddr::from(([0, 0, 0, 0], DHCP_SERVER_PORT));
+        // Bind to the requested port
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
         socket.bind(&addr.into())?;
 
         // Convert to tokio UdpSocket
         socket.set_nonblocking(true)?;
         let std_socket = std::net::UdpSocket::from(socket);
-        let udp_socket = UdpSocket::from_std(std_socket)?;
+        Ok(UdpSocket::from_std(std_socket)?)
+    }
 
-        log::info!("DHCP server listening on port {}", DHCP_SERVER_PORT);
+    /// Interface to bind the socket to, unless scopes are served on several
+    fn bind_interface(&self) -> Option<&str> {
+        if self.scopes.iter().any(|scope| scope.ifindex.is_some()) {
+            return None;
+        }
+        configured_interface(&self.config.interface)
+    }
 
+    async fn serve_dhcp(&self, udp_socket: &UdpSocket) {
         let mut buf = vec![0u8; 1500];
-        let config = Arc::clone(&self.config);
-        let ip_pool = &self.ip_pool;
 
         loop {
-            match udp_socket.recv_from(&mut buf).await {
-                Ok((size, _peer)) => {
+            #[cfg(target_os = "linux")]
+            let received = pktinfo::recv_from(udp_socket, &mut buf).await;
+            #[cfg(not(target_os = "linux"))]
+            let received = udp_socket
+                .recv_from(&mut buf)
+                .await
+                .map(|(size, peer)| (size, peer, None));
+
+            match received {
+                Ok((size, _peer, ifindex)) => {
                     let data = &buf[..size];
                     if let Ok(request) = DhcpMessage::from_bytes(data) {
-                        if let Some((response, should_broadcast)) =
-                            self.handle_request(&request, ip_pool, &config).await
-                        {
-                            let response_bytes = response.to_bytes();
-                            // Always send DHCP responses to broadcast address (255.255.255.255:68)
-                            // This is required because clients may not have an IP address yet
-                            let dest_addr = SocketAddr::from(([255, 255, 255, 255], 68));
-                            if let Err(e) = udp_socket.send_to(&response_bytes, dest_addr).await {
+                        if let Some(response) = self.process(&request, ifindex).await {
+                            let response_bytes =
+                                match response.to_bytes_with_max_size(request.max_message_size()) {
+                                    Ok(bytes) => bytes,
+                                    Err(e) => {
+                                        log::error!("Cannot encode DHCP response: {}", e);
+                                        continue;
+                                    }
+                                };
+                            let dest_addr = self.reply_destination(&request, &response);
+                            if let Err(e) = self
+                                .send_reply(udp_socket, &response_bytes, dest_addr, ifindex)
+                                .await
+                            {
                                 log::error!("Failed to send DHCP response: {}", e);
                             } else {
-                                let msg_type_name = if should_broadcast { "Offer" } else { "ACK" };
                                 log::info!(
-                                    "Sent DHCP {} to broadcast address {} ({} bytes)",
-                                    msg_type_name,
+                                    "Sent DHCP {} to {} ({} bytes)",
+                                    message_type_name(response.get_message_type().unwrap_or(0)),
                                     dest_addr,
                                     response_bytes.len()
                                 );
@@ -289,123 +815,2136 @@ impl DhcpServer {
         }
     }
 
-    async fn handle_request(
-        &self,
-        request: &DhcpMessage,
-        ip_pool: &IpPool,
-        config: &Arc<DhcpConfig>,
-    ) -> Option<(DhcpMessage, bool)> {
-        let msg_type = request.get_message_type()?;
-
-        // Handle Discover (1) and Request (3)
-        if msg_type != 1 && msg_type != 3 {
-            return None;
+    fn reply_destination(&self, request: &DhcpMessage, response: &DhcpMessage) -> Destination {
+        let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, self.config.client_port);
+        if !response.giaddr.is_unspecified() {
+            // Relayed request: the relay agent forwards the reply
+            return Destination::Udp(SocketAddrV4::new(response.giaddr, self.config.port));
         }
-
-        if msg_type == 1 {
-            let mac_str = format!(
-                "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                request.chaddr[0],
-                request.chaddr[1],
-                request.chaddr[2],
-                request.chaddr[3],
-                request.chaddr[4],
-                request.chaddr[5]
-            );
-            log::info!("Received DHCP Discover from MAC: {}", mac_str);
-        } else {
-            let mac_str = format!(
-                "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-                request.chaddr[0],
-                request.chaddr[1],
-                request.chaddr[2],
-                request.chaddr[3],
-                request.chaddr[4],
-                request.chaddr[5]
-            );
-            log::info!("Received DHCP Request from MAC: {}", mac_str);
+        if response.get_message_type() == Some(DHCP_NAK) {
+            // The client may still think it owns the address
+            return Destination::Udp(broadcast);
+        }
+        if !request.ciaddr.is_unspecified() {
+            // Renewing or informing clients are reachable at their address
+            return Destination::Udp(SocketAddrV4::new(request.ciaddr, self.config.client_port));
+        }
+        let server_id = response
+            .get_option(54)
+            .and_then(|id| <[u8; 4]>::try_from(id).ok())
+            .map(Ipv4Addr::from);
+        match server_id {
+            Some(server_id)
+                if request.flags & BROADCAST_FLAG == 0
+                    && !response.yiaddr.is_unspecified()
+                    && request.htype == 1
+                    && request.hlen == 6 =>
+            {
+                Destination::Frame {
+                    mac: request.client_mac(),
+                    src: SocketAddrV4::new(server_id, self.config.port),
+                    dest: SocketAddrV4::new(response.yiaddr, self.config.client_port),
+                }
+            }
+            // Clients asking for broadcasts, or without an address to unicast to
+            _ => Destination::Udp(broadcast),
         }
+    }
 
-        let mac = {
-            let mut mac = [0u8; 6];
-            mac.copy_from_slice(&request.chaddr[..6]);
-            mac
+    /// Send a reply, out of the interface the request came in on when known.
+    /// Frames need that interface and a packet socket, without them the
+    /// client gets a broadcast instead.
+    async fn send_reply(
+        &self,
+        udp_socket: &UdpSocket,
+        data: &[u8],
+        dest: Destination,
+        ifindex: Option<u32>,
+    ) -> std::io::Result<usize> {
+        let dest_addr = match dest {
+            Destination::Udp(addr) => addr,
+            Destination::Frame { mac, src, dest } => {
+                #[cfg(target_os = "linux")]
+                if let (Some(frames), Some(ifindex)) = (self.frame_socket(), ifindex) {
+                    return frames.send_to(data, src, dest, mac, ifindex).await;
+                }
+                #[cfg(not(target_os = "linux"))]
+                let _ = (mac, src);
+                SocketAddrV4::new(Ipv4Addr::BROADCAST, dest.port())
+            }
         };
 
-        let client_ip = ip_pool.allocate(mac)?;
-        let client_arch = request.get_client_arch();
+        #[cfg(target_os = "linux")]
+        if let Some(ifindex) = ifindex {
+            return pktinfo::send_to(udp_socket, data, dest_addr, ifindex).await;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = ifindex;
+        udp_socket.send_to(data, dest_addr).await
+    }
 
-        let protocol = ProtocolHandler::select_protocol(&config.protocols, client_arch)?;
-        let filename = ProtocolHandler::get_boot_filename(protocol, &config.protocols);
+    #[cfg(target_os = "linux")]
+    fn frame_socket(&self) -> Option<&FrameSocket> {
+        self.frames
+            .get_or_init(|| match FrameSocket::new() {
+                Ok(frames) => Some(frames),
+                Err(e) => {
+                    log::warn!(
+                        "Cannot open packet socket ({}), replies to clients without an address are broadcast",
+                        e
+                    );
+                    None
+                }
+            })
+            .as_ref()
+    }
 
-        log::info!(
-            "Selected protocol: {:?}, boot filename: {}",
-            protocol,
-            filename
-        );
-        log::info!("Allocated IP: {} for client", client_ip);
+    /// PXE boot server discovery: clients unicast their DHCPREQUEST to port
+    /// 4011 and expect the DHCPACK back at their source address
+    async fn serve_boot_server(&self, udp_socket: &UdpSocket) {
+        let mut buf = vec![0u8; 1500];
+        let config = Arc::clone(&self.config);
 
-        // Determine response message type: Discover -> Offer (2), Request -> ACK (5)
-        let response_msg_type = if msg_type == 1 { 2 } else { 5 };
+        loop {
+            match udp_socket.recv_from(&mut buf).await {
+                Ok((size, peer)) => {
+                    let data = &buf[..size];
+                    if let Ok(request) = DhcpMessage::from_bytes(data) {
+                        if let Some(response) = self.handle_boot_server_request(&request, &config) {
+                            let response_bytes = match response
+                                .to_bytes_with_max_size(request.max_message_size())
+                            {
+                                Ok(bytes) => bytes,
+                                Err(e) => {
+                                    log::error!("Cannot encode PXE boot server response: {}", e);
+                                    continue;
+                                }
+                            };
+                            if let Err(e) = udp_socket.send_to(&response_bytes, peer).await {
+                                log::error!("Failed to send PXE boot server response: {}", e);
+                            } else {
+                                log::info!(
+                                    "Sent PXE boot server ACK to {} ({} bytes)",
+                                    peer,
+                                    response_bytes.len()
+                                );
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("PXE boot server receive error: {}", e);
+                }
+            }
+        }
+    }
 
-        let mut response = DhcpMessage {
-            op: 2, // BOOTREPLY
-            htype: request.htype,
-            hlen: request.hlen,
-            hops: 0,
-            xid: request.xid,
-            secs: 0,
-            flags: request.flags,
-            ciaddr: Ipv4Addr::UNSPECIFIED,
-            yiaddr: client_ip,
-            siaddr: config.next_server.parse().ok()?,
-            giaddr: Ipv4Addr::UNSPECIFIED,
-            chaddr: request.chaddr,
-            options: Vec::new(),
-        };
+    /// Handle a request received on the DHCP port: pick the scope it belongs
+    /// to and route relayed replies back through the relay agent
+    async fn process(&self, request: &DhcpMessage, ifindex: Option<u32>) -> Option<DhcpMessage> {
+        if !self.is_allowed(request) {
+            return None;
+        }
+        let (ip_pool, config) = self.select_scope(request, ifindex)?;
+        let mut response = self.handle_request(request, ip_pool, config).await?;
 
-        let mut options = DhcpOptions::build_options(config, client_ip, response_msg_type);
-        let filename_options = DhcpOptions::build_filename_option(&filename);
-        options.pop(); // Remove end marker
-        options.extend_from_slice(&filename_options);
+        if !request.giaddr.is_unspecified() {
+            response.giaddr = request.giaddr;
+            // The relay must broadcast NAKs onto the client's segment
+            if response.get_message_type() == Some(DHCP_NAK) {
+                response.flags |= BROADCAST_FLAG;
+            }
+        }
 
-        response.options = options;
+        // Relay agent information is echoed back unchanged (RFC 3046)
+        if let Some(relay_info) = request.get_option(82) {
+            response.append_option(&DhcpOption::RelayAgentInformation(relay_info.to_vec()));
+        }
 
-        // Broadcast response for Discover, and for Request if client doesn't have IP
-        let should_broadcast = msg_type == 1 || request.ciaddr == Ipv4Addr::UNSPECIFIED;
+        Some(response)
+    }
 
-        Some((response, should_broadcast))
+    /// Whether the access lists let the client be answered
+    fn is_allowed(&self, request: &DhcpMessage) -> bool {
+        let known = self.reservations.find(request).is_some();
+        match self.access.check(&Client::from_dhcp(request), known) {
+            Ok(()) => true,
+            Err(reason) => {
+                log::debug!(
+                    "Ignoring MAC: {}, {}",
+                    format_mac(&request.client_mac()),
+                    reason
+                );
+                false
+            }
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Pick the scope for a request: relayed requests by relay address, local
+    /// ones by the interface they were received on
+    fn select_scope(
+        &self,
+        request: &DhcpMessage,
+        ifindex: Option<u32>,
+    ) -> Option<(&IpPool, &Arc<DhcpConfig>)> {
+        if request.giaddr.is_unspecified() {
+            if let Some(ifindex) = ifindex {
+                if let Some(scope) = self
+                    .scopes
+                    .iter()
+                    .find(|scope| scope.ifindex == Some(ifindex))
+                {
+                    return Some((&scope.ip_pool, &scope.config));
+                }
+                if self.ifindex.is_some_and(|index| index != ifindex) {
+                    log::debug!("Ignoring request received on interface {}", ifindex);
+                    return None;
+                }
+            }
+            return Some((&self.ip_pool, &self.config));
+        }
 
-    #[test]
-    fn test_dhcp_message_parsing() {
-        let mut data = vec![0u8; 240];
-        data[0] = 1; // BOOTREQUEST
-        data[1] = 1; // Ethernet
-        data[2] = 6; // MAC length
-        data[4..8].copy_from_slice(&0x12345678u32.to_be_bytes());
+        let relay_info = request.get_option(82);
+        if let Some(scope) = self
+            .scopes
+            .iter()
+            .find(|scope| scope.matches(request.giaddr, relay_info))
+        {
+            log::debug!(
+                "Relayed request via {} matched scope {}",
+                request.giaddr,
+                scope.name
+            );
+            return Some((&scope.ip_pool, &scope.config));
+        }
 
-        let msg = DhcpMessage::from_bytes(&data).unwrap();
-        assert_eq!(msg.op, 1);
-        assert_eq!(msg.xid, 0x12345678);
+        // Relay on the directly attached network
+        let mask = self.config.subnet_mask.parse::<Ipv4Addr>().ok()?;
+        if network(request.giaddr, mask) == network(self.ip_pool.start, mask) {
+            return Some((&self.ip_pool, &self.config));
+        }
+
+        log::warn!("No scope for request relayed via {}", request.giaddr);
+        None
     }
 
-    #[test]
-    fn test_ip_pool() {
-        let start = "192.168.1.100".parse().unwrap();
-        let end = "192.168.1.110".parse().unwrap();
-        let pool = IpPool::new(start, end);
+    async fn handle_request(
+        &self,
+        request: &DhcpMessage,
+        ip_pool: &IpPool,
+        config: &Arc<DhcpConfig>,
+    ) -> Option<DhcpMessage> {
+        if config.proxy_dhcp {
+            return self.handle_proxy_request(request, config);
+        }
+
+        let host = self.reservations.find(request);
+        let Some(msg_type) = request.get_message_type() else {
+            if config.bootp && request.op == BOOTREQUEST {
+                return self
+                    .handle_bootp_request(request, ip_pool, config, host)
+                    .await;
+            }
+            return None;
+        };
+        let mac = request.client_mac();
+        log::info!(
+            "Received DHCP {} from MAC: {}",
+            message_type_name(msg_type),
+            format_mac(&mac)
+        );
+
+        match msg_type {
+            DHCP_DISCOVER => {
+                let client_ip = match host {
+                    Some(host) => ip_pool.assign(mac, host.ip).ip,
+                    None => self.allocate(mac, ip_pool)?,
+                };
+                log::info!("Allocated IP: {} for client", client_ip);
+                let lease_time =
+                    DhcpOptions::lease_time(config, request.get_requested_lease_time());
+                self.record_lease(
+                    request,
+                    ip_pool,
+                    config,
+                    host,
+                    LeaseState::Offered,
+                    OFFER_HOLD_TIME,
+                );
+                let response = self.build_reply(
+                    request,
+                    config,
+                    host,
+                    DHCP_OFFER,
+                    client_ip,
+                    Some(lease_time),
+                )?;
+                Some(response)
+            }
+            DHCP_REQUEST => self.handle_dhcp_request(request, ip_pool, config, host),
+            DHCP_DECLINE => {
+                let ip = request.get_requested_ip()?;
+                if let Some(lease) = ip_pool.decline(mac, ip) {
+                    log::warn!(
+                        "Client {} declined {}, address quarantined",
+                        format_mac(&mac),
+                        ip
+                    );
+                    self.update_dns(Some(lease), false);
+                }
+                None
+            }
+            DHCP_RELEASE => {
+                let lease = ip_pool.lease(mac);
+                if ip_pool.release(mac, request.ciaddr) {
+                    log::info!(
+                        "Released IP {} from MAC: {}",
+                        request.ciaddr,
+                        format_mac(&mac)
+                    );
+                    self.update_dns(lease, false);
+                }
+                None
+            }
+            DHCP_INFORM => {
+                // Client already has an address: reply with configuration only
+                let mut response =
+                    self.build_reply(request, config, host, DHCP_ACK, Ipv4Addr::UNSPECIFIED, None)?;
+                response.ciaddr = request.ciaddr;
+                Some(response)
+            }
+            _ => None,
+        }
+    }
+
+    /// Allocate an address from the pool. With conflict detection, fresh
+    /// addresses are probed first: the request goes unanswered until the
+    /// probe is done, and addresses another host answers on are marked in
+    /// conflict and skipped.
+    fn allocate(&self, mac: [u8; 6], ip_pool: &IpPool) -> Option<Ipv4Addr> {
+        let Some(ref probe) = self.probe else {
+            return self.allocate_from(mac, ip_pool);
+        };
+
+        let previous = ip_pool.lease(mac).map(|lease| lease.ip);
+        for _ in 0..MAX_CONFLICT_PROBES {
+            let ip = self.allocate_from(mac, ip_pool)?;
+            match probe.state(ip) {
+                Some(ProbeState::Free(_)) => return Some(ip),
+                // Addresses the client holds already aren't probed
+                None if Some(ip) == previous => return Some(ip),
+                None | Some(ProbeState::Probing) => {
+                    // The offered address stays held for the retransmission
+                    probe.start(ip);
+                    log::debug!(
+                        "Probing {} before offering it to MAC: {}",
+                        ip,
+                        format_mac(&mac)
+                    );
+                    return None;
+                }
+                Some(ProbeState::InUse) => {
+                    log::warn!(
+                        "Address {} is in use by another host, marked in conflict",
+                        ip
+                    );
+                    self.update_dns(ip_pool.decline(mac, ip), false);
+                }
+            }
+        }
+        log::warn!(
+            "No conflict free address found for MAC: {}",
+            format_mac(&mac)
+        );
+        None
+    }
+
+    /// Address from the pool, taking DNS records of a lease reclaimed for
+    /// it down
+    fn allocate_from(&self, mac: [u8; 6], ip_pool: &IpPool) -> Option<Ipv4Addr> {
+        let (ip, reclaimed) = ip_pool.allocate(mac)?;
+        self.update_dns(reclaimed, false);
+        Some(ip)
+    }
+
+    /// Answer a plain BOOTP client: its reserved address, or one from the pool,
+    /// is bound to it for good (RFC 1534)
+    async fn handle_bootp_request(
+        &self,
+        request: &DhcpMessage,
+        ip_pool: &IpPool,
+        config: &Arc<DhcpConfig>,
+        host: Option<&Reservation>,
+    ) -> Option<DhcpMessage> {
+        let mac = request.client_mac();
+        log::info!("Received BOOTP request from MAC: {}", format_mac(&mac));
+
+        let client_ip = match host {
+            Some(host) => ip_pool.assign(mac, host.ip).ip,
+            None => self.allocate(mac, ip_pool)?,
+        };
+        self.record_lease(
+            request,
+            ip_pool,
+            config,
+            host,
+            LeaseState::Bound,
+            INFINITE_LEASE,
+        );
+        log::info!("Assigned IP: {} to BOOTP client", client_ip);
+        self.update_dns(ip_pool.lease(mac), true);
+
+        let mut response = self.build_reply(request, config, host, DHCP_ACK, client_ip, None)?;
+        let mut options = response.decode_options().ok()?;
+        // Leave room for the relay agent information echoed back
+        let relay_info = request.get_option(82).map_or(0, |info| 2 + info.len());
+        options::bootp_options(&mut options, relay_info);
+        response.set_options(&options);
+        Some(response)
+    }
+
+    /// Validate a DHCPREQUEST against our lease records (RFC 2131 section 4.3.2)
+    fn handle_dhcp_request(
+        &self,
+        request: &DhcpMessage,
+        ip_pool: &IpPool,
+        config: &Arc<DhcpConfig>,
+        host: Option<&Reservation>,
+    ) -> Option<DhcpMessage> {
+        let mac = request.client_mac();
+        let lease = match host {
+            Some(host) => Some(ip_pool.assign(mac, host.ip)),
+            None => ip_pool.lease(mac),
+        };
+        let requested_ip = request.get_requested_ip();
+
+        let client_ip = match request.get_server_id() {
+            // SELECTING: the client is answering an offer
+            Some(server_id) => {
+                if Some(server_id) != DhcpOptions::server_id(config) {
+                    // Client picked another server's offer
+                    if lease.is_some_and(|lease| lease.state == LeaseState::Offered) {
+                        ip_pool.release(mac, Ipv4Addr::UNSPECIFIED);
+                    }
+                    return None;
+                }
+                match lease {
+                    Some(lease) if Some(lease.ip) == requested_ip => lease.ip,
+                    _ => return self.build_nak(request, config, "requested address not offered"),
+                }
+            }
+            // INIT-REBOOT, RENEWING or REBINDING
+            None => {
+                let wanted = requested_ip.unwrap_or(request.ciaddr);
+                if wanted.is_unspecified() {
+                    return self.build_nak(request, config, "no address requested");
+                }
+                match lease {
+                    Some(lease) if lease.ip == wanted => lease.ip,
+                    Some(_) => {
+                        return self.build_nak(request, config, "address not leased to client")
+                    }
+                    None if !ip_pool.contains(wanted) => {
+                        return self.build_nak(request, config, "address not on this network")
+                    }
+                    // No record of this client: remain silent
+                    None => return None,
+                }
+            }
+        };
+
+        let lease_time = DhcpOptions::lease_time(config, request.get_requested_lease_time());
+        self.record_lease(
+            request,
+            ip_pool,
+            config,
+            host,
+            LeaseState::Bound,
+            lease_time,
+        );
+        log::info!("Acknowledged IP: {} for client", client_ip);
+        self.update_dns(ip_pool.lease(request.client_mac()), true);
+
+        let response =
+            self.build_reply(request, config, host, DHCP_ACK, client_ip, Some(lease_time))?;
+        Some(response)
+    }
+
+    fn record_lease(
+        &self,
+        request: &DhcpMessage,
+        ip_pool: &IpPool,
+        config: &DhcpConfig,
+        host: Option<&Reservation>,
+        state: LeaseState,
+        duration: u32,
+    ) {
+        let client_arch = request.get_client_arch();
+        ip_pool.update(request.client_mac(), |lease| {
+            let hostname = assigned_hostname(config, host, &lease.mac, lease.ip)
+                .or_else(|| request.get_hostname());
+            // A bound client rediscovering keeps its binding until it requests again
+            let keep_binding = state == LeaseState::Offered
+                && lease.state == LeaseState::Bound
+                && !lease.is_expired(leases::now());
+            if !keep_binding {
+                lease.renew(state, duration);
+            }
+            if hostname.is_some() {
+                lease.hostname = hostname;
+            }
+            lease.client_arch = client_arch;
+        });
+    }
+
+    /// Add or remove the records of a lease's hostname in DNS, in the
+    /// background
+    fn update_dns(&self, lease: Option<Lease>, add: bool) {
+        let (Some(ddns), Some(lease)) = (self.ddns.clone(), lease) else {
+            return;
+        };
+        let Some(hostname) = lease.hostname else {
+            return;
+        };
+        tokio::spawn(async move {
+            let result = if add {
+                ddns.add(&hostname, lease.ip, lease.mac).await
+            } else {
+                ddns.remove(&hostname, lease.ip, lease.mac).await
+            };
+            match result {
+                Ok(()) if add => log::info!("Published {} at {} in DNS", hostname, lease.ip),
+                Ok(()) => log::info!("Removed {} at {} from DNS", hostname, lease.ip),
+                Err(e) => log::warn!("Cannot update DNS for {}: {}", hostname, e),
+            }
+        });
+    }
+
+    fn build_reply(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+        host: Option<&Reservation>,
+        msg_type: u8,
+        client_ip: Ipv4Addr,
+        lease_time: Option<u32>,
+    ) -> Option<DhcpMessage> {
+        let (mut filename, next_server) = self.select_boot_file(request, config, host)?;
+        let http_boot = request.is_http_client() && self.http.is_some();
+        if http_boot {
+            filename = self.http_boot_url(&filename, next_server);
+        }
+
+        let mut response = DhcpMessage {
+            op: 2, // BOOTREPLY
+            htype: request.htype,
+            hlen: request.hlen,
+            hops: 0,
+            xid: request.xid,
+            secs: 0,
+            flags: request.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: client_ip,
+            siaddr: next_server,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: request.chaddr,
+            sname: [0; 64],
+            file: [0; 128],
+            options: Vec::new(),
+        };
+
+        let mut options = match DhcpOptions::build_options(config, msg_type, lease_time) {
+            Ok(options) => options,
+            Err(e) => {
+                log::error!("Cannot build DHCP reply: {}", e);
+                return None;
+            }
+        };
+        let ip = if client_ip.is_unspecified() {
+            request.ciaddr
+        } else {
+            client_ip
+        };
+        let name = assigned_hostname(config, host, &request.client_mac(), ip);
+        if let Some(ref name) = name {
+            options.push(DhcpOption::HostName(name.clone()));
+        }
+        if let Some(fqdn) = request.get_option(81) {
+            // Tell the client the name published in DNS
+            let published = name
+                .as_ref()
+                .and_then(|name| self.ddns.as_ref()?.fqdn(name).ok());
+            let name = published.or(name.map(|name| match config.domain_name {
+                Some(ref domain) if !name.contains('.') => format!("{}.{}", name, domain),
+                _ => name,
+            }));
+            if let Some(reply) = hostname::client_fqdn(fqdn, name.as_deref(), self.ddns.is_some()) {
+                options.push(DhcpOption::ClientFqdn(reply));
+            }
+        }
+        if http_boot {
+            // HTTP Boot firmware ignores offers without the HTTPClient class
+            options.push(DhcpOption::VendorClassIdentifier(
+                HTTP_CLIENT_CLASS.to_vec(),
+            ));
+        } else if let Some(vendor) = self.pxe_menu_options(request) {
+            // The PXE ROM only looks at option 43 next to the PXEClient class
+            options.push(DhcpOption::VendorClassIdentifier(PXE_CLIENT_CLASS.to_vec()));
+            options.push(DhcpOption::VendorSpecific(vendor));
+        }
+        response.set_boot_info(&filename, next_server);
+        options.push(DhcpOption::BootfileName(filename));
+        if let Some(host) = host {
+            merge_options(&mut options, host.options.iter().cloned());
+        }
+        if let Some(DhcpOption::ParameterRequestList(mut requested)) = request.option(55) {
+            // A client sending its name gets the one it was given, whether
+            // it asks for it or not (RFC 4702, section 4)
+            for code in [12, 81] {
+                if request.get_option(code).is_some() && !requested.contains(&code) {
+                    requested.push(code);
+                }
+            }
+            order_options(&mut options, &requested);
+        }
+
+        response.set_options(&options);
+        Some(response)
+    }
+
+    /// Option 43 boot menu for PXE firmware. iPXE has already been chosen
+    /// from the menu, so it doesn't get it again.
+    fn pxe_menu_options(&self, request: &DhcpMessage) -> Option<Vec<u8>> {
+        let menu = self.pxe_menu.as_ref()?;
+        if !request.is_pxe_client() || request.is_ipxe() {
+            return None;
+        }
+        Some(menu.encode())
+    }
+
+    /// Boot file URL for HTTP Boot and iPXE clients, served by the built-in
+    /// HTTP server on the boot server address. Filenames that already are
+    /// URLs are passed through unchanged.
+    fn http_boot_url(&self, filename: &str, next_server: Ipv4Addr) -> String {
+        if filename.starts_with("http://") || filename.starts_with("https://") {
+            return filename.to_string();
+        }
+        let port = self.http.as_ref().map_or(80, |http| http.port);
+        let path = filename.trim_start_matches('/');
+        if port == 80 {
+            format!("http://{}/{}", next_server, path)
+        } else {
+            format!("http://{}:{}/{}", next_server, port, path)
+        }
+    }
+
+    /// Boot filename and TFTP server for a client: the iPXE script for
+    /// clients already running iPXE, the host reservation's overrides, or the
+    /// file for the protocol matching its architecture
+    fn select_boot_file(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+        host: Option<&Reservation>,
+    ) -> Option<(String, Ipv4Addr)> {
+        let next_server = match host.and_then(|host| host.next_server) {
+            Some(next_server) => next_server,
+            None => config.next_server.parse().ok()?,
+        };
+
+        // Handing iPXE its own binary again would chainload it forever
+        if let Some(ref script) = config.protocols.ipxe_script {
+            if request.is_ipxe() {
+                let url = self.http_boot_url(script, next_server);
+                log::info!("iPXE client, boot script: {}", url);
+                return Some((url, next_server));
+            }
+        }
+
+        let filename = match host.and_then(|host| host.boot_filename.clone()) {
+            Some(filename) => {
+                log::info!("Host reservation boot filename: {}", filename);
+                filename
+            }
+            None => {
+                let client_arch = request.get_client_arch();
+                if let Some(arch) = client_arch.map(ClientArch::from_code) {
+                    log::debug!("Client architecture: {}", arch);
+                }
+                let (protocol, filename) =
+                    ProtocolHandler::select_boot_filename(&config.protocols, client_arch)?;
+                log::info!(
+                    "Selected protocol: {:?}, boot filename: {}",
+                    protocol,
+                    filename
+                );
+                filename
+            }
+        };
+
+        Some((filename, next_server))
+    }
+
+    /// Answer a DHCPDISCOVER in proxyDHCP mode: boot information only, the
+    /// address itself comes from the network's own DHCP server
+    fn handle_proxy_request(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+    ) -> Option<DhcpMessage> {
+        if request.get_message_type()? != DHCP_DISCOVER || !request.is_pxe_client() {
+            return None;
+        }
+
+        log::info!(
+            "Received PXE Discover from MAC: {} (proxyDHCP)",
+            format_mac(&request.client_mac())
+        );
+        let response = self.build_proxy_reply(request, config, DHCP_OFFER)?;
+        Some(response)
+    }
+
+    /// Answer a DHCPREQUEST sent to the PXE boot server port (4011)
+    fn handle_boot_server_request(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+    ) -> Option<DhcpMessage> {
+        if request.get_message_type()? != DHCP_REQUEST || !request.is_pxe_client() {
+            return None;
+        }
+        if !self.is_allowed(request) {
+            return None;
+        }
+
+        log::info!(
+            "Received PXE boot server Request from MAC: {}",
+            format_mac(&request.client_mac())
+        );
+
+        // A boot server request for an item picked from the boot menu
+        let boot_item = request.get_option(43).and_then(pxe_menu::boot_item);
+        if let (Some(menu), Some((boot_type, layer))) = (self.pxe_menu.as_ref(), boot_item) {
+            let item = menu.find(boot_type)?;
+            log::info!("PXE boot menu selection: {}", item.label);
+
+            let mut vendor = vec![PXE_BOOT_ITEM, 4];
+            vendor.extend_from_slice(&boot_type.to_be_bytes());
+            vendor.extend_from_slice(&layer.to_be_bytes());
+            vendor.push(255);
+
+            let filename = item.boot_filename.clone()?;
+            let mut options =
+                DhcpOptions::build_proxy_options(config, DHCP_ACK, request.get_option(97), &vendor);
+
+            let mut response = DhcpMessage {
+                op: 2, // BOOTREPLY
+                htype: request.htype,
+                hlen: request.hlen,
+                hops: 0,
+                xid: request.xid,
+                secs: 0,
+                flags: request.flags,
+                ciaddr: request.ciaddr,
+                yiaddr: Ipv4Addr::UNSPECIFIED,
+                siaddr: item.boot_server?,
+                giaddr: Ipv4Addr::UNSPECIFIED,
+                chaddr: request.chaddr,
+                sname: [0; 64],
+                file: [0; 128],
+                options: Vec::new(),
+            };
+            response.set_boot_info(&filename, response.siaddr);
+            options.push(DhcpOption::BootfileName(filename));
+            response.set_options(&options);
+            return Some(response);
+        }
+
+        let mut response = self.build_proxy_reply(request, config, DHCP_ACK)?;
+        response.ciaddr = request.ciaddr;
+        Some(response)
+    }
+
+    fn build_proxy_reply(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+        msg_type: u8,
+    ) -> Option<DhcpMessage> {
+        let host = self.reservations.find(request);
+        let (filename, next_server) = self.select_boot_file(request, config, host)?;
+
+        // Offer the boot menu, or tell the ROM to boot the file right away
+        let vendor = match self.pxe_menu_options(request) {
+            Some(vendor) if msg_type == DHCP_OFFER => vendor,
+            _ => PXE_BOOT_FILE_ONLY.to_vec(),
+        };
+        let mut options =
+            DhcpOptions::build_proxy_options(config, msg_type, request.get_option(97), &vendor);
+
+        let mut response = DhcpMessage {
+            op: 2, // BOOTREPLY
+            htype: request.htype,
+            hlen: request.hlen,
+            hops: 0,
+            xid: request.xid,
+            secs: 0,
+            flags: request.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: next_server,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: request.chaddr,
+            sname: [0; 64],
+            file: [0; 128],
+            options: Vec::new(),
+        };
+        response.set_boot_info(&filename, next_server);
+        options.push(DhcpOption::BootfileName(filename));
+        response.set_options(&options);
+        Some(response)
+    }
+
+    fn build_nak(
+        &self,
+        request: &DhcpMessage,
+        config: &Arc<DhcpConfig>,
+        reason: &str,
+    ) -> Option<DhcpMessage> {
+        log::warn!(
+            "Sending DHCP NAK to MAC {}: {}",
+            format_mac(&request.client_mac()),
+            reason
+        );
+
+        let response = DhcpMessage {
+            op: 2, // BOOTREPLY
+            htype: request.htype,
+            hlen: request.hlen,
+            hops: 0,
+            xid: request.xid,
+            secs: 0,
+            flags: request.flags,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: request.chaddr,
+            sname: [0; 64],
+            file: [0; 128],
+            options: encode_options(&DhcpOptions::build_nak_options(config, reason)),
+        };
+
+        Some(response)
+    }
+}
+
+/// Hostname given to a client: the one of its reservation, or one made
+/// from the hostname pattern
+fn assigned_hostname(
+    config: &DhcpConfig,
+    host: Option<&Reservation>,
+    mac: &[u8; 6],
+    ip: Ipv4Addr,
+) -> Option<String> {
+    if let Some(hostname) = host.and_then(|host| host.hostname.clone()) {
+        return Some(hostname);
+    }
+    let pattern = config.hostname_pattern.as_ref()?;
+    (!ip.is_unspecified()).then(|| hostname::expand(pattern, mac, ip))
+}
+
+fn set_header_string(field: &mut [u8], value: &str) {
+    // One byte is kept for the terminating NUL
+    if value.len() < field.len() {
+        field.fill(0);
+        field[..value.len()].copy_from_slice(value.as_bytes());
+    }
+}
+
+pub fn message_type_name(msg_type: u8) -> &'static str {
+    match msg_type {
+        DHCP_DISCOVER => "Discover",
+        DHCP_OFFER => "Offer",
+        DHCP_REQUEST => "Request",
+        DHCP_DECLINE => "Decline",
+        DHCP_ACK => "ACK",
+        DHCP_NAK => "NAK",
+        DHCP_RELEASE => "Release",
+        DHCP_INFORM => "Inform",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    /// Name of the loopback interface
+    pub(crate) const LOOPBACK: &str = if cfg!(target_os = "linux") {
+        "lo"
+    } else {
+        "lo0"
+    };
+
+    fn test_config() -> DhcpConfig {
+        Config::default().dhcp
+    }
+
+    fn test_server() -> DhcpServer {
+        DhcpServer::new(test_config()).unwrap()
+    }
+
+    /// A server on the default config changed by `f`
+    fn test_server_with<F: FnOnce(&mut DhcpConfig)>(f: F) -> DhcpServer {
+        let mut config = test_config();
+        f(&mut config);
+        DhcpServer::new(config).unwrap()
+    }
+
+    /// A request from `mac` carrying `options`, followed by the end option
+    pub(crate) fn client_request(mac: [u8; 6], options: &[u8]) -> DhcpMessage {
+        let mut chaddr = [0u8; 16];
+        chaddr[..6].copy_from_slice(&mac);
+        let mut options = options.to_vec();
+        options.push(255);
+        DhcpMessage {
+            op: 1,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: 0x12345678,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            sname: [0; 64],
+            file: [0; 128],
+            options,
+        }
+    }
+
+    fn request(msg_type: u8, mac: [u8; 6], extra_options: &[u8]) -> DhcpMessage {
+        let mut options = vec![53, 1, msg_type];
+        options.extend_from_slice(extra_options);
+        client_request(mac, &options)
+    }
+
+    async fn handle(server: &DhcpServer, request: &DhcpMessage) -> Option<DhcpMessage> {
+        server.process(request, None).await
+    }
+
+    /// Run DISCOVER and return the offered address
+    async fn discover(server: &DhcpServer, mac: [u8; 6]) -> Ipv4Addr {
+        let offer = handle(server, &request(DHCP_DISCOVER, mac, &[]))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_message_type(), Some(DHCP_OFFER));
+        offer.yiaddr
+    }
+
+    fn select_options(ip: Ipv4Addr, server_id: Ipv4Addr) -> Vec<u8> {
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&ip.octets());
+        options.extend_from_slice(&[54, 4]);
+        options.extend_from_slice(&server_id.octets());
+        options
+    }
+
+    #[test]
+    fn test_dhcp_message_parsing() {
+        let mut data = vec![0u8; 240];
+        data[0] = BOOTREQUEST;
+        data[1] = 1; // Ethernet
+        data[2] = 6; // MAC length
+        data[4..8].copy_from_slice(&0x12345678u32.to_be_bytes());
+        data[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let msg = DhcpMessage::from_bytes(&data).unwrap();
+        assert_eq!(msg.op, 1);
+        assert_eq!(msg.xid, 0x12345678);
+
+        // Without the magic cookie the vendor area isn't DHCP options
+        data[236..240].fill(0);
+        data.extend_from_slice(&[53, 1, DHCP_DISCOVER, 255]);
+        let msg = DhcpMessage::from_bytes(&data).unwrap();
+        assert_eq!(msg.get_message_type(), None);
+    }
+
+    #[test]
+    fn test_split_option() {
+        // Hostname split over two instances (RFC 3396)
+        let msg = request(
+            DHCP_DISCOVER,
+            MAC,
+            &[12, 4, b'n', b'o', b'd', b'e', 12, 2, b'-', b'1'],
+        );
+        assert_eq!(msg.get_option(12), Some(&b"node"[..]));
+        assert_eq!(
+            msg.option(12),
+            Some(DhcpOption::HostName("node-1".to_string()))
+        );
+        assert_eq!(msg.option(15), None);
+    }
+
+    #[test]
+    fn test_bootp_header() {
+        let mut msg = request(DHCP_OFFER, MAC, &[]);
+        msg.set_boot_info("pxelinux.0", "192.168.1.2".parse().unwrap());
+
+        let data = msg.to_bytes();
+        assert_eq!(&data[236..240], &[0x63, 0x82, 0x53, 0x63]);
+        assert_eq!(&data[44..56], b"192.168.1.2\0");
+        assert_eq!(&data[108..119], b"pxelinux.0\0");
+
+        let parsed = DhcpMessage::from_bytes(&data).unwrap();
+        assert_eq!(parsed.sname, msg.sname);
+        assert_eq!(parsed.file, msg.file);
+        assert_eq!(parsed.to_bytes(), data);
+
+        // A name too long for its field is left out
+        msg.set_boot_info(&"a".repeat(128), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(&msg.file[..11], b"pxelinux.0\0");
+    }
+
+    #[test]
+    fn test_option_overload_keeps_names() {
+        let mut msg = request(DHCP_OFFER, MAC, &[]);
+        msg.set_boot_info("bootx64.efi", Ipv4Addr::UNSPECIFIED);
+        msg.set_options(&[
+            DhcpOption::MessageType(DHCP_OFFER),
+            DhcpOption::VendorSpecific(vec![0xaa; 250]),
+            DhcpOption::Message("m".repeat(30)),
+            DhcpOption::HostName("rack12-node01.build.example.com".to_string()),
+        ]);
+
+        // The empty sname field takes the overflow, the file name stays
+        let data = msg.to_bytes_with_max_size(548).unwrap();
+        assert_eq!(&data[240..243], &[52, 1, OVERLOAD_SNAME]);
+        let parsed = DhcpMessage::from_bytes(&data).unwrap();
+        assert_eq!(parsed.file, msg.file);
+        assert_eq!(parsed.sname, [0; 64]);
+        assert_eq!(
+            parsed.get_hostname().as_deref(),
+            Some("rack12-node01.build.example.com")
+        );
+    }
+
+    #[test]
+    fn test_get_option_skips_pad() {
+        let msg = request(
+            DHCP_DISCOVER,
+            MAC,
+            &[0, 0, 12, 2, b'p', b'c', 0, 50, 4, 10, 0, 0, 5],
+        );
+        assert_eq!(msg.get_hostname().as_deref(), Some("pc"));
+        assert_eq!(msg.get_requested_ip(), Some(Ipv4Addr::new(10, 0, 0, 5)));
+        assert_eq!(msg.get_option(0), None);
+    }
+
+    #[test]
+    fn test_option_overload() {
+        let mut msg = request(DHCP_OFFER, MAC, &[]);
+        let options = vec![
+            DhcpOption::MessageType(DHCP_OFFER),
+            DhcpOption::VendorSpecific(vec![0xaa; 250]),
+            DhcpOption::Message("m".repeat(100)),
+            DhcpOption::BootfileName("bootx64.efi".to_string()),
+            DhcpOption::HostName("rack12-node01.example".to_string()),
+        ];
+        msg.set_options(&options);
+
+        let data = msg.to_bytes_with_max_size(548).unwrap();
+        assert!(data.len() <= 548);
+        assert_eq!(&data[240..243], &[52, 1, OVERLOAD_BOTH]);
+
+        let parsed = DhcpMessage::from_bytes(&data).unwrap();
+        assert_eq!(parsed.get_message_type(), Some(DHCP_OFFER));
+        assert_eq!(parsed.get_option(67), Some(&b"bootx64.efi"[..]));
+        assert_eq!(
+            parsed.get_hostname().as_deref(),
+            Some("rack12-node01.example")
+        );
+        assert_eq!(parsed.decode_options().unwrap(), options);
+
+        // Small messages are left alone
+        let msg = request(DHCP_OFFER, MAC, &[]);
+        assert_eq!(msg.to_bytes_with_max_size(548).unwrap(), msg.to_bytes());
+
+        // Not even overload makes room for this
+        let mut msg = request(DHCP_OFFER, MAC, &[]);
+        msg.set_options(&[DhcpOption::VendorSpecific(vec![0; 1000])]);
+        assert_eq!(msg.to_bytes_with_max_size(548), Err(OptionError::TooLong));
+    }
+
+    #[test]
+    fn test_truncated_options_rejected() {
+        let mut data = request(DHCP_DISCOVER, MAC, &[]).to_bytes();
+        data.pop(); // End option
+        data.extend_from_slice(&[12, 10, b'a']);
+        assert!(DhcpMessage::from_bytes(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_selecting() {
+        let server = test_server();
+        let server_id: Ipv4Addr = "192.168.1.1".parse().unwrap();
+        let offered = discover(&server, MAC).await;
+
+        let ack = handle(
+            &server,
+            &request(DHCP_REQUEST, MAC, &select_options(offered, server_id)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(ack.yiaddr, offered);
+        assert_eq!(server.ip_pool.lease(MAC).unwrap().state, LeaseState::Bound);
+
+        // Requesting an address that was never offered is refused
+        let other: Ipv4Addr = "192.168.1.150".parse().unwrap();
+        let nak = handle(
+            &server,
+            &request(DHCP_REQUEST, MAC, &select_options(other, server_id)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(nak.get_message_type(), Some(DHCP_NAK));
+        assert_eq!(nak.yiaddr, Ipv4Addr::UNSPECIFIED);
+    }
+
+    #[tokio::test]
+    async fn test_request_for_other_server() {
+        let server = test_server();
+        let offered = discover(&server, MAC).await;
+
+        let other_server = "192.168.1.2".parse().unwrap();
+        let response = handle(
+            &server,
+            &request(DHCP_REQUEST, MAC, &select_options(offered, other_server)),
+        )
+        .await;
+        assert!(response.is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_init_reboot() {
+        let server = test_server();
+
+        // Unknown client asking for an address in our range: stay silent
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&[192, 168, 1, 120]);
+        assert!(handle(&server, &request(DHCP_REQUEST, MAC, &options))
+            .await
+            .is_none());
+
+        // Unknown client on the wrong network: NAK
+        let options = [50, 4, 10, 0, 0, 5];
+        let nak = handle(&server, &request(DHCP_REQUEST, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(nak.get_message_type(), Some(DHCP_NAK));
+    }
+
+    #[tokio::test]
+    async fn test_renewal() {
+        let server = test_server();
+        let offered = discover(&server, MAC).await;
+
+        let mut renew = request(DHCP_REQUEST, MAC, &[]);
+        renew.ciaddr = offered;
+        let ack = handle(&server, &renew).await.unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(ack.yiaddr, offered);
+
+        renew.ciaddr = "192.168.1.199".parse().unwrap();
+        let nak = handle(&server, &renew).await.unwrap();
+        assert_eq!(nak.get_message_type(), Some(DHCP_NAK));
+    }
+
+    #[tokio::test]
+    async fn test_release() {
+        let server = test_server();
+        let offered = discover(&server, MAC).await;
+
+        // Release naming a different address is ignored
+        let mut release = request(DHCP_RELEASE, MAC, &[]);
+        release.ciaddr = "192.168.1.199".parse().unwrap();
+        assert!(handle(&server, &release).await.is_none());
+        assert!(server.ip_pool.lease(MAC).is_some());
+
+        release.ciaddr = offered;
+        assert!(handle(&server, &release).await.is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_decline_quarantines_address() {
+        let server = test_server();
+        let offered = discover(&server, MAC).await;
+
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&offered.octets());
+        assert!(handle(&server, &request(DHCP_DECLINE, MAC, &options))
+            .await
+            .is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
+        assert!(server.ip_pool.is_quarantined(offered, leases::now()));
+
+        let next = discover(&server, MAC).await;
+        assert_ne!(next, offered);
+    }
+
+    #[tokio::test]
+    async fn test_excluded_addresses() {
+        let mut config = test_config();
+        config.exclude = vec![
+            "192.168.1.100-192.168.1.102".to_string(),
+            "192.168.1.104".to_string(),
+        ];
+        let server = DhcpServer::new(config.clone()).unwrap();
+        assert_eq!(
+            discover(&server, MAC).await,
+            "192.168.1.103".parse::<Ipv4Addr>().unwrap()
+        );
+        assert_eq!(
+            discover(&server, [0x02, 0, 0, 0, 0, 1]).await,
+            "192.168.1.105".parse::<Ipv4Addr>().unwrap()
+        );
+
+        config.exclude = vec!["192.168.1.0/24".to_string()];
+        assert!(DhcpServer::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_reservation() {
+        use crate::config::HostConfig;
+
+        let server = test_server_with(|config| {
+            config.ip_pool_start = "192.168.1.100".to_string();
+            config.ip_pool_end = "192.168.1.101".to_string();
+            config.hosts = vec![HostConfig {
+                mac: Some("00:11:22:33:44:55".to_string()),
+                client_id: None,
+                uuid: None,
+                ip: "192.168.1.100".to_string(),
+                hostname: Some("rack1-node1".to_string()),
+                boot_filename: Some("rescue.efi".to_string()),
+                next_server: Some("192.168.1.2".to_string()),
+                options: Vec::new(),
+            }];
+        });
+
+        // Reserved address is skipped for other clients even while its host is offline
+        let other = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        assert_eq!(
+            discover(&server, other).await,
+            "192.168.1.101".parse::<Ipv4Addr>().unwrap()
+        );
+        let third = [0x00, 0x11, 0x22, 0x33, 0x44, 0x77];
+        assert!(handle(&server, &request(DHCP_DISCOVER, third, &[]))
+            .await
+            .is_none());
+
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &[]))
+            .await
+            .unwrap();
+        assert_eq!(offer.yiaddr, "192.168.1.100".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(offer.siaddr, "192.168.1.2".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(offer.get_option(12), Some(&b"rack1-node1"[..]));
+        assert_eq!(offer.get_option(67), Some(&b"rescue.efi"[..]));
+        assert_eq!(&offer.file[..11], b"rescue.efi\0");
+        assert_eq!(&offer.sname[..12], b"192.168.1.2\0");
+
+        // INIT-REBOOT with the reserved address is acknowledged without prior state
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&offer.yiaddr.octets());
+        let ack = handle(&server, &request(DHCP_REQUEST, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(
+            server.ip_pool.lease(MAC).unwrap().hostname.as_deref(),
+            Some("rack1-node1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hostname_pattern() {
+        let mut config = test_config();
+        config.hostname_pattern = Some("node-{ip_last_octet}".to_string());
+        config.domain_name = Some("lab.example".to_string());
+        let server = DhcpServer::new(config.clone()).unwrap();
+
+        // The assigned name wins over the one the client sent
+        let mut options = vec![12, 6];
+        options.extend_from_slice(b"laptop");
+        // Client FQDN, canonical wire format, asking for server updates
+        options.extend_from_slice(&[81, 11, 0x05, 0, 0, 6]);
+        options.extend_from_slice(b"laptop\0");
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(12), Some(&b"node-100"[..]));
+        assert_eq!(offer.get_option(15), Some(&b"lab.example"[..]));
+        assert_eq!(
+            offer.get_option(81),
+            Some(&b"\x0e\xff\xff\x08node-100\x03lab\x07example\x00"[..])
+        );
+        assert_eq!(
+            server.ip_pool.lease(MAC).unwrap().hostname.as_deref(),
+            Some("node-100")
+        );
+
+        // Names the client sent are answered even when its parameter request
+        // list leaves them out
+        options.extend_from_slice(&[55, 3, 1, 3, 6]);
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(12), Some(&b"node-100"[..]));
+        assert!(offer.get_option(81).is_some());
+        assert!(offer.get_option(15).is_none());
+
+        // Without option 81 in the request, none in the reply
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &[]))
+            .await
+            .unwrap();
+        assert!(offer.get_option(81).is_none());
+
+        config.hostname_pattern = Some("node-{serial}".to_string());
+        assert!(DhcpServer::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_dns() {
+        use crate::config::DdnsConfig;
+        use crate::dhcp::ddns::tests::stand_in_server;
+
+        let (dns_server, mut updates) = stand_in_server(&[0]).await;
+        let server = test_server_with(|config| {
+            config.hostname_pattern = Some("node-{ip_last_octet}".to_string());
+            config.ddns = Some(DdnsConfig {
+                server: dns_server.to_string(),
+                forward_zone: Some("lab.example".to_string()),
+                reverse_zone: None,
+                ttl: 300,
+                tsig: None,
+            });
+        });
+        let name = b"\x08node-100\x03lab\x07example\x00";
+
+        // Offers don't touch DNS, committed leases do
+        let offered = discover(&server, MAC).await;
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&offered.octets());
+        let ack = handle(&server, &request(DHCP_REQUEST, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        let update = tokio::time::timeout(Duration::from_secs(2), updates.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut record = name.to_vec();
+        record.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 44, 0, 4]);
+        record.extend_from_slice(&offered.octets());
+        assert!(update.ends_with(&record));
+
+        let mut release = request(DHCP_RELEASE, MAC, &[]);
+        release.ciaddr = offered;
+        assert!(handle(&server, &release).await.is_none());
+        let update = tokio::time::timeout(Duration::from_secs(2), updates.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut record = name.to_vec();
+        record.extend_from_slice(&[0, 1, 0, 254, 0, 0, 0, 0, 0, 4]);
+        record.extend_from_slice(&offered.octets());
+        assert!(update.ends_with(&record));
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_dns_dropped_leases() {
+        use crate::config::DdnsConfig;
+        use crate::dhcp::ddns::tests::stand_in_server;
+
+        let (dns_server, mut updates) = stand_in_server(&[0]).await;
+        let server = test_server_with(|config| {
+            config.ip_pool_end = config.ip_pool_start.clone();
+            config.hostname_pattern = Some("node-{ip_last_octet}".to_string());
+            config.ddns = Some(DdnsConfig {
+                server: dns_server.to_string(),
+                forward_zone: Some("lab.example".to_string()),
+                // Holds none of the pool's addresses
+                reverse_zone: Some("2.168.192.in-addr.arpa".to_string()),
+                ttl: 300,
+                tsig: None,
+            });
+        });
+        async fn next(updates: &mut tokio::sync::mpsc::Receiver<Vec<u8>>) -> Vec<u8> {
+            tokio::time::timeout(Duration::from_secs(2), updates.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+        let bind = async |mac| {
+            let offered = discover(&server, mac).await;
+            let mut options = vec![50, 4];
+            options.extend_from_slice(&offered.octets());
+            let ack = handle(&server, &request(DHCP_REQUEST, mac, &options))
+                .await
+                .unwrap();
+            assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+            offered
+        };
+        let mut removal = b"\x08node-100\x03lab\x07example\x00".to_vec();
+        removal.extend_from_slice(&[0, 1, 0, 254, 0, 0, 0, 0, 0, 4, 192, 168, 1, 100]);
+
+        // An expired lease reclaimed for another client is taken out of DNS,
+        // and no PTR record is sent for it, the reverse zone being another
+        // network's
+        bind(MAC).await;
+        next(&mut updates).await;
+        server.ip_pool.update(MAC, |lease| lease.expiry = 0);
+        let other = [0x02, 0, 0, 0, 0, 1];
+        let ip = bind(other).await;
+        // Next to the records of the new lease
+        let sent = [next(&mut updates).await, next(&mut updates).await];
+        assert!(sent.iter().any(|update| update.ends_with(&removal)));
+
+        // So is a declined address
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&ip.octets());
+        assert!(handle(&server, &request(DHCP_DECLINE, other, &options))
+            .await
+            .is_none());
+        assert!(next(&mut updates).await.ends_with(&removal));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), updates.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_dns_name_in_use() {
+        use crate::config::DdnsConfig;
+        use crate::dhcp::ddns::tests::stand_in_server;
+
+        // The name is taken and its DHCID record is another client's
+        let (dns_server, mut updates) = stand_in_server(&[6, 8]).await;
+        let server = test_server_with(|config| {
+            config.domain_name = Some("lab.example".to_string());
+            config.ddns = Some(DdnsConfig {
+                server: dns_server.to_string(),
+                forward_zone: None,
+                reverse_zone: Some("1.168.192.in-addr.arpa".to_string()),
+                ttl: 300,
+                tsig: None,
+            });
+        });
+
+        let offered = discover(&server, MAC).await;
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&offered.octets());
+        options.extend_from_slice(&[12, 7]);
+        options.extend_from_slice(b"gateway");
+        let ack = handle(&server, &request(DHCP_REQUEST, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+
+        let claim = tokio::time::timeout(Duration::from_secs(2), updates.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&claim[4..12], &[0, 1, 0, 1, 0, 2, 0, 0]);
+        // Only the client holding the name may point it elsewhere
+        let update = updates.recv().await.unwrap();
+        let name = b"\x07gateway\x03lab\x07example\x00";
+        let mut prerequisite = name.to_vec();
+        prerequisite.extend_from_slice(&[0, 49, 0, 1, 0, 0, 0, 0, 0, 35]);
+        assert!(update
+            .windows(prerequisite.len())
+            .any(|window| window == &prerequisite[..]));
+        // Neither the A nor the PTR record is touched
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), updates.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_access_control() {
+        use crate::config::HostConfig;
+
+        let server = test_server_with(|config| {
+            config.access.known_hosts_only = true;
+            config.access.deny.oui = vec!["00:11:22".to_string()];
+            config.hosts = vec![HostConfig {
+                mac: Some("52:54:00:00:00:01".to_string()),
+                client_id: None,
+                uuid: None,
+                ip: "192.168.1.150".to_string(),
+                hostname: None,
+                boot_filename: None,
+                next_server: None,
+                options: Vec::new(),
+            }];
+        });
+
+        // Unknown and denied clients are ignored, on the boot server port too
+        let unknown = [0x52, 0x54, 0x00, 0x00, 0x00, 0x02];
+        assert!(handle(&server, &request(DHCP_DISCOVER, unknown, &[]))
+            .await
+            .is_none());
+        assert!(handle(&server, &request(DHCP_DISCOVER, MAC, &[]))
+            .await
+            .is_none());
+        let mut boot_request = request(DHCP_REQUEST, unknown, &[60, 9]);
+        boot_request
+            .options
+            .splice(5..5, b"PXEClient".iter().copied());
+        assert!(server
+            .handle_boot_server_request(&boot_request, &server.config)
+            .is_none());
+        assert!(server.ip_pool.lease(unknown).is_none());
+
+        assert_eq!(
+            discover(&server, [0x52, 0x54, 0x00, 0x00, 0x00, 0x01]).await,
+            "192.168.1.150".parse::<Ipv4Addr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bootp() {
+        let bootp_request = || {
+            let mut request = request(DHCP_DISCOVER, MAC, &[]);
+            request.options = vec![255];
+            request
+        };
+
+        // Ignored unless BOOTP is enabled
+        let server = test_server();
+        assert!(handle(&server, &bootp_request()).await.is_none());
+
+        let server = test_server_with(|config| {
+            config.bootp = true;
+            config.gateway = Some("192.168.1.1".to_string());
+        });
+
+        let reply = handle(&server, &bootp_request()).await.unwrap();
+        assert_eq!(reply.op, BOOTREPLY);
+        assert_eq!(reply.yiaddr, "192.168.1.100".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(reply.siaddr, "192.168.1.1".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(&reply.file[..12], b"bootx64.efi\0");
+        assert_eq!(reply.get_message_type(), None);
+        assert_eq!(reply.get_option(1), Some(&[255, 255, 255, 0][..]));
+        assert_eq!(reply.get_option(3), Some(&[192, 168, 1, 1][..]));
+        assert!(reply.get_option(51).is_none());
+        assert!(reply.get_option(54).is_none());
+
+        let data = reply.to_bytes();
+        assert_eq!(data.len(), BOOTP_MIN_LEN);
+
+        let lease = server.ip_pool.lease(MAC).unwrap();
+        assert_eq!(lease.state, LeaseState::Bound);
+        assert!(lease.expiry - lease.start >= INFINITE_LEASE as u64);
+
+        // The echoed relay agent information still fits the vendor area
+        let server = test_server_with(|config| {
+            config.bootp = true;
+            config.gateway = Some("192.168.1.1".to_string());
+            config.dns_servers = vec!["192.168.1.53".to_string(); 8];
+        });
+        let mut relayed = bootp_request();
+        relayed.giaddr = "192.168.1.1".parse().unwrap();
+        relayed.options = vec![82, 8, 1, 6, b'p', b'o', b'r', b't', b'-', b'1', 255];
+        let reply = handle(&server, &relayed).await.unwrap();
+        assert_eq!(reply.get_option(82), Some(&relayed.options[2..10]));
+        assert!(COOKIE_FIELD.len() + reply.options.len() <= 64);
+    }
+
+    #[tokio::test]
+    async fn test_conflict_detection() {
+        // Every loopback address answers pings, so the whole pool is in use
+        let server = test_server_with(|config| {
+            config.ip_pool_start = "127.0.0.10".to_string();
+            config.ip_pool_end = "127.0.0.11".to_string();
+            config.subnet_mask = "255.0.0.0".to_string();
+            config.conflict_detection = true;
+            config.conflict_probe_timeout_ms = 200;
+        });
+        let probe = Arc::clone(server.probe.as_ref().unwrap());
+        let first = "127.0.0.10".parse().unwrap();
+        let second = "127.0.0.11".parse().unwrap();
+        let discover = request(DHCP_DISCOVER, MAC, &[]);
+
+        // The probe runs in the background, the retransmission is answered
+        assert!(handle(&server, &discover).await.is_none());
+        probe.finished(first).await;
+
+        if !ConflictProbe::can_ping() {
+            // Without a ping socket or CAP_NET_RAW, addresses are taken as free
+            let offer = handle(&server, &discover).await.unwrap();
+            assert_eq!(offer.yiaddr, first);
+            return;
+        }
+        assert!(handle(&server, &discover).await.is_none());
+        assert!(server.ip_pool.is_quarantined(first, leases::now()));
+        probe.finished(second).await;
+        assert!(handle(&server, &discover).await.is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
+        assert!(server.ip_pool.is_quarantined(second, leases::now()));
+    }
+
+    #[tokio::test]
+    async fn test_parameter_request_list() {
+        let server = test_server();
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &[55, 2, 6, 1]))
+            .await
+            .unwrap();
+        let codes: Vec<u8> = raw_options(&offer.options)
+            .map(|option| option.unwrap().0)
+            .collect();
+        assert_eq!(codes, vec![53, 6, 1, 51, 58, 59, 54, 67]);
+
+        // Option 57 counts the IP and UDP headers, and never shrinks the
+        // datagram below 576 bytes
+        let large = request(DHCP_DISCOVER, MAC, &[57, 2, 0x05, 0xdc]);
+        assert_eq!(large.max_message_size(), 1472);
+        let small = request(DHCP_DISCOVER, MAC, &[57, 2, 0x01, 0x00]);
+        assert_eq!(small.max_message_size(), 548);
+        assert_eq!(offer.max_message_size(), 548);
+    }
+
+    #[tokio::test]
+    async fn test_custom_options() {
+        use crate::config::{DhcpOptionConfig, DhcpOptionType, DhcpOptionValue, HostConfig};
+
+        let tftp_server = |ip: &str| DhcpOptionConfig {
+            code: 150,
+            kind: DhcpOptionType::IpList,
+            value: DhcpOptionValue::String(ip.to_string()),
+        };
+        let server = test_server_with(|config| {
+            config.options = vec![tftp_server("192.168.1.5")];
+            config.hosts = vec![HostConfig {
+                mac: Some("00:11:22:33:44:66".to_string()),
+                client_id: None,
+                uuid: None,
+                ip: "192.168.1.150".to_string(),
+                hostname: None,
+                boot_filename: None,
+                next_server: None,
+                options: vec![tftp_server("192.168.1.6")],
+            }];
+            config.scopes = vec![ScopeConfig {
+                name: None,
+                interface: None,
+                ip_pool_start: "10.20.0.100".to_string(),
+                ip_pool_end: "10.20.0.200".to_string(),
+                exclude: Vec::new(),
+                subnet_mask: "255.255.255.0".to_string(),
+                gateway: None,
+                dns_servers: None,
+                domain_name: None,
+                hostname_pattern: None,
+                relay_circuit_id: None,
+                relay_remote_id: None,
+                next_server: None,
+                protocols: None,
+                default_lease_time: None,
+                max_lease_time: None,
+                options: vec![tftp_server("10.20.0.5")],
+            }];
+        });
+
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &[]))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(150), Some(&[192, 168, 1, 5][..]));
+
+        let host = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let offer = handle(&server, &request(DHCP_DISCOVER, host, &[]))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(150), Some(&[192, 168, 1, 6][..]));
+
+        let mut relayed = request(DHCP_DISCOVER, MAC, &[]);
+        relayed.giaddr = "10.20.0.1".parse().unwrap();
+        let offer = handle(&server, &relayed).await.unwrap();
+        assert_eq!(offer.get_option(150), Some(&[10, 20, 0, 5][..]));
+    }
+
+    #[tokio::test]
+    async fn test_http_boot() {
+        let server = test_server().with_http(HttpConfig {
+            port: 8080,
+            root: "./http".to_string(),
+            listen: Vec::new(),
+        });
+
+        let mut options = vec![60, 10];
+        options.extend_from_slice(b"HTTPClient");
+        options.extend_from_slice(&[93, 2, 0, 16]); // x64 UEFI HTTP
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(60), Some(&b"HTTPClient"[..]));
+        assert_eq!(
+            offer.get_option(67),
+            Some(&b"http://192.168.1.1:8080/bootx64.efi"[..])
+        );
+
+        // TFTP clients keep getting a plain filename
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let offer = handle(&server, &request(DHCP_DISCOVER, mac2, &pxe_options()))
+            .await
+            .unwrap();
+        assert!(offer.get_option(60).is_none());
+        assert_eq!(offer.get_option(67), Some(&b"bootx64.efi"[..]));
+    }
+
+    #[test]
+    fn test_http_boot_url() {
+        let mut http = HttpConfig {
+            port: 80,
+            root: "./http".to_string(),
+            listen: Vec::new(),
+        };
+        let next_server = "10.0.0.1".parse().unwrap();
+        let server = test_server().with_http(http.clone());
+        assert_eq!(
+            server.http_boot_url("/efi/bootaa64.efi", next_server),
+            "http://10.0.0.1/efi/bootaa64.efi"
+        );
+
+        http.port = 8080;
+        let server = test_server().with_http(http);
+        assert_eq!(
+            server.http_boot_url("https://boot.example.com/ipxe.efi", next_server),
+            "https://boot.example.com/ipxe.efi"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ipxe_chainload() {
+        let server = test_server_with(|config| {
+            config.protocols.boot_filename_efi = Some("ipxe.efi".to_string());
+            config.protocols.boot_filename_legacy = Some("undionly.kpxe".to_string());
+            config.protocols.ipxe_script = Some("boot.ipxe".to_string());
+        })
+        .with_http(HttpConfig {
+            port: 8080,
+            root: "./http".to_string(),
+            listen: Vec::new(),
+        });
+
+        // UEFI firmware gets the iPXE binary
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &pxe_options()))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(67), Some(&b"ipxe.efi"[..]));
+
+        // iPXE itself gets the script
+        let mut options = pxe_options();
+        options.extend_from_slice(&[77, 4, b'i', b'P', b'X', b'E']);
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &options))
+            .await
+            .unwrap();
+        assert_eq!(
+            offer.get_option(67),
+            Some(&b"http://192.168.1.1:8080/boot.ipxe"[..])
+        );
+
+        // Legacy BIOS firmware, then iPXE recognised by its feature option
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let offer = handle(&server, &request(DHCP_DISCOVER, mac2, &[93, 2, 0, 0]))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(67), Some(&b"undionly.kpxe"[..]));
+        let options = [93, 2, 0, 0, 175, 3, 19, 1, 1];
+        let offer = handle(&server, &request(DHCP_DISCOVER, mac2, &options))
+            .await
+            .unwrap();
+        assert_eq!(
+            offer.get_option(67),
+            Some(&b"http://192.168.1.1:8080/boot.ipxe"[..])
+        );
+    }
+
+    fn proxy_server() -> DhcpServer {
+        test_server_with(|config| {
+            config.proxy_dhcp = true;
+        })
+    }
+
+    const PXE_CLIENT: &[u8] = b"PXEClient:Arch:00007:UNDI:003016";
+
+    fn pxe_options() -> Vec<u8> {
+        let mut options = vec![60, PXE_CLIENT.len() as u8];
+        options.extend_from_slice(PXE_CLIENT);
+        options.extend_from_slice(&[93, 2, 0, 7]);
+        options
+    }
+
+    #[tokio::test]
+    async fn test_proxy_dhcp_offer() {
+        let server = proxy_server();
+
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &pxe_options()))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_message_type(), Some(DHCP_OFFER));
+        assert_eq!(offer.yiaddr, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(offer.get_vendor_class(), Some(&b"PXEClient"[..]));
+        assert_eq!(offer.get_option(67), Some(&b"bootx64.efi"[..]));
+        assert_eq!(&offer.file[..12], b"bootx64.efi\0");
+        assert!(offer.get_option(1).is_none());
+        assert!(offer.get_option(51).is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
+
+        // Non-PXE clients and address requests are left to the real DHCP server
+        assert!(handle(&server, &request(DHCP_DISCOVER, MAC, &[]))
+            .await
+            .is_none());
+        assert!(handle(&server, &request(DHCP_REQUEST, MAC, &pxe_options()))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_boot_server_request() {
+        let server = proxy_server();
+        let mut boot_request = request(DHCP_REQUEST, MAC, &pxe_options());
+        boot_request.ciaddr = "10.0.0.50".parse().unwrap();
+
+        let ack = server
+            .handle_boot_server_request(&boot_request, &server.config)
+            .unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(ack.yiaddr, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(ack.ciaddr, boot_request.ciaddr);
+        assert_eq!(ack.get_option(67), Some(&b"bootx64.efi"[..]));
+
+        let discover = request(DHCP_DISCOVER, MAC, &pxe_options());
+        assert!(server
+            .handle_boot_server_request(&discover, &server.config)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pxe_boot_menu() {
+        use crate::config::{PxeMenuConfig, PxeMenuItemConfig};
+
+        let server = test_server_with(|config| {
+            config.pxe_menu = Some(PxeMenuConfig {
+                prompt: "Press F8 for boot menu".to_string(),
+                timeout: 10,
+                discovery_control: None,
+                items: vec![
+                    PxeMenuItemConfig {
+                        label: "Install".to_string(),
+                        boot_filename: Some("install.efi".to_string()),
+                        boot_server: None,
+                    },
+                    PxeMenuItemConfig {
+                        label: "Rescue".to_string(),
+                        boot_filename: Some("rescue.efi".to_string()),
+                        boot_server: Some("192.168.1.5".to_string()),
+                    },
+                    PxeMenuItemConfig {
+                        label: "Local disk".to_string(),
+                        boot_filename: None,
+                        boot_server: None,
+                    },
+                ],
+            });
+        });
+        let menu = server.pxe_menu.as_ref().unwrap().encode();
+
+        let offer = handle(&server, &request(DHCP_DISCOVER, MAC, &pxe_options()))
+            .await
+            .unwrap();
+        assert_eq!(offer.get_option(60), Some(&b"PXEClient"[..]));
+        assert_eq!(offer.get_option(43), Some(&menu[..]));
+
+        // Non-PXE clients get no menu
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let offer = handle(&server, &request(DHCP_DISCOVER, mac2, &[]))
+            .await
+            .unwrap();
+        assert!(offer.get_option(43).is_none());
+
+        // "Rescue" picked from the menu
+        let mut options = pxe_options();
+        options.extend_from_slice(&[43, 7, 71, 4, 0x80, 0x01, 0, 0, 255]);
+        let mut boot_request = request(DHCP_REQUEST, MAC, &options);
+        boot_request.ciaddr = "192.168.1.100".parse().unwrap();
+        let ack = server
+            .handle_boot_server_request(&boot_request, &server.config)
+            .unwrap();
+        assert_eq!(ack.get_option(67), Some(&b"rescue.efi"[..]));
+        assert_eq!(ack.siaddr, "192.168.1.5".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(
+            ack.get_option(43),
+            Some(&[71, 4, 0x80, 0x01, 0, 0, 255][..])
+        );
+
+        // Unknown boot server types are ignored
+        let mut options = pxe_options();
+        options.extend_from_slice(&[43, 7, 71, 4, 0x80, 0x09, 0, 0, 255]);
+        let boot_request = request(DHCP_REQUEST, MAC, &options);
+        assert!(server
+            .handle_boot_server_request(&boot_request, &server.config)
+            .is_none());
+    }
+
+    fn relay_server() -> DhcpServer {
+        test_server_with(|config| {
+            let scope = |start: &str, end: &str, circuit_id: Option<&str>| ScopeConfig {
+                name: None,
+                interface: None,
+                ip_pool_start: start.to_string(),
+                ip_pool_end: end.to_string(),
+                exclude: Vec::new(),
+                subnet_mask: "255.255.255.0".to_string(),
+                gateway: Some("10.20.0.1".to_string()),
+                dns_servers: None,
+                domain_name: None,
+                hostname_pattern: None,
+                relay_circuit_id: circuit_id.map(str::to_string),
+                relay_remote_id: None,
+                next_server: None,
+                protocols: None,
+                default_lease_time: None,
+                max_lease_time: None,
+                options: Vec::new(),
+            };
+            config.scopes = vec![
+                scope("10.20.0.10", "10.20.0.19", Some("rack7")),
+                scope("10.20.0.100", "10.20.0.200", None),
+            ];
+        })
+    }
+
+    #[tokio::test]
+    async fn test_relayed_request() {
+        let server = relay_server();
+        let relay_info = [82, 6, 1, 4, b'p', b'o', b'r', b't'];
+        let mut discover = request(DHCP_DISCOVER, MAC, &relay_info);
+        discover.giaddr = "10.20.0.1".parse().unwrap();
+
+        let offer = handle(&server, &discover).await.unwrap();
+        assert_eq!(offer.yiaddr, "10.20.0.100".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(offer.giaddr, discover.giaddr);
+        assert_eq!(offer.get_option(3), Some(&[10, 20, 0, 1][..]));
+        assert_eq!(offer.get_option(82), Some(&relay_info[2..]));
+
+        // The circuit-id policy selects the rack scope
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let mut discover = request(
+            DHCP_DISCOVER,
+            mac2,
+            &[82, 7, 1, 5, b'r', b'a', b'c', b'k', b'7'],
+        );
+        discover.giaddr = "10.20.0.1".parse().unwrap();
+        let offer = handle(&server, &discover).await.unwrap();
+        assert_eq!(offer.yiaddr, "10.20.0.10".parse::<Ipv4Addr>().unwrap());
+
+        // Unknown relay networks are ignored
+        discover.giaddr = "10.30.0.1".parse().unwrap();
+        assert!(handle(&server, &discover).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reply_ports() {
+        let server = test_server_with(|config| {
+            config.port = 1067;
+            config.client_port = 1068;
+        });
+
+        // Clients without an address get a frame to their hardware address
+        let mut discover = request(DHCP_DISCOVER, MAC, &[]);
+        let offer = handle(&server, &discover).await.unwrap();
+        assert_eq!(
+            server.reply_destination(&discover, &offer),
+            Destination::Frame {
+                mac: MAC,
+                src: SocketAddrV4::new("192.168.1.1".parse().unwrap(), 1067),
+                dest: SocketAddrV4::new(offer.yiaddr, 1068),
+            }
+        );
+
+        // unless they ask for a broadcast
+        discover.flags = BROADCAST_FLAG;
+        let offer = handle(&server, &discover).await.unwrap();
+        let broadcast = Destination::Udp(SocketAddrV4::new(Ipv4Addr::BROADCAST, 1068));
+        assert_eq!(server.reply_destination(&discover, &offer), broadcast);
+
+        // Renewing clients are answered at their address
+        let mut renew = request(DHCP_REQUEST, MAC, &[]);
+        renew.ciaddr = offer.yiaddr;
+        let ack = handle(&server, &renew).await.unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(
+            server.reply_destination(&renew, &ack),
+            Destination::Udp(SocketAddrV4::new(offer.yiaddr, 1068))
+        );
+
+        // NAKs are broadcast
+        renew.ciaddr = "192.168.1.250".parse().unwrap();
+        let nak = handle(&server, &renew).await.unwrap();
+        assert_eq!(nak.get_message_type(), Some(DHCP_NAK));
+        assert_eq!(server.reply_destination(&renew, &nak), broadcast);
+
+        // Relay agents are answered on the server port
+        discover.giaddr = "192.168.1.2".parse().unwrap();
+        let offer = handle(&server, &discover).await.unwrap();
+        assert_eq!(
+            server.reply_destination(&discover, &offer),
+            Destination::Udp(SocketAddrV4::new(discover.giaddr, 1067))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relayed_nak_is_broadcast() {
+        let server = relay_server();
+        discover(&server, MAC).await; // Lease on the local network
+
+        // Client rebooted behind the relay, still asking for its old address
+        let mut options = vec![50, 4];
+        options.extend_from_slice(&[10, 0, 0, 5]);
+        let mut reboot = request(DHCP_REQUEST, MAC, &options);
+        reboot.giaddr = "10.20.0.1".parse().unwrap();
+        let nak = handle(&server, &reboot).await.unwrap();
+        assert_eq!(nak.get_message_type(), Some(DHCP_NAK));
+        assert_eq!(nak.giaddr, reboot.giaddr);
+        assert_eq!(nak.flags & BROADCAST_FLAG, BROADCAST_FLAG);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_interface_scope() {
+        use crate::config::ProtocolConfig;
+
+        let server = test_server_with(|config| {
+            config.scopes = vec![ScopeConfig {
+                name: Some("loopback".to_string()),
+                interface: Some("lo".to_string()),
+                ip_pool_start: "10.30.0.100".to_string(),
+                ip_pool_end: "10.30.0.200".to_string(),
+                exclude: Vec::new(),
+                subnet_mask: "255.255.255.0".to_string(),
+                gateway: None,
+                dns_servers: Some(Vec::new()),
+                domain_name: None,
+                hostname_pattern: None,
+                relay_circuit_id: None,
+                relay_remote_id: None,
+                next_server: Some("10.30.0.1".to_string()),
+                protocols: Some(ProtocolConfig {
+                    efi: false,
+                    legacy: true,
+                    dhcp_boot: false,
+                    boot_filename_efi: None,
+                    boot_filename_legacy: Some("lab.kpxe".to_string()),
+                    boot_filename_dhcp_boot: None,
+                    boot_filenames: Default::default(),
+                    ipxe_script: None,
+                }),
+                default_lease_time: Some(600),
+                max_lease_time: None,
+                options: Vec::new(),
+            }];
+        });
+        assert_eq!(server.bind_interface(), None);
+
+        let loopback = interface_index("lo");
+        let offer = server
+            .process(&request(DHCP_DISCOVER, MAC, &[]), loopback)
+            .await
+            .unwrap();
+        assert_eq!(offer.yiaddr, "10.30.0.100".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(offer.siaddr, "10.30.0.1".parse::<Ipv4Addr>().unwrap());
+        assert_eq!(offer.get_option(67), Some(&b"lab.kpxe"[..]));
+        assert_eq!(offer.get_option(51), Some(&600u32.to_be_bytes()[..]));
+        assert!(offer.get_option(3).is_none());
+        assert!(offer.get_option(6).is_none());
+
+        // Other interfaces fall back to the main [dhcp] scope
+        let mac2 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+        let offer = server
+            .process(&request(DHCP_DISCOVER, mac2, &[]), Some(u32::MAX))
+            .await
+            .unwrap();
+        assert_eq!(offer.yiaddr, "192.168.1.100".parse::<Ipv4Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_unknown_scope_interface() {
+        let mut config = test_config();
+        config.scopes = vec![ScopeConfig {
+            name: None,
+            interface: Some("does-not-exist0".to_string()),
+            ip_pool_start: "10.30.0.100".to_string(),
+            ip_pool_end: "10.30.0.200".to_string(),
+            exclude: Vec::new(),
+            subnet_mask: "255.255.255.0".to_string(),
+            gateway: None,
+            dns_servers: None,
+            domain_name: None,
+            hostname_pattern: None,
+            relay_circuit_id: None,
+            relay_remote_id: None,
+            next_server: None,
+            protocols: None,
+            default_lease_time: None,
+            max_lease_time: None,
+            options: Vec::new(),
+        }];
+        assert!(DhcpServer::new(config).is_err());
+    }
+
+    #[test]
+    fn test_unknown_interface() {
+        assert!(interface_index(LOOPBACK).is_some());
+        assert!(interface_index("does-not-exist0").is_none());
+
+        let mut config = test_config();
+        config.interface = Some("does-not-exist0".to_string());
+        assert!(DhcpServer::new(config).is_err());
+        let mut config = test_config();
+        config.interface = Some(LOOPBACK.to_string());
+        assert!(DhcpServer::new(config).is_ok());
+
+        // An empty name means no interface
+        let mut config = test_config();
+        config.interface = Some(String::new());
+        assert!(DhcpServer::new(config).is_ok());
+    }
 
-        let mac1 = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
-        let ip1 = pool.allocate(mac1).unwrap();
-        assert_eq!(ip1, start);
+    #[tokio::test]
+    async fn test_inform() {
+        let server = test_server();
+        let mut inform = request(DHCP_INFORM, MAC, &[]);
+        inform.ciaddr = "192.168.1.50".parse().unwrap();
 
-        let ip1_again = pool.allocate(mac1).unwrap();
-        assert_eq!(ip1_again, ip1); // Same MAC gets same IP
+        let ack = handle(&server, &inform).await.unwrap();
+        assert_eq!(ack.get_message_type(), Some(DHCP_ACK));
+        assert_eq!(ack.yiaddr, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(ack.ciaddr, inform.ciaddr);
+        assert!(ack.get_option(51).is_none());
+        assert!(server.ip_pool.lease(MAC).is_none());
     }
 }
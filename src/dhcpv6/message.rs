@@ -0,0 +1,352 @@
+//! DHCPv6 messages and options (RFC 8415)
+
+use std::net::Ipv6Addr;
+
+// Message types
+pub const SOLICIT: u8 = 1;
+pub const ADVERTISE: u8 = 2;
+pub const REQUEST: u8 = 3;
+pub const RENEW: u8 = 5;
+pub const REBIND: u8 = 6;
+pub const REPLY: u8 = 7;
+pub const RELEASE: u8 = 8;
+pub const INFORMATION_REQUEST: u8 = 11;
+
+// Option codes
+pub const OPTION_CLIENTID: u16 = 1;
+pub const OPTION_SERVERID: u16 = 2;
+pub const OPTION_IA_NA: u16 = 3;
+pub const OPTION_IAADDR: u16 = 5;
+pub const OPTION_ORO: u16 = 6;
+pub const OPTION_STATUS_CODE: u16 = 13;
+pub const OPTION_RAPID_COMMIT: u16 = 14;
+pub const OPTION_USER_CLASS: u16 = 15;
+pub const OPTION_VENDOR_CLASS: u16 = 16;
+pub const OPTION_DNS_SERVERS: u16 = 23;
+/// Boot file URL (RFC 5970)
+pub const OPTION_BOOTFILE_URL: u16 = 59;
+/// Client system architecture types (RFC 5970)
+pub const OPTION_CLIENT_ARCH_TYPE: u16 = 61;
+
+// DUID types (RFC 8415, section 11; RFC 6355)
+const DUID_LLT: u16 = 1;
+const DUID_LL: u16 = 3;
+const DUID_UUID: u16 = 4;
+const HARDWARE_ETHERNET: u16 = 1;
+
+// Status codes
+pub const STATUS_SUCCESS: u16 = 0;
+pub const STATUS_NO_ADDRS_AVAIL: u16 = 2;
+pub const STATUS_NO_BINDING: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dhcpv6Message {
+    pub msg_type: u8,
+    pub transaction_id: [u8; 3],
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+/// An identity association for non-temporary addresses (RFC 8415, section 21.4)
+#[derive(Debug, Clone, PartialEq)]
+pub struct IaNa {
+    pub iaid: u32,
+    pub t1: u32,
+    pub t2: u32,
+    /// Addresses with their preferred and valid lifetimes
+    pub addresses: Vec<(Ipv6Addr, u32, u32)>,
+    pub status: Option<(u16, String)>,
+}
+
+impl Dhcpv6Message {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Dhcpv6Message {
+            msg_type: data[0],
+            transaction_id: [data[1], data[2], data[3]],
+            options: parse_options(&data[4..])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![self.msg_type];
+        data.extend_from_slice(&self.transaction_id);
+        data.extend(encode_options(&self.options));
+        data
+    }
+
+    pub fn get_option(&self, code: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub fn add_option(&mut self, code: u16, data: Vec<u8>) {
+        self.options.push((code, data));
+    }
+
+    pub fn client_id(&self) -> Option<&[u8]> {
+        self.get_option(OPTION_CLIENTID).filter(|id| !id.is_empty())
+    }
+
+    pub fn ia_na(&self) -> Vec<IaNa> {
+        self.options
+            .iter()
+            .filter(|(code, _)| *code == OPTION_IA_NA)
+            .filter_map(|(_, data)| IaNa::from_bytes(data))
+            .collect()
+    }
+
+    /// First architecture of the client architecture option (61)
+    pub fn client_arch(&self) -> Option<u16> {
+        self.get_option(OPTION_CLIENT_ARCH_TYPE)
+            .filter(|data| data.len() >= 2)
+            .map(|data| u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    /// Data items of the vendor class (option 16), after the enterprise
+    /// number
+    pub fn vendor_classes(&self) -> Vec<&[u8]> {
+        self.get_option(OPTION_VENDOR_CLASS)
+            .and_then(|data| data.get(4..))
+            .map(|items| class_items(items).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the client sent a vendor class (option 16) data item
+    /// starting with `prefix`, e.g. "HTTPClient"
+    pub fn has_vendor_class(&self, prefix: &[u8]) -> bool {
+        self.vendor_classes()
+            .iter()
+            .any(|item| item.starts_with(prefix))
+    }
+
+    /// Whether the client sent a user class (option 15) data item `class`,
+    /// e.g. "iPXE"
+    pub fn has_user_class(&self, class: &[u8]) -> bool {
+        self.get_option(OPTION_USER_CLASS)
+            .is_some_and(|items| class_items(items).any(|item| item == class))
+    }
+
+    /// Options requested by the client (option 6)
+    pub fn requested_options(&self) -> Vec<u16> {
+        self.get_option(OPTION_ORO)
+            .map(|data| {
+                data.chunks_exact(2)
+                    .map(|code| u16::from_be_bytes([code[0], code[1]]))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl IaNa {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 12 {
+            return None;
+        }
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let mut ia = IaNa {
+            iaid: u32_at(0),
+            t1: u32_at(4),
+            t2: u32_at(8),
+            addresses: Vec::new(),
+            status: None,
+        };
+        for (code, option) in parse_options(&data[12..])? {
+            match code {
+                OPTION_IAADDR if option.len() >= 24 => {
+                    let octets: [u8; 16] = option[..16].try_into().ok()?;
+                    let lifetime =
+                        |i: usize| u32::from_be_bytes(option[i..i + 4].try_into().unwrap());
+                    ia.addresses
+                        .push((Ipv6Addr::from(octets), lifetime(16), lifetime(20)));
+                }
+                OPTION_STATUS_CODE if option.len() >= 2 => {
+                    let status = u16::from_be_bytes([option[0], option[1]]);
+                    let message = String::from_utf8_lossy(&option[2..]).into_owned();
+                    ia.status = Some((status, message));
+                }
+                _ => {}
+            }
+        }
+        Some(ia)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.iaid.to_be_bytes());
+        data.extend_from_slice(&self.t1.to_be_bytes());
+        data.extend_from_slice(&self.t2.to_be_bytes());
+        let mut options = Vec::new();
+        for (addr, preferred, valid) in &self.addresses {
+            let mut option = addr.octets().to_vec();
+            option.extend_from_slice(&preferred.to_be_bytes());
+            option.extend_from_slice(&valid.to_be_bytes());
+            options.push((OPTION_IAADDR, option));
+        }
+        if let Some((status, ref message)) = self.status {
+            options.push((OPTION_STATUS_CODE, status_code(status, message)));
+        }
+        data.extend(encode_options(&options));
+        data
+    }
+}
+
+pub fn message_type_name(msg_type: u8) -> &'static str {
+    match msg_type {
+        SOLICIT => "Solicit",
+        ADVERTISE => "Advertise",
+        REQUEST => "Request",
+        RENEW => "Renew",
+        REBIND => "Rebind",
+        REPLY => "Reply",
+        RELEASE => "Release",
+        INFORMATION_REQUEST => "Information-request",
+        _ => "Unknown",
+    }
+}
+
+/// Status code option data (RFC 8415, section 21.13)
+pub fn status_code(status: u16, message: &str) -> Vec<u8> {
+    let mut data = status.to_be_bytes().to_vec();
+    data.extend_from_slice(message.as_bytes());
+    data
+}
+
+fn parse_options(mut data: &[u8]) -> Option<Vec<(u16, Vec<u8>)>> {
+    let mut options = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return None;
+        }
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let value = data.get(4..4 + len)?;
+        options.push((code, value.to_vec()));
+        data = &data[4 + len..];
+    }
+    Some(options)
+}
+
+fn encode_options(options: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (code, value) in options {
+        data.extend_from_slice(&code.to_be_bytes());
+        data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+/// Ethernet address of a DUID-LLT or DUID-LL
+pub fn duid_mac(duid: &[u8]) -> Option<[u8; 6]> {
+    let u16_at = |i: usize| Some(u16::from_be_bytes([*duid.get(i)?, *duid.get(i + 1)?]));
+    if u16_at(2)? != HARDWARE_ETHERNET {
+        return None;
+    }
+    let address = match u16_at(0)? {
+        DUID_LLT => duid.get(8..)?,
+        DUID_LL => duid.get(4..)?,
+        _ => return None,
+    };
+    address.try_into().ok()
+}
+
+/// Machine UUID of a DUID-UUID
+pub fn duid_uuid(duid: &[u8]) -> Option<&[u8]> {
+    let uuid = duid.get(2..).filter(|uuid| uuid.len() == 16)?;
+    (u16::from_be_bytes([duid[0], duid[1]]) == DUID_UUID).then_some(uuid)
+}
+
+/// Items of a user or vendor class option, each prefixed by a 16-bit length
+fn class_items(mut data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        let len = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+        let item = data.get(2..2 + len)?;
+        data = &data[2 + len..];
+        Some(item)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let ia = IaNa {
+            iaid: 1,
+            t1: 1800,
+            t2: 2880,
+            addresses: vec![("2001:db8::10".parse().unwrap(), 3600, 7200)],
+            status: Some((STATUS_SUCCESS, "ok".to_string())),
+        };
+        let mut message = Dhcpv6Message {
+            msg_type: REPLY,
+            transaction_id: [1, 2, 3],
+            options: Vec::new(),
+        };
+        message.add_option(OPTION_CLIENTID, vec![0, 3, 0, 1, 2, 3, 4, 5, 6, 7]);
+        message.add_option(OPTION_IA_NA, ia.to_bytes());
+
+        let parsed = Dhcpv6Message::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.ia_na(), vec![ia]);
+        assert_eq!(
+            parsed.client_id(),
+            Some(&[0, 3, 0, 1, 2, 3, 4, 5, 6, 7][..])
+        );
+    }
+
+    #[test]
+    fn test_client_classes() {
+        let mut message = Dhcpv6Message {
+            msg_type: SOLICIT,
+            transaction_id: [0; 3],
+            options: Vec::new(),
+        };
+        message.add_option(OPTION_CLIENT_ARCH_TYPE, vec![0, 16]);
+        // Enterprise number 343, then "HTTPClient:Arch:00016"
+        let mut vendor = 343u32.to_be_bytes().to_vec();
+        vendor.extend_from_slice(&21u16.to_be_bytes());
+        vendor.extend_from_slice(b"HTTPClient:Arch:00016");
+        message.add_option(OPTION_VENDOR_CLASS, vendor);
+        message.add_option(OPTION_USER_CLASS, vec![0, 4, b'i', b'P', b'X', b'E']);
+        message.add_option(OPTION_ORO, vec![0, 23, 0, 59]);
+
+        assert_eq!(message.client_arch(), Some(16));
+        assert!(message.has_vendor_class(b"HTTPClient"));
+        assert!(!message.has_vendor_class(b"PXEClient"));
+        assert!(message.has_user_class(b"iPXE"));
+        assert_eq!(message.requested_options(), vec![23, 59]);
+    }
+
+    #[test]
+    fn test_duid() {
+        let mac = [0x52, 0x54, 0x00, 0xab, 0xcd, 0xef];
+        let ll = [&[0, 3, 0, 1][..], &mac].concat();
+        let llt = [&[0, 1, 0, 1, 0x2a, 0, 0, 0][..], &mac].concat();
+        assert_eq!(duid_mac(&ll), Some(mac));
+        assert_eq!(duid_mac(&llt), Some(mac));
+        // Not Ethernet
+        assert_eq!(duid_mac(&[0, 3, 0, 6, 1, 2]), None);
+
+        let uuid = [0x11; 16];
+        let duid = [&[0, 4][..], &uuid].concat();
+        assert_eq!(duid_uuid(&duid), Some(&uuid[..]));
+        assert_eq!(duid_mac(&duid), None);
+        assert_eq!(duid_uuid(&ll), None);
+    }
+
+    #[test]
+    fn test_truncated_messages() {
+        assert!(Dhcpv6Message::from_bytes(&[SOLICIT, 0, 0]).is_none());
+        // Option length past the end of the message
+        assert!(Dhcpv6Message::from_bytes(&[SOLICIT, 0, 0, 0, 0, 1, 0, 8, 0]).is_none());
+        assert!(IaNa::from_bytes(&[0; 8]).is_none());
+    }
+}
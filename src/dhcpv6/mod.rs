@@ -0,0 +1,5 @@
+pub mod message;
+pub mod pool;
+pub mod server;
+
+pub use server::Dhcpv6Server;
@@ -0,0 +1,401 @@
+//! IA_NA address assignment out of a range of the configured prefix
+
+use crate::dhcp::leases::{self, format_mac, LeaseChange, LeaseStore, Record, Snapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+
+/// A client's identity association: its DUID and IAID
+type IaKey = (Vec<u8>, u32);
+
+/// Bindings always kept before expired ones are dropped
+const MIN_PRUNE_LEN: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(with = "hex_format")]
+    pub duid: Vec<u8>,
+    pub iaid: u32,
+    pub addr: Ipv6Addr,
+    /// Binding expiry, in seconds since the Unix epoch
+    pub expiry: u64,
+}
+
+impl Binding {
+    fn ia(&self) -> IaKey {
+        (self.duid.clone(), self.iaid)
+    }
+}
+
+impl Record for Binding {
+    fn key(&self) -> String {
+        format!("{}/{}", format_mac(&self.duid), self.iaid)
+    }
+
+    fn address(&self) -> IpAddr {
+        IpAddr::V6(self.addr)
+    }
+}
+
+struct Bindings {
+    by_ia: HashMap<IaKey, Binding>,
+    by_addr: HashMap<Ipv6Addr, IaKey>,
+    /// Offset the search for a free address starts from
+    next: u128,
+    /// Number of bindings at which expired ones are dropped
+    prune_at: usize,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            by_ia: HashMap::new(),
+            by_addr: HashMap::new(),
+            next: 0,
+            prune_at: MIN_PRUNE_LEN,
+        }
+    }
+}
+
+impl Bindings {
+    fn insert(&mut self, binding: Binding) {
+        let key = binding.ia();
+        let addr = binding.addr;
+        if let Some(old) = self.by_ia.insert(key.clone(), binding) {
+            self.by_addr.remove(&old.addr);
+        }
+        if let Some(other) = self.by_addr.insert(addr, key.clone()) {
+            if other != key {
+                self.by_ia.remove(&other);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &IaKey) -> Option<Binding> {
+        let binding = self.by_ia.remove(key)?;
+        self.by_addr.remove(&binding.addr);
+        Some(binding)
+    }
+
+    fn is_free(&self, addr: Ipv6Addr, now: u64) -> bool {
+        self.by_addr
+            .get(&addr)
+            .and_then(|key| self.by_ia.get(key))
+            .is_none_or(|binding| binding.expiry <= now)
+    }
+
+    /// Drop the expired bindings once there are twice as many bindings as
+    /// after the last time, so a flood of clients that never come back
+    /// doesn't grow the table for good
+    fn prune(&mut self, now: u64) {
+        if self.by_ia.len() < self.prune_at {
+            return;
+        }
+        self.by_ia.retain(|_, binding| binding.expiry > now);
+        let by_ia = &self.by_ia;
+        self.by_addr.retain(|_, key| by_ia.contains_key(key));
+        self.prune_at = (self.by_ia.len() * 2).max(MIN_PRUNE_LEN);
+    }
+}
+
+impl Snapshot<Binding> for Bindings {
+    fn count(&self) -> usize {
+        self.by_ia.len()
+    }
+
+    fn snapshot(&self) -> Vec<Binding> {
+        self.by_ia.values().cloned().collect()
+    }
+}
+
+/// Addresses handed out from `start..=end`. Bindings are written to the
+/// lease store as they are made, and loaded back on startup.
+pub struct AddressPool {
+    start: u128,
+    end: u128,
+    bindings: Mutex<Bindings>,
+    store: LeaseStore<Binding>,
+}
+
+impl AddressPool {
+    pub fn new(start: Ipv6Addr, end: Ipv6Addr, store: LeaseStore<Binding>) -> Result<Self, String> {
+        if start > end {
+            return Err(format!("Address pool {}-{} is empty", start, end));
+        }
+        let mut pool = AddressPool {
+            start: u128::from(start),
+            end: u128::from(end),
+            bindings: Mutex::new(Bindings::default()),
+            store,
+        };
+        pool.load()
+            .map_err(|e| format!("Failed to load DHCPv6 leases: {}", e))?;
+        Ok(pool)
+    }
+
+    /// Restore the saved bindings that are still valid and in the pool
+    fn load(&mut self) -> io::Result<()> {
+        let now = leases::now();
+        let bindings = self.bindings.get_mut().unwrap();
+        for binding in self.store.load()? {
+            if binding.expiry > now && (self.start..=self.end).contains(&u128::from(binding.addr)) {
+                bindings.insert(binding);
+            }
+        }
+        // Fold the journal into the database
+        self.store.save(bindings.by_ia.values())
+    }
+
+    pub fn contains(&self, addr: Ipv6Addr) -> bool {
+        (self.start..=self.end).contains(&u128::from(addr))
+    }
+
+    /// Address for the client's IA, held for `hold` seconds: the one it is
+    /// bound to, the `hint` it asked for when free, or the next free one.
+    /// The hold is kept in memory only, see `bind`.
+    pub fn allocate(
+        &self,
+        duid: &[u8],
+        iaid: u32,
+        hint: Option<Ipv6Addr>,
+        hold: u32,
+    ) -> Option<Ipv6Addr> {
+        self.reserve(duid, iaid, hint, hold, false)
+    }
+
+    /// Like `allocate`, for an address the client is going to use: the
+    /// binding is written to the lease store
+    pub fn bind(
+        &self,
+        duid: &[u8],
+        iaid: u32,
+        hint: Option<Ipv6Addr>,
+        valid: u32,
+    ) -> Option<Ipv6Addr> {
+        self.reserve(duid, iaid, hint, valid, true)
+    }
+
+    fn reserve(
+        &self,
+        duid: &[u8],
+        iaid: u32,
+        hint: Option<Ipv6Addr>,
+        hold: u32,
+        commit: bool,
+    ) -> Option<Ipv6Addr> {
+        let now = leases::now();
+        let key = (duid.to_vec(), iaid);
+        let mut bindings = self.bindings.lock().unwrap();
+
+        let addr = match bindings.by_ia.get(&key) {
+            Some(binding) => binding.addr,
+            None => {
+                bindings.prune(now);
+                match hint.filter(|&addr| self.contains(addr) && bindings.is_free(addr, now)) {
+                    Some(addr) => addr,
+                    None => self.find_free(&mut bindings, now)?,
+                }
+            }
+        };
+        let expiry = bindings
+            .by_ia
+            .get(&key)
+            .map_or(0, |binding| binding.expiry)
+            .max(now + hold as u64);
+        let binding = Binding {
+            duid: duid.to_vec(),
+            iaid,
+            addr,
+            expiry,
+        };
+        bindings.insert(binding.clone());
+        if commit {
+            self.store.commit(bindings, LeaseChange::Lease(binding));
+        }
+        Some(addr)
+    }
+
+    /// At most one address per binding is taken, so the search ends after
+    /// as many steps as there are bindings
+    fn find_free(&self, bindings: &mut Bindings, now: u64) -> Option<Ipv6Addr> {
+        let len = self.end - self.start + 1;
+        let tries = (bindings.by_ia.len() as u128 + 1).min(len);
+        for i in 0..tries {
+            let offset = (bindings.next + i) % len;
+            let addr = Ipv6Addr::from(self.start + offset);
+            if bindings.is_free(addr, now) {
+                bindings.next = (offset + 1) % len;
+                return Some(addr);
+            }
+        }
+        None
+    }
+
+    /// Extend the client's binding on `addr` to `valid` seconds from now
+    pub fn renew(&self, duid: &[u8], iaid: u32, addr: Ipv6Addr, valid: u32) -> bool {
+        let mut bindings = self.bindings.lock().unwrap();
+        match bindings.by_ia.get_mut(&(duid.to_vec(), iaid)) {
+            Some(binding) if binding.addr == addr => {
+                binding.expiry = leases::now() + valid as u64;
+                let change = LeaseChange::Lease(binding.clone());
+                self.store.commit(bindings, change);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn release(&self, duid: &[u8], iaid: u32, addr: Ipv6Addr) -> bool {
+        let key = (duid.to_vec(), iaid);
+        let mut bindings = self.bindings.lock().unwrap();
+        if bindings.by_ia.get(&key).is_some_and(|b| b.addr == addr) {
+            if let Some(binding) = bindings.remove(&key) {
+                self.store
+                    .commit(bindings, LeaseChange::Remove(binding.key()));
+            }
+            return true;
+        }
+        false
+    }
+
+    pub fn binding(&self, duid: &[u8], iaid: u32) -> Option<Binding> {
+        self.bindings
+            .lock()
+            .unwrap()
+            .by_ia
+            .get(&(duid.to_vec(), iaid))
+            .cloned()
+    }
+}
+
+mod hex_format {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crate::dhcp::leases::format_mac(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        crate::dhcp::options::parse_hex(&s)
+            .ok_or_else(|| D::Error::custom(format!("invalid DUID: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn addr(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allocate() {
+        let pool = AddressPool::new(
+            addr("2001:db8::10"),
+            addr("2001:db8::11"),
+            LeaseStore::new(None),
+        )
+        .unwrap();
+        let first = pool.allocate(b"client1", 1, None, 60).unwrap();
+        assert_eq!(first, addr("2001:db8::10"));
+        // Same IA, same address; another IA of the client gets its own
+        assert_eq!(pool.allocate(b"client1", 1, None, 60), Some(first));
+        assert_eq!(
+            pool.allocate(b"client1", 2, Some(first), 60),
+            Some(addr("2001:db8::11"))
+        );
+        assert_eq!(pool.allocate(b"client2", 1, None, 60), None);
+
+        assert!(pool.release(b"client1", 1, first));
+        assert_eq!(pool.allocate(b"client2", 1, None, 60), Some(first));
+    }
+
+    #[test]
+    fn test_hint_and_expiry() {
+        let pool = AddressPool::new(
+            addr("2001:db8::1"),
+            addr("2001:db8::ffff"),
+            LeaseStore::new(None),
+        )
+        .unwrap();
+        assert_eq!(
+            pool.allocate(b"client1", 1, Some(addr("2001:db8::42")), 60),
+            Some(addr("2001:db8::42"))
+        );
+        // Hints out of the pool are ignored
+        assert_eq!(
+            pool.allocate(b"client2", 1, Some(addr("2001:db9::1")), 60),
+            Some(addr("2001:db8::1"))
+        );
+
+        assert!(pool.renew(b"client1", 1, addr("2001:db8::42"), 3600));
+        assert!(!pool.renew(b"client1", 1, addr("2001:db8::43"), 3600));
+        assert!(!pool.renew(b"client3", 1, addr("2001:db8::42"), 3600));
+
+        // Expired bindings free their address
+        pool.bindings
+            .lock()
+            .unwrap()
+            .by_ia
+            .get_mut(&(b"client1".to_vec(), 1))
+            .unwrap()
+            .expiry = 0;
+        assert_eq!(
+            pool.allocate(b"client3", 1, Some(addr("2001:db8::42")), 60),
+            Some(addr("2001:db8::42"))
+        );
+        assert!(pool.binding(b"client1", 1).is_none());
+    }
+
+    #[test]
+    fn test_saved_bindings() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("leases6.json");
+        let open = || {
+            let store = LeaseStore::new(Some(path.clone()));
+            AddressPool::new(addr("2001:db8::1"), addr("2001:db8::ff"), store).unwrap()
+        };
+
+        let pool = open();
+        let bound = pool.bind(b"client1", 1, None, 3600).unwrap();
+        let released = pool.bind(b"client2", 1, None, 3600).unwrap();
+        assert!(pool.release(b"client2", 1, released));
+        // Advertised addresses are not saved
+        pool.allocate(b"client3", 1, None, 60).unwrap();
+        assert!(pool.renew(b"client1", 1, bound, 7200));
+        drop(pool);
+
+        let pool = open();
+        let binding = pool.binding(b"client1", 1).unwrap();
+        assert_eq!(binding.addr, bound);
+        assert!(binding.expiry >= leases::now() + 7000);
+        assert!(pool.binding(b"client2", 1).is_none());
+        assert!(pool.binding(b"client3", 1).is_none());
+        // The address is not handed out again
+        assert_ne!(pool.allocate(b"client4", 1, Some(bound), 60), Some(bound));
+    }
+
+    #[test]
+    fn test_prune_expired() {
+        let pool = AddressPool::new(
+            addr("2001:db8::"),
+            addr("2001:db8::ffff:ffff"),
+            LeaseStore::new(None),
+        )
+        .unwrap();
+        // Clients that never come back after a Solicit
+        for n in 0..3 * MIN_PRUNE_LEN as u32 {
+            pool.allocate(&n.to_be_bytes(), 1, None, 0).unwrap();
+        }
+        let bound = pool.allocate(b"client1", 1, None, 60).unwrap();
+        let bindings = pool.bindings.lock().unwrap();
+        assert!(bindings.by_ia.len() <= MIN_PRUNE_LEN);
+        assert_eq!(bindings.by_ia.len(), bindings.by_addr.len());
+        assert_eq!(bindings.by_addr[&bound], (b"client1".to_vec(), 1));
+    }
+}
@@ -0,0 +1,678 @@
+use crate::config::{DhcpConfig, Dhcpv6Config, HttpConfig, ProtocolConfig};
+use crate::dhcp::access::{AccessControl, Client};
+use crate::dhcp::leases::{format_mac, LeaseStore};
+use crate::dhcp::options::{parse_hex, HTTP_CLIENT_CLASS, IPXE_USER_CLASS};
+use crate::dhcp::protocols::{ClientArch, ProtocolHandler};
+use crate::dhcp::reservations::Reservations;
+use crate::dhcp::server::{configured_interface, interface_index};
+use crate::dhcpv6::message::{
+    message_type_name, status_code, Dhcpv6Message, IaNa, ADVERTISE, INFORMATION_REQUEST,
+    OPTION_BOOTFILE_URL, OPTION_CLIENTID, OPTION_DNS_SERVERS, OPTION_IA_NA, OPTION_RAPID_COMMIT,
+    OPTION_SERVERID, OPTION_STATUS_CODE, REBIND, RELEASE, RENEW, REPLY, REQUEST, SOLICIT,
+    STATUS_NO_ADDRS_AVAIL, STATUS_NO_BINDING, STATUS_SUCCESS,
+};
+use crate::dhcpv6::pool::AddressPool;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use tokio::net::UdpSocket;
+use tracing as log;
+
+/// All_DHCP_Relay_Agents_and_Servers (RFC 8415, section 7.1)
+const ALL_DHCP_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+/// How long an advertised address is held for a client before it may be reused
+const ADVERTISE_HOLD_TIME: u32 = 60;
+
+/// DUID based on the link-layer address, for Ethernet (RFC 8415, section 11.4)
+const DUID_LL_ETHERNET: [u8; 4] = [0, 3, 0, 1];
+
+pub struct Dhcpv6Server {
+    config: Dhcpv6Config,
+    protocols: ProtocolConfig,
+    access: AccessControl,
+    reservations: Reservations,
+    http: Option<HttpConfig>,
+    server_id: Vec<u8>,
+    pool: AddressPool,
+    boot_server: Ipv6Addr,
+    dns_servers: Vec<Ipv6Addr>,
+}
+
+impl Dhcpv6Server {
+    /// `dhcp` is the `[dhcp]` section: its access lists and host
+    /// reservations apply, and its boot policy unless the DHCPv6 section has
+    /// its own
+    pub fn new(
+        config: Dhcpv6Config,
+        dhcp: &DhcpConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let protocols = config
+            .protocols
+            .clone()
+            .unwrap_or_else(|| dhcp.protocols.clone());
+        ProtocolHandler::validate(&protocols)?;
+        let access = AccessControl::from_config(&dhcp.access)?;
+        let reservations = Reservations::from_config(&dhcp.hosts)?;
+
+        let (network, mask) = parse_prefix(&config.prefix)?;
+        let in_prefix = |addr: Ipv6Addr| u128::from(addr) & mask == network;
+        let parse = |addr: &str| {
+            addr.parse::<Ipv6Addr>()
+                .ok()
+                .filter(|&addr| in_prefix(addr))
+                .ok_or_else(|| format!("Invalid address in prefix {}: {}", config.prefix, addr))
+        };
+        let start = match config.pool_start {
+            Some(ref start) => parse(start)?,
+            None => Ipv6Addr::from(network + 1),
+        };
+        let end = match config.pool_end {
+            Some(ref end) => parse(end)?,
+            None => Ipv6Addr::from(network | !mask),
+        };
+        let store = LeaseStore::new(config.lease_file.as_ref().map(PathBuf::from));
+        let pool = AddressPool::new(start, end, store)?;
+
+        let boot_server = config
+            .boot_server
+            .parse()
+            .map_err(|_| format!("Invalid DHCPv6 boot_server: {}", config.boot_server))?;
+        let dns_servers = config
+            .dns_servers
+            .iter()
+            .map(|ip| {
+                ip.parse()
+                    .map_err(|_| format!("Invalid DHCPv6 DNS server: {}", ip))
+            })
+            .collect::<Result<_, _>>()?;
+        let server_id = match config.server_duid {
+            Some(ref duid) => {
+                parse_hex(duid).ok_or_else(|| format!("Invalid server DUID: {}", duid))?
+            }
+            None => hardware_duid(configured_interface(&config.interface))?,
+        };
+
+        Ok(Dhcpv6Server {
+            config,
+            protocols,
+            access,
+            reservations,
+            http: None,
+            server_id,
+            pool,
+            boot_server,
+            dns_servers,
+        })
+    }
+
+    /// HTTP server settings, used to build HTTP boot URLs
+    pub fn with_http(mut self, http: HttpConfig) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let socket = self.bind_socket()?;
+        log::info!("DHCPv6 server listening on port {}", self.config.port);
+
+        let mut buf = vec![0u8; 1500];
+        loop {
+            let (size, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    log::error!("DHCPv6 receive error: {}", e);
+                    continue;
+                }
+            };
+            let Some(request) = Dhcpv6Message::from_bytes(&buf[..size]) else {
+                continue;
+            };
+            log::info!(
+                "Received DHCPv6 {} from {}",
+                message_type_name(request.msg_type),
+                peer
+            );
+
+            // Link-local clients are answered on the interface the request
+            // came from, through the scope id of `peer`
+            if let Some(reply) = self.handle(&request) {
+                match socket.send_to(&reply.to_bytes(), peer).await {
+                    Ok(_) => log::info!(
+                        "Sent DHCPv6 {} to {}",
+                        message_type_name(reply.msg_type),
+                        peer
+                    ),
+                    Err(e) => log::error!("Failed to send DHCPv6 reply: {}", e),
+                }
+            }
+        }
+    }
+
+    fn bind_socket(&self) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.set_reuse_address(true)?;
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, self.config.port));
+        socket.bind(&addr.into())?;
+
+        // Clients send to the servers multicast group, on the default
+        // multicast interface unless one is configured
+        let ifindex = match configured_interface(&self.config.interface) {
+            Some(interface) => interface_index(interface)
+                .ok_or_else(|| format!("Unknown DHCPv6 interface: {}", interface))?,
+            None => 0,
+        };
+        socket.join_multicast_v6(&ALL_DHCP_SERVERS, ifindex)?;
+
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    /// Answer a client message, `None` when it is not for this server
+    pub fn handle(&self, request: &Dhcpv6Message) -> Option<Dhcpv6Message> {
+        if !self.is_allowed(request) {
+            return None;
+        }
+        let server_id = request.get_option(OPTION_SERVERID);
+        let for_us = server_id == Some(&self.server_id[..]);
+
+        match request.msg_type {
+            SOLICIT if server_id.is_none() => {
+                let client_id = request.client_id()?;
+                // Rapid commit (RFC 8415, section 18.3.1) skips the Request
+                let rapid_commit = request.get_option(OPTION_RAPID_COMMIT).is_some();
+                let msg_type = if rapid_commit { REPLY } else { ADVERTISE };
+
+                let mut reply = self.reply(request, msg_type);
+                if rapid_commit {
+                    reply.add_option(OPTION_RAPID_COMMIT, Vec::new());
+                }
+                for ia in request.ia_na() {
+                    reply.add_option(
+                        OPTION_IA_NA,
+                        self.assign(client_id, &ia, rapid_commit).to_bytes(),
+                    );
+                }
+                self.add_config_options(request, &mut reply);
+                Some(reply)
+            }
+            REQUEST | RENEW if for_us => {
+                let client_id = request.client_id()?;
+                let mut reply = self.reply(request, REPLY);
+                for ia in request.ia_na() {
+                    let ia = if request.msg_type == REQUEST {
+                        self.assign(client_id, &ia, true)
+                    } else {
+                        self.renew(client_id, &ia)
+                    };
+                    reply.add_option(OPTION_IA_NA, ia.to_bytes());
+                }
+                self.add_config_options(request, &mut reply);
+                Some(reply)
+            }
+            // Sent to any server once the one that assigned the address is
+            // unreachable
+            REBIND if server_id.is_none() => {
+                let client_id = request.client_id()?;
+                let mut reply = self.reply(request, REPLY);
+                for ia in request.ia_na() {
+                    reply.add_option(OPTION_IA_NA, self.renew(client_id, &ia).to_bytes());
+                }
+                self.add_config_options(request, &mut reply);
+                Some(reply)
+            }
+            RELEASE if for_us => {
+                let client_id = request.client_id()?;
+                for ia in request.ia_na() {
+                    for (addr, _, _) in ia.addresses {
+                        if self.pool.release(client_id, ia.iaid, addr) {
+                            log::info!("Released DHCPv6 address {}", addr);
+                        }
+                    }
+                }
+                let mut reply = self.reply(request, REPLY);
+                reply.add_option(
+                    OPTION_STATUS_CODE,
+                    status_code(STATUS_SUCCESS, "Release received"),
+                );
+                Some(reply)
+            }
+            INFORMATION_REQUEST if server_id.is_none() || for_us => {
+                let mut reply = self.reply(request, REPLY);
+                self.add_config_options(request, &mut reply);
+                Some(reply)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the access lists of `[dhcp]` let the client be answered
+    fn is_allowed(&self, request: &Dhcpv6Message) -> bool {
+        let client = Client::from_dhcpv6(request);
+        let known = self.reservations.knows(&client);
+        match self.access.check(&client, known) {
+            Ok(()) => true,
+            Err(reason) => {
+                log::debug!(
+                    "Ignoring DHCPv6 client {}, {}",
+                    format_mac(request.client_id().unwrap_or_default()),
+                    reason
+                );
+                false
+            }
+        }
+    }
+
+    fn reply(&self, request: &Dhcpv6Message, msg_type: u8) -> Dhcpv6Message {
+        let mut reply = Dhcpv6Message {
+            msg_type,
+            transaction_id: request.transaction_id,
+            options: Vec::new(),
+        };
+        reply.add_option(OPTION_SERVERID, self.server_id.clone());
+        if let Some(client_id) = request.client_id() {
+            reply.add_option(OPTION_CLIENTID, client_id.to_vec());
+        }
+        reply
+    }
+
+    /// Give the IA an address: bound to the client when it `commit`s to it,
+    /// held for a while when only advertised
+    fn assign(&self, client_id: &[u8], ia: &IaNa, commit: bool) -> IaNa {
+        let hint = ia.addresses.first().map(|(addr, _, _)| *addr);
+        let addr = if commit {
+            self.pool
+                .bind(client_id, ia.iaid, hint, self.config.valid_lifetime)
+        } else {
+            self.pool
+                .allocate(client_id, ia.iaid, hint, ADVERTISE_HOLD_TIME)
+        };
+        match addr {
+            Some(addr) => {
+                log::info!("Allocated IPv6 address: {} for client", addr);
+                self.ia_with(ia.iaid, addr)
+            }
+            None => {
+                log::warn!("DHCPv6 address pool exhausted");
+                ia_status(ia.iaid, STATUS_NO_ADDRS_AVAIL, "No addresses available")
+            }
+        }
+    }
+
+    /// Extend the lifetime of the IA's address
+    fn renew(&self, client_id: &[u8], ia: &IaNa) -> IaNa {
+        let binding = self.pool.binding(client_id, ia.iaid).filter(|binding| {
+            ia.addresses.is_empty()
+                || ia
+                    .addresses
+                    .iter()
+                    .any(|(addr, _, _)| *addr == binding.addr)
+        });
+        match binding {
+            Some(binding) => {
+                self.pool
+                    .renew(client_id, ia.iaid, binding.addr, self.config.valid_lifetime);
+                self.ia_with(ia.iaid, binding.addr)
+            }
+            None => ia_status(ia.iaid, STATUS_NO_BINDING, "No binding for this IA"),
+        }
+    }
+
+    fn ia_with(&self, iaid: u32, addr: Ipv6Addr) -> IaNa {
+        let preferred = self.config.preferred_lifetime;
+        IaNa {
+            iaid,
+            // Renew at half the preferred lifetime, rebind at 80%
+            t1: preferred / 2,
+            t2: (preferred as u64 * 4 / 5) as u32,
+            addresses: vec![(addr, preferred, self.config.valid_lifetime)],
+            status: None,
+        }
+    }
+
+    /// DNS servers and boot file URL, when requested
+    fn add_config_options(&self, request: &Dhcpv6Message, reply: &mut Dhcpv6Message) {
+        let requested = request.requested_options();
+        if requested.contains(&OPTION_DNS_SERVERS) && !self.dns_servers.is_empty() {
+            let servers = self.dns_servers.iter().flat_map(|ip| ip.octets()).collect();
+            reply.add_option(OPTION_DNS_SERVERS, servers);
+        }
+        // Netboot clients send their architecture, some without asking for
+        // the URL
+        if requested.contains(&OPTION_BOOTFILE_URL) || request.client_arch().is_some() {
+            if let Some(url) = self.boot_url(request) {
+                reply.add_option(OPTION_BOOTFILE_URL, url.into_bytes());
+            }
+        }
+    }
+
+    /// Boot file URL (option 59): the iPXE script for clients already
+    /// running iPXE, or the file for the client's architecture, over HTTP for
+    /// UEFI HTTP boot clients and TFTP otherwise
+    fn boot_url(&self, request: &Dhcpv6Message) -> Option<String> {
+        if let Some(ref script) = self.protocols.ipxe_script {
+            if request.has_user_class(IPXE_USER_CLASS) {
+                let url = self.http_url(script);
+                log::info!("iPXE client, boot script: {}", url);
+                return Some(url);
+            }
+        }
+
+        let client_arch = request.client_arch();
+        if let Some(arch) = client_arch.map(ClientArch::from_code) {
+            log::debug!("Client architecture: {}", arch);
+        }
+        let (protocol, filename) =
+            ProtocolHandler::select_boot_filename(&self.protocols, client_arch)?;
+        log::info!(
+            "Selected protocol: {:?}, boot filename: {}",
+            protocol,
+            filename
+        );
+
+        if request.has_vendor_class(HTTP_CLIENT_CLASS) {
+            return Some(self.http_url(&filename));
+        }
+        if filename.contains("://") {
+            return Some(filename);
+        }
+        Some(format!(
+            "tftp://[{}]/{}",
+            self.boot_server,
+            filename.trim_start_matches('/')
+        ))
+    }
+
+    fn http_url(&self, filename: &str) -> String {
+        if filename.contains("://") {
+            return filename.to_string();
+        }
+        let port = self.http.as_ref().map_or(80, |http| http.port);
+        let path = filename.trim_start_matches('/');
+        if port == 80 {
+            format!("http://[{}]/{}", self.boot_server, path)
+        } else {
+            format!("http://[{}]:{}/{}", self.boot_server, port, path)
+        }
+    }
+}
+
+fn ia_status(iaid: u32, status: u16, message: &str) -> IaNa {
+    IaNa {
+        iaid,
+        t1: 0,
+        t2: 0,
+        addresses: Vec::new(),
+        status: Some((status, message.to_string())),
+    }
+}
+
+/// Parse "2001:db8::/64" into the network and its mask
+fn parse_prefix(prefix: &str) -> Result<(u128, u128), String> {
+    let invalid = || format!("Invalid DHCPv6 prefix: {}", prefix);
+    let (addr, len) = prefix.split_once('/').ok_or_else(invalid)?;
+    let addr: Ipv6Addr = addr.parse().map_err(|_| invalid())?;
+    let len: u32 = len.parse().map_err(|_| invalid())?;
+    if len > 128 {
+        return Err(invalid());
+    }
+    let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+    Ok((u128::from(addr) & mask, mask))
+}
+
+/// DUID-LL from the hardware address of `interface`, or of the first
+/// interface that has one
+fn hardware_duid(interface: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut names = match interface {
+        Some(interface) => vec![interface.to_string()],
+        None => std::fs::read_dir("/sys/class/net")
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+    names.sort();
+
+    names
+        .iter()
+        .filter_map(|name| std::fs::read_to_string(format!("/sys/class/net/{}/address", name)).ok())
+        .filter_map(|mac| crate::dhcp::leases::parse_mac(mac.trim()))
+        .find(|mac| mac.iter().any(|&b| b != 0))
+        .map(|mac| [&DUID_LL_ETHERNET[..], &mac].concat())
+        .ok_or_else(|| {
+            "No hardware address for the DHCPv6 server DUID, set server_duid".to_string()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::dhcpv6::message::{OPTION_CLIENT_ARCH_TYPE, OPTION_ORO, OPTION_VENDOR_CLASS};
+
+    const CLIENT_ID: &[u8] = &[0, 3, 0, 1, 0x52, 0x54, 0, 0, 0, 1];
+    const SERVER_ID: &[u8] = &[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 1];
+
+    fn config() -> Dhcpv6Config {
+        Dhcpv6Config {
+            port: 547,
+            interface: None,
+            prefix: "2001:db8:1::/64".to_string(),
+            pool_start: Some("2001:db8:1::100".to_string()),
+            pool_end: Some("2001:db8:1::101".to_string()),
+            boot_server: "2001:db8:1::1".to_string(),
+            dns_servers: vec!["2001:db8:1::53".to_string()],
+            preferred_lifetime: 3600,
+            valid_lifetime: 7200,
+            lease_file: None,
+            server_duid: Some("00:03:00:01:02:00:00:00:00:01".to_string()),
+            protocols: None,
+        }
+    }
+
+    fn test_server() -> Dhcpv6Server {
+        Dhcpv6Server::new(config(), &Config::default().dhcp).unwrap()
+    }
+
+    fn message(msg_type: u8, iaid: u32) -> Dhcpv6Message {
+        let mut message = Dhcpv6Message {
+            msg_type,
+            transaction_id: [0xab, 0xcd, 0xef],
+            options: Vec::new(),
+        };
+        message.add_option(OPTION_CLIENTID, CLIENT_ID.to_vec());
+        message.add_option(OPTION_ORO, vec![0, 23, 0, 59]);
+        let ia = IaNa {
+            iaid,
+            t1: 0,
+            t2: 0,
+            addresses: Vec::new(),
+            status: None,
+        };
+        message.add_option(OPTION_IA_NA, ia.to_bytes());
+        message
+    }
+
+    fn address(reply: &Dhcpv6Message) -> Ipv6Addr {
+        reply.ia_na()[0].addresses[0].0
+    }
+
+    #[test]
+    fn test_solicit_request() {
+        let server = test_server();
+        let mut solicit = message(SOLICIT, 1);
+        solicit.add_option(OPTION_CLIENT_ARCH_TYPE, vec![0, 7]);
+
+        let advertise = server.handle(&solicit).unwrap();
+        assert_eq!(advertise.msg_type, ADVERTISE);
+        assert_eq!(advertise.transaction_id, solicit.transaction_id);
+        assert_eq!(advertise.get_option(OPTION_SERVERID), Some(SERVER_ID));
+        assert_eq!(advertise.client_id(), Some(CLIENT_ID));
+        let offered = address(&advertise);
+        assert_eq!(offered, "2001:db8:1::100".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(advertise.ia_na()[0].t1, 1800);
+        assert_eq!(
+            advertise.get_option(OPTION_DNS_SERVERS),
+            Some(&"2001:db8:1::53".parse::<Ipv6Addr>().unwrap().octets()[..])
+        );
+        assert_eq!(
+            advertise.get_option(OPTION_BOOTFILE_URL),
+            Some(&b"tftp://[2001:db8:1::1]/bootx64.efi"[..])
+        );
+
+        // Request for another server is ignored
+        let mut request = message(REQUEST, 1);
+        request.add_option(OPTION_SERVERID, vec![0, 3, 0, 1, 2, 2, 2, 2, 2, 2]);
+        assert!(server.handle(&request).is_none());
+
+        let mut request = message(REQUEST, 1);
+        request.add_option(OPTION_SERVERID, SERVER_ID.to_vec());
+        let reply = server.handle(&request).unwrap();
+        assert_eq!(reply.msg_type, REPLY);
+        assert_eq!(address(&reply), offered);
+        let binding = server.pool.binding(CLIENT_ID, 1).unwrap();
+        assert!(binding.expiry >= crate::dhcp::leases::now() + 7000);
+
+        // Another client finds the second address, then nothing
+        let mut other = message(SOLICIT, 1);
+        other.options[0].1 = vec![0, 3, 0, 1, 0x52, 0x54, 0, 0, 0, 2];
+        assert_ne!(address(&server.handle(&other).unwrap()), offered);
+        let mut third = message(SOLICIT, 1);
+        third.options[0].1 = vec![0, 3, 0, 1, 0x52, 0x54, 0, 0, 0, 3];
+        let ia = &server.handle(&third).unwrap().ia_na()[0];
+        assert!(ia.addresses.is_empty());
+        assert_eq!(ia.status.as_ref().unwrap().0, STATUS_NO_ADDRS_AVAIL);
+    }
+
+    #[test]
+    fn test_renew_release() {
+        let server = test_server();
+        let mut solicit = message(SOLICIT, 7);
+        solicit.add_option(OPTION_RAPID_COMMIT, Vec::new());
+        let reply = server.handle(&solicit).unwrap();
+        assert_eq!(reply.msg_type, REPLY);
+        assert!(reply.get_option(OPTION_RAPID_COMMIT).is_some());
+        let addr = address(&reply);
+
+        let mut renew = message(RENEW, 7);
+        renew.add_option(OPTION_SERVERID, SERVER_ID.to_vec());
+        assert_eq!(address(&server.handle(&renew).unwrap()), addr);
+
+        // Unknown IAs have no binding
+        let rebind = message(REBIND, 8);
+        let ia = &server.handle(&rebind).unwrap().ia_na()[0];
+        assert_eq!(ia.status.as_ref().unwrap().0, STATUS_NO_BINDING);
+
+        let mut release = message(RELEASE, 7);
+        release.options.retain(|(code, _)| *code != OPTION_IA_NA);
+        let ia = IaNa {
+            iaid: 7,
+            t1: 0,
+            t2: 0,
+            addresses: vec![(addr, 0, 0)],
+            status: None,
+        };
+        release.add_option(OPTION_IA_NA, ia.to_bytes());
+        release.add_option(OPTION_SERVERID, SERVER_ID.to_vec());
+        let reply = server.handle(&release).unwrap();
+        assert_eq!(
+            reply
+                .get_option(OPTION_STATUS_CODE)
+                .map(|status| &status[..2]),
+            Some(&[0, 0][..])
+        );
+        assert!(server.pool.binding(CLIENT_ID, 7).is_none());
+    }
+
+    #[test]
+    fn test_http_boot_url() {
+        let server = test_server().with_http(HttpConfig {
+            port: 8080,
+            root: "./http".to_string(),
+            listen: Vec::new(),
+        });
+        let mut solicit = message(SOLICIT, 1);
+        solicit.add_option(OPTION_CLIENT_ARCH_TYPE, vec![0, 16]);
+        let mut vendor = 343u32.to_be_bytes().to_vec();
+        vendor.extend_from_slice(&21u16.to_be_bytes());
+        vendor.extend_from_slice(b"HTTPClient:Arch:00016");
+        solicit.add_option(OPTION_VENDOR_CLASS, vendor);
+
+        let advertise = server.handle(&solicit).unwrap();
+        assert_eq!(
+            advertise.get_option(OPTION_BOOTFILE_URL),
+            Some(&b"http://[2001:db8:1::1]:8080/bootx64.efi"[..])
+        );
+
+        // The architecture table of [dhcp.protocols] applies
+        let mut dhcp = Config::default().dhcp;
+        dhcp.protocols
+            .boot_filenames
+            .insert("arm64_uefi".to_string(), "grubaa64.efi".to_string());
+        let server = Dhcpv6Server::new(config(), &dhcp).unwrap();
+        let mut solicit = message(INFORMATION_REQUEST, 1);
+        solicit.add_option(OPTION_CLIENT_ARCH_TYPE, vec![0, 11]);
+        assert_eq!(
+            server
+                .handle(&solicit)
+                .unwrap()
+                .get_option(OPTION_BOOTFILE_URL),
+            Some(&b"tftp://[2001:db8:1::1]/grubaa64.efi"[..])
+        );
+    }
+
+    #[test]
+    fn test_access_control() {
+        use crate::config::HostConfig;
+
+        // CLIENT_ID is a DUID-LL for 52:54:00:00:00:01
+        let mut dhcp = Config::default().dhcp;
+        dhcp.access.deny.oui = vec!["52:54:00".to_string()];
+        let server = Dhcpv6Server::new(config(), &dhcp).unwrap();
+        assert!(server.handle(&message(SOLICIT, 1)).is_none());
+
+        let mut dhcp = Config::default().dhcp;
+        dhcp.access.known_hosts_only = true;
+        let server = Dhcpv6Server::new(config(), &dhcp).unwrap();
+        assert!(server.handle(&message(SOLICIT, 1)).is_none());
+
+        dhcp.hosts = vec![HostConfig {
+            mac: Some("52:54:00:00:00:01".to_string()),
+            client_id: None,
+            uuid: None,
+            ip: "192.168.1.150".to_string(),
+            hostname: None,
+            boot_filename: None,
+            next_server: None,
+            options: Vec::new(),
+        }];
+        let server = Dhcpv6Server::new(config(), &dhcp).unwrap();
+        assert!(server.handle(&message(SOLICIT, 1)).is_some());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let dhcp = Config::default().dhcp;
+        let mut bad = config();
+        bad.prefix = "2001:db8:1::".to_string();
+        assert!(Dhcpv6Server::new(bad, &dhcp).is_err());
+
+        let mut bad = config();
+        bad.pool_end = Some("2001:db8:2::1".to_string());
+        assert!(Dhcpv6Server::new(bad, &dhcp).is_err());
+
+        // Without a range, the whole prefix but its first address
+        let mut whole = config();
+        whole.prefix = "2001:db8:1::/120".to_string();
+        whole.pool_start = None;
+        whole.pool_end = None;
+        let server = Dhcpv6Server::new(whole, &dhcp).unwrap();
+        assert!(server.pool.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(server.pool.contains("2001:db8:1::ff".parse().unwrap()));
+        assert!(!server.pool.contains("2001:db8:1::".parse().unwrap()));
+        assert!(!server.pool.contains("2001:db8:1::100".parse().unwrap()));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::filesystem::FileSystem;
+use crate::listen;
 use axum::{
     http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
     response::{IntoResponse, Response},
@@ -6,34 +7,46 @@ use axum::{
     Router,
 };
 use bytes::Bytes;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tracing as log;
 
 pub struct HttpServer {
-    port: u16,
+    listen: Vec<SocketAddr>,
     filesystem: Arc<dyn FileSystem>,
 }
 
 impl HttpServer {
     pub fn new(port: u16, filesystem: Box<dyn FileSystem>) -> Self {
         HttpServer {
-            port,
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], port))],
             filesystem: Arc::from(filesystem),
         }
     }
 
+    /// Listen on these addresses instead of 0.0.0.0
+    pub fn with_listen(mut self, listen: Vec<SocketAddr>) -> Self {
+        self.listen = listen;
+        self
+    }
+
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
         let filesystem = Arc::clone(&self.filesystem);
         let app = Router::new()
-            .route("/*path", get(Self::handle_request))
+            .route("/{*path}", get(Self::handle_request))
             .with_state(filesystem);
 
-        use std::net::SocketAddr;
-        let addr: SocketAddr = format!("0.0.0.0:{}", self.port).parse()?;
-        log::info!("HTTP server listening on port {}", self.port);
-
-        let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, app).await?;
+        let mut servers = tokio::task::JoinSet::new();
+        for &addr in &self.listen {
+            let listener = listen::bind_tcp(addr, &self.listen)
+                .map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+            log::info!("HTTP server listening on {}", addr);
+            let app = app.clone();
+            servers.spawn(async move { axum::serve(listener, app).await });
+        }
+        while let Some(result) = servers.join_next().await {
+            result??;
+        }
 
         Ok(())
     }
@@ -1,6 +1,8 @@
 pub mod config;
 pub mod dhcp;
+pub mod dhcpv6;
 pub mod filesystem;
 pub mod http;
+pub mod listen;
 pub mod server;
 pub mod tftp;
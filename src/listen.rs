@@ -0,0 +1,149 @@
+//! Listen addresses of the TFTP and HTTP servers
+
+use crate::dhcp::server::interface_index;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+/// Parse listen addresses such as "0.0.0.0", "::", "[2001:db8::1]" or
+/// "fe80::1%eth0", where link-local addresses need the interface, by name or
+/// index
+pub fn parse_listen(listen: &[String], port: u16) -> Result<Vec<SocketAddr>, String> {
+    if listen.is_empty() {
+        return Err("No listen address".to_string());
+    }
+    listen
+        .iter()
+        .map(|addr| parse_address(addr, port))
+        .collect()
+}
+
+fn parse_address(addr: &str, port: u16) -> Result<SocketAddr, String> {
+    let invalid = || format!("Invalid listen address: {}", addr);
+    let trimmed = addr.trim_start_matches('[').trim_end_matches(']');
+    let (ip, scope) = match trimmed.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (trimmed, None),
+    };
+
+    match ip.parse::<IpAddr>().map_err(|_| invalid())? {
+        IpAddr::V4(ip) if scope.is_none() => Ok(SocketAddr::from((ip, port))),
+        IpAddr::V4(_) => Err(invalid()),
+        IpAddr::V6(ip) => {
+            let scope_id = match scope {
+                Some(scope) => scope
+                    .parse()
+                    .ok()
+                    .or_else(|| interface_index(scope))
+                    .ok_or_else(|| format!("Unknown interface in listen address: {}", addr))?,
+                None if is_link_local(ip) => {
+                    return Err(format!(
+                        "Link-local listen address {} needs an interface, e.g. {}%eth0",
+                        addr, ip
+                    ))
+                }
+                None => 0,
+            };
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+        }
+    }
+}
+
+fn is_link_local(ip: Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Create a socket for `addr`, one of `all` the listen addresses. `::` is
+/// dual-stack unless IPv4 addresses are listened on separately.
+fn socket(addr: SocketAddr, all: &[SocketAddr], kind: Type) -> io::Result<Socket> {
+    let protocol = if kind == Type::STREAM {
+        Protocol::TCP
+    } else {
+        Protocol::UDP
+    };
+    let socket = Socket::new(Domain::for_address(addr), kind, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(all.iter().any(|other| other.is_ipv4()))?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+pub fn bind_udp(addr: SocketAddr, all: &[SocketAddr]) -> io::Result<tokio::net::UdpSocket> {
+    let socket = socket(addr, all, Type::DGRAM)?;
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+pub fn bind_tcp(addr: SocketAddr, all: &[SocketAddr]) -> io::Result<tokio::net::TcpListener> {
+    let socket = socket(addr, all, Type::STREAM)?;
+    socket.listen(1024)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::server::tests::LOOPBACK;
+
+    fn parse(addr: &str) -> Result<SocketAddr, String> {
+        parse_address(addr, 69)
+    }
+
+    #[test]
+    fn test_parse_listen() {
+        assert_eq!(parse("0.0.0.0"), Ok("0.0.0.0:69".parse().unwrap()));
+        assert_eq!(parse("::"), Ok("[::]:69".parse().unwrap()));
+        assert_eq!(
+            parse("[2001:db8::1]"),
+            Ok("[2001:db8::1]:69".parse().unwrap())
+        );
+        assert_eq!(
+            parse("fe80::1%3"),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                69,
+                0,
+                3
+            )))
+        );
+        assert!(parse("fe80::1").is_err());
+        assert!(parse("192.168.1.1%eth0").is_err());
+        assert!(parse("fe80::1%no-such-interface0").is_err());
+        let loopback = interface_index(LOOPBACK).unwrap();
+        assert_eq!(
+            parse(&format!("[fe80::1%{}]", LOOPBACK)),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                69,
+                0,
+                loopback
+            )))
+        );
+        assert!(parse("localhost").is_err());
+        assert!(parse_listen(&[], 69).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_dual_stack() {
+        let all = parse_listen(&["::".to_string()], 0).unwrap();
+        let socket = bind_udp(all[0], &all).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        // IPv4 clients reach the dual-stack socket, as mapped addresses
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"ping", ("127.0.0.1", port)).await.unwrap();
+        let mut buf = [0u8; 4];
+        let (_, peer) = socket.recv_from(&mut buf).await.unwrap();
+        assert!(matches!(peer, SocketAddr::V6(peer) if peer.ip().to_ipv4_mapped().is_some()));
+
+        // Next to an IPv4 address, `::` is IPv6 only
+        let all = parse_listen(&["0.0.0.0".to_string(), "::".to_string()], port).unwrap();
+        drop(socket);
+        let v4 = bind_udp(all[0], &all).unwrap();
+        let v6 = bind_udp(all[1], &all).unwrap();
+        assert_eq!(v4.local_addr().unwrap().port(), port);
+        assert_eq!(v6.local_addr().unwrap().port(), port);
+    }
+}
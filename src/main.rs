@@ -3,8 +3,10 @@ use std::path::PathBuf;
 
 mod config;
 mod dhcp;
+mod dhcpv6;
 mod filesystem;
 mod http;
+mod listen;
 mod server;
 mod tftp;
 
@@ -36,6 +38,10 @@ enum Commands {
         #[arg(long)]
         dhcp_port: Option<u16>,
 
+        /// Port DHCP replies are sent to on clients
+        #[arg(long)]
+        dhcp_client_port: Option<u16>,
+
         /// TFTP server port
         #[arg(long)]
         tftp_port: Option<u16>,
@@ -67,6 +73,18 @@ enum Commands {
         /// Enable DHCP-boot protocol
         #[arg(long)]
         enable_dhcp_boot: Option<bool>,
+
+        /// Run as proxyDHCP alongside an existing DHCP server
+        #[arg(long)]
+        proxy_dhcp: Option<bool>,
+
+        /// Answer plain BOOTP clients
+        #[arg(long)]
+        bootp: Option<bool>,
+
+        /// Only answer clients with a host reservation
+        #[arg(long)]
+        known_hosts_only: Option<bool>,
     },
 }
 
@@ -87,6 +105,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(Commands::Start {
             config: config_path,
             dhcp_port,
+            dhcp_client_port,
             tftp_port,
             http_port,
             tftp_root,
@@ -95,6 +114,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             enable_efi,
             enable_legacy,
             enable_dhcp_boot,
+            proxy_dhcp,
+            bootp,
+            known_hosts_only,
         }) => {
             let mut config = if let Some(config_path) = config_path {
                 config::Config::from_file(&config_path)?
@@ -106,6 +128,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             if let Some(port) = dhcp_port {
                 config.dhcp.port = port;
             }
+            if let Some(port) = dhcp_client_port {
+                config.dhcp.client_port = port;
+            }
             if let Some(port) = tftp_port {
                 config.tftp.port = port;
             }
@@ -130,6 +155,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             if let Some(enabled) = enable_dhcp_boot {
                 config.dhcp.protocols.dhcp_boot = enabled;
             }
+            if let Some(enabled) = proxy_dhcp {
+                config.dhcp.proxy_dhcp = enabled;
+            }
+            if let Some(enabled) = bootp {
+                config.dhcp.bootp = enabled;
+            }
+            if let Some(enabled) = known_hosts_only {
+                config.dhcp.access.known_hosts_only = enabled;
+            }
 
             server::Server::new(config)?.start().await?;
         }
@@ -1,7 +1,9 @@
 use crate::config::Config;
 use crate::dhcp::DhcpServer;
+use crate::dhcpv6::Dhcpv6Server;
 use crate::filesystem;
 use crate::http::HttpServer;
+use crate::listen;
 use crate::tftp::TftpServer;
 use tokio::signal;
 use tracing as log;
@@ -23,9 +25,21 @@ impl Server {
         let http_fs = filesystem::create_filesystem(&self.config.http.root)?;
 
         // Create servers
-        let dhcp_server = DhcpServer::new(self.config.dhcp.clone())?;
-        let tftp_server = TftpServer::new(self.config.tftp.port, tftp_fs);
-        let http_server = HttpServer::new(self.config.http.port, http_fs);
+        let dhcp_server =
+            DhcpServer::new(self.config.dhcp.clone())?.with_http(self.config.http.clone());
+        let dhcpv6_server = match self.config.dhcpv6 {
+            Some(ref config) => Some(
+                Dhcpv6Server::new(config.clone(), &self.config.dhcp)?
+                    .with_http(self.config.http.clone()),
+            ),
+            None => None,
+        };
+        let tftp_server = TftpServer::new(self.config.tftp.port, tftp_fs).with_listen(
+            listen::parse_listen(&self.config.tftp.listen, self.config.tftp.port)?,
+        );
+        let http_server = HttpServer::new(self.config.http.port, http_fs).with_listen(
+            listen::parse_listen(&self.config.http.listen, self.config.http.port)?,
+        );
 
         log::info!("All servers initialized");
 
@@ -36,6 +50,15 @@ impl Server {
             }
         });
 
+        let dhcpv6_handle = tokio::spawn(async move {
+            let Some(dhcpv6_server) = dhcpv6_server else {
+                return std::future::pending().await;
+            };
+            if let Err(e) = dhcpv6_server.start().await {
+                log::error!("DHCPv6 server error: {}", e);
+            }
+        });
+
         let tftp_handle = tokio::spawn(async move {
             if let Err(e) = tftp_server.start().await {
                 log::error!("TFTP server error: {}", e);
@@ -56,6 +79,9 @@ impl Server {
             _ = dhcp_handle => {
                 log::warn!("DHCP server stopped");
             }
+            _ = dhcpv6_handle => {
+                log::warn!("DHCPv6 server stopped");
+            }
             _ = tftp_handle => {
                 log::warn!("TFTP server stopped");
             }
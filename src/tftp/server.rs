@@ -1,6 +1,7 @@
 use crate::filesystem::FileSystem;
+use crate::listen;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, SocketAddrV6};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
@@ -9,6 +10,9 @@ use tracing as log;
 const BLOCK_SIZE: usize = 512;
 const MAX_PACKET_SIZE: usize = 516; // 4 bytes header + 512 bytes data
 
+/// Active transfers: peer -> sender channel
+type Transfers = Arc<tokio::sync::Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>>;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TftpOpcode {
     ReadRequest = 1,
@@ -90,32 +94,45 @@ impl TftpPacket {
 }
 
 pub struct TftpServer {
-    port: u16,
+    listen: Vec<SocketAddr>,
     filesystem: Arc<dyn FileSystem>,
 }
 
 impl TftpServer {
     pub fn new(port: u16, filesystem: Box<dyn FileSystem>) -> Self {
         TftpServer {
-            port,
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], port))],
             filesystem: Arc::from(filesystem),
         }
     }
 
+    /// Listen on these addresses instead of 0.0.0.0
+    pub fn with_listen(mut self, listen: Vec<SocketAddr>) -> Self {
+        self.listen = listen;
+        self
+    }
+
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let socket = Arc::new(UdpSocket::bind(format!("0.0.0.0:{}", self.port)).await?);
-        log::info!("TFTP server listening on port {}", self.port);
+        let mut servers = tokio::task::JoinSet::new();
+        for &addr in &self.listen {
+            let socket = listen::bind_udp(addr, &self.listen)
+                .map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+            log::info!("TFTP server listening on {}", addr);
+            servers.spawn(Self::serve(Arc::new(socket), Arc::clone(&self.filesystem)));
+        }
+        while servers.join_next().await.is_some() {}
 
-        let mut buf = vec![0u8; MAX_PACKET_SIZE];
-        let filesystem = Arc::clone(&self.filesystem);
+        Ok(())
+    }
 
-        // Track active transfers: peer -> sender channel
-        let active_transfers: Arc<tokio::sync::Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>> =
-            Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    async fn serve(socket: Arc<UdpSocket>, filesystem: Arc<dyn FileSystem>) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let active_transfers: Transfers = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
 
         loop {
             match socket.recv_from(&mut buf).await {
                 Ok((size, peer)) => {
+                    let key = transfer_key(peer);
                     let data = &buf[..size];
                     if let Ok(packet) = TftpPacket::parse(data) {
                         match packet.opcode {
@@ -126,13 +143,14 @@ impl TftpServer {
 
                                 // Create a channel for this transfer
                                 let (tx, rx) = mpsc::channel::<Vec<u8>>(10);
-                                active_transfers_clone.lock().await.insert(peer, tx);
+                                active_transfers_clone.lock().await.insert(key, tx);
 
                                 if let Some(filename) = packet.extract_filename() {
                                     log::info!("TFTP read request for: {} from {}", filename, peer);
                                     tokio::spawn(Self::handle_read_with_channel(
                                         socket_clone,
                                         peer,
+                                        key,
                                         filename,
                                         filesystem_clone,
                                         active_transfers_clone,
@@ -145,7 +163,7 @@ impl TftpServer {
                                 let active_transfers_clone = Arc::clone(&active_transfers);
                                 let tx_opt = {
                                     let transfers = active_transfers_clone.lock().await;
-                                    transfers.get(&peer).cloned()
+                                    transfers.get(&key).cloned()
                                 };
 
                                 if let Some(tx) = tx_opt {
@@ -154,7 +172,7 @@ impl TftpServer {
                                             "Failed to send ACK to transfer handler for {}",
                                             peer
                                         );
-                                        active_transfers_clone.lock().await.remove(&peer);
+                                        active_transfers_clone.lock().await.remove(&key);
                                     }
                                 } else {
                                     log::warn!("Received ACK from {} but no active transfer", peer);
@@ -186,9 +204,10 @@ impl TftpServer {
     async fn handle_read_with_channel(
         socket: Arc<UdpSocket>,
         peer: SocketAddr,
+        key: SocketAddr,
         filename: String,
         filesystem: Arc<dyn FileSystem>,
-        active_transfers: Arc<tokio::sync::Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>>,
+        active_transfers: Transfers,
         mut ack_rx: mpsc::Receiver<Vec<u8>>,
     ) {
         // Normalize filename (remove leading slash if present)
@@ -246,7 +265,7 @@ impl TftpServer {
                                     filename,
                                     file_data.len()
                                 );
-                                active_transfers.lock().await.remove(&peer);
+                                active_transfers.lock().await.remove(&key);
                                 return;
                             }
 
@@ -261,23 +280,23 @@ impl TftpServer {
                                 block_num,
                                 ack_block
                             );
-                            active_transfers.lock().await.remove(&peer);
+                            active_transfers.lock().await.remove(&key);
                             return;
                         }
                     } else {
                         log::warn!("ACK packet too short from {}", peer);
-                        active_transfers.lock().await.remove(&peer);
+                        active_transfers.lock().await.remove(&key);
                         return;
                     }
                 }
                 Ok(None) => {
                     log::warn!("ACK channel closed for {}", peer);
-                    active_transfers.lock().await.remove(&peer);
+                    active_transfers.lock().await.remove(&key);
                     return;
                 }
                 Err(_) => {
                     log::warn!("Timeout waiting for ACK from {}", peer);
-                    active_transfers.lock().await.remove(&peer);
+                    active_transfers.lock().await.remove(&key);
                     return;
                 }
             }
@@ -285,6 +304,19 @@ impl TftpServer {
     }
 }
 
+/// Key of a client's transfer: IPv4 clients of a dual-stack socket show up
+/// as mapped IPv6 addresses, and only the address, port and scope of IPv6
+/// peers identify them
+fn transfer_key(peer: SocketAddr) -> SocketAddr {
+    match peer {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::from((ip, v6.port())),
+            None => SocketAddr::V6(SocketAddrV6::new(*v6.ip(), v6.port(), 0, v6.scope_id())),
+        },
+        peer => peer,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -310,4 +342,45 @@ mod tests {
         assert_eq!(u16::from_be_bytes([ack[0], ack[1]]), TftpOpcode::Ack as u16);
         assert_eq!(u16::from_be_bytes([ack[2], ack[3]]), 1);
     }
+
+    #[test]
+    fn test_transfer_key() {
+        let mapped: SocketAddr = "[::ffff:192.168.1.10]:1234".parse().unwrap();
+        assert_eq!(transfer_key(mapped), "192.168.1.10:1234".parse().unwrap());
+
+        let ip = "fe80::1".parse().unwrap();
+        let peer = SocketAddr::V6(SocketAddrV6::new(ip, 1234, 7, 2));
+        assert_eq!(
+            transfer_key(peer),
+            SocketAddr::V6(SocketAddrV6::new(ip, 1234, 0, 2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_dual_stack() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("boot.ipxe"), b"#!ipxe\n").unwrap();
+        let filesystem = crate::filesystem::create_filesystem(dir.path()).unwrap();
+
+        let all = [SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0))];
+        let socket = listen::bind_udp(all[0], &all).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(TftpServer::serve(Arc::new(socket), Arc::from(filesystem)));
+
+        for server in ["[::1]", "127.0.0.1"] {
+            let server: SocketAddr = format!("{}:{}", server, port).parse().unwrap();
+            let client = UdpSocket::bind((server.ip(), 0)).await.unwrap();
+            let mut request = 1u16.to_be_bytes().to_vec();
+            request.extend_from_slice(b"boot.ipxe\0octet\0");
+            client.send_to(&request, server).await.unwrap();
+
+            let mut buf = [0u8; MAX_PACKET_SIZE];
+            let (len, _) = client.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], TftpPacket::build_data(1, b"#!ipxe\n"));
+            client
+                .send_to(&TftpPacket::build_ack(1), server)
+                .await
+                .unwrap();
+        }
+    }
 }
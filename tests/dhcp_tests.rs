@@ -17,6 +17,8 @@ fn test_dhcp_message_serialization() {
         siaddr: "0.0.0.0".parse().unwrap(),
         giaddr: "0.0.0.0".parse().unwrap(),
         chaddr: [0u8; 16],
+        sname: [0; 64],
+        file: [0; 128],
         options: vec![53, 1, 1, 255], // DHCP Discover
     };
 
@@ -42,6 +44,8 @@ fn test_dhcp_message_options() {
         siaddr: "0.0.0.0".parse().unwrap(),
         giaddr: "0.0.0.0".parse().unwrap(),
         chaddr: [0u8; 16],
+        sname: [0; 64],
+        file: [0; 128],
         options: vec![53, 1, 1, 93, 2, 0, 6, 255], // Message type + Client arch (EFI)
     };
 
@@ -60,6 +64,8 @@ fn test_protocol_handler() {
         boot_filename_efi: None,
         boot_filename_legacy: None,
         boot_filename_dhcp_boot: None,
+        boot_filenames: Default::default(),
+        ipxe_script: None,
     };
 
     // Test EFI architecture
@@ -88,6 +94,8 @@ fn test_protocol_handler() {
         boot_filename_efi: None,
         boot_filename_legacy: None,
         boot_filename_dhcp_boot: None,
+        boot_filenames: Default::default(),
+        ipxe_script: None,
     };
     assert_eq!(
         ProtocolHandler::select_protocol(&config_disabled, None),
@@ -106,6 +114,8 @@ fn test_boot_filename() {
         boot_filename_efi: None,
         boot_filename_legacy: None,
         boot_filename_dhcp_boot: None,
+        boot_filenames: Default::default(),
+        ipxe_script: None,
     };
 
     assert_eq!(
@@ -133,6 +143,8 @@ fn test_boot_filename_custom() {
         boot_filename_efi: Some("custom_efi.efi".to_string()),
         boot_filename_legacy: Some("custom_legacy.0".to_string()),
         boot_filename_dhcp_boot: Some("custom_dhcp.0".to_string()),
+        boot_filenames: Default::default(),
+        ipxe_script: None,
     };
 
     assert_eq!(